@@ -2,6 +2,7 @@
 //! Testing client for OpenIAP, will over time transition to a full management client.
 
 use std::ops::Add;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::available_parallelism;
 use std::time::Duration;
@@ -12,6 +13,7 @@ use openiap_client::{
     self, disable_observable_gauge, disable_tracing, enable_tracing, set_f64_observable_gauge,
     set_i64_observable_gauge, set_otel_url, set_u64_observable_gauge, Client, InsertManyRequest,
     PopWorkitemRequest, RegisterExchangeRequest, RegisterQueueRequest, UpdateWorkitemRequest,
+    WorkitemWorker, WorkitemWorkerHandle,
 };
 use openiap_client::{CustomCommandRequest, InvokeOpenRpaRequest, PushWorkitemRequest, QueueMessageRequest};
 
@@ -80,7 +82,7 @@ async fn doit() -> Result<(), Box<dyn std::error::Error>> {
                 println!("CLI: Client disconnected! {:?}", e)
             }
             openiap_client::ClientEvent::SignedIn => println!("CLI: Client signed in!"),
-            // openiap_client::ClientEvent::SignedOut => println!("CLI: Client signed out!"),
+            openiap_client::ClientEvent::SignedOut => println!("CLI: Client signed out!"),
         }
     }))
     .await;
@@ -119,10 +121,10 @@ async fn doit() -> Result<(), Box<dyn std::error::Error>> {
     let mut input = String::from("bum");
     println!("? for help");
     let mut sthandle: Option<tokio::task::JoinHandle<()>> = None;
+    let mut workerhandle: Option<WorkitemWorkerHandle> = None;
     let mut f64handle: Option<tokio::task::JoinHandle<()>> = None;
     let mut u64handle: Option<tokio::task::JoinHandle<()>> = None;
     let mut i64handle: Option<tokio::task::JoinHandle<()>> = None;
-    let mut x: u64 = 0;
     while !input.eq_ignore_ascii_case("quit") {
         if input.eq_ignore_ascii_case("?") {
             println!("? for help");
@@ -273,93 +275,50 @@ async fn doit() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
         if input.eq_ignore_ascii_case("st") {
-            // || input.eq_ignore_ascii_case("bum")
             input = "".to_string();
-            let client = b.clone();
-            if sthandle.is_some() {
+            if workerhandle.is_some() {
                 println!("Stopping nonstop");
-                sthandle.unwrap().abort();
-                sthandle = None;
+                if let Some(handle) = sthandle.take() {
+                    handle.abort();
+                }
+                if let Err(e) = workerhandle.take().unwrap().stop().await {
+                    println!("Failed to stop worker: {:?}", e);
+                }
             } else {
-                sthandle = Some(
-                    // tokio::task::Builder::new().name("NonStop").spawn(async move {
-                    tokio::task::spawn(async move {
-                        println!("Task started, begin loop...");
-                        loop {
-                            // tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-                            tokio::time::sleep(tokio::time::Duration::from_micros(1)).await;
-                            match client
-                                .pop_workitem(PopWorkitemRequest::bywiq("q2"), openiap_client::EnvConfig::new(), None)
-                                .await
-                            {
-                                Ok(response) => match response.workitem {
-                                    Some(mut workitem) => {
-                                        let name = workitem.name.clone();
-                                        let id = workitem.id.clone();
-                                        println!("popped workitem {:?} {:?}", id, name);
-                                        workitem.state = "successful".to_string();
-                                        match client
-                                            .update_workitem(UpdateWorkitemRequest {
-                                                workitem: Some(workitem),
-                                                ignoremaxretries: false,
-                                                ..Default::default()
-                                            }, openiap_client::EnvConfig::new())
-                                            .await
-                                        {
-                                            Ok(_response) => {
-                                                x = x + 1;
-                                                if x % 500 == 0 {
-                                                    println!(
-                                                        "Updated workitem {:?} {:?}",
-                                                        id, name
-                                                    );
-                                                }
-                                            }
-                                            Err(e) => {
-                                                x = x + 1;
-                                                if x % 500 == 0 {
-                                                    println!("Failed to update workitem: {:?}", e);
-                                                }
-                                            }
-                                        };
-                                    }
-                                    None => {
-                                        let a = std::time::SystemTime::now()
-                                            .duration_since(std::time::UNIX_EPOCH)
-                                            .unwrap()
-                                            .as_secs();
-                                        x = x + 1;
-                                        if x % 500 == 0 {
-                                            println!("No workitem popped {:?}", a);
-                                        }
-                                    }
-                                },
-                                Err(e) => {
-                                    println!("Failed to pop workitem: {:?}", e);
-                                }
-                            }
+                println!("Task started, begin loop...");
+                let counter = Arc::new(AtomicU64::new(0));
+                let worker = WorkitemWorker::new(b.clone(), "q2", move |_client, workitem, _folder| {
+                    let counter = counter.clone();
+                    async move {
+                        println!("popped workitem {:?} {:?}", workitem.id, workitem.name);
+                        let x = counter.fetch_add(1, Ordering::Relaxed) + 1;
+                        if x % 500 == 0 {
+                            println!("Updated workitem {:?} {:?}", workitem.id, workitem.name);
                         }
-                    }),
-                );
+                        Ok(workitem)
+                    }
+                });
+                workerhandle = Some(worker.start());
             }
         }
         if input.eq_ignore_ascii_case("st2") {
-            // || input.eq_ignore_ascii_case("bum")
             input = "".to_string();
             let client = b.clone();
-            if sthandle.is_some() {
+            if workerhandle.is_some() {
                 println!("Stopping nonstop");
-                sthandle.unwrap().abort();
-                sthandle = None;
+                if let Some(handle) = sthandle.take() {
+                    handle.abort();
+                }
+                if let Err(e) = workerhandle.take().unwrap().stop().await {
+                    println!("Failed to stop worker: {:?}", e);
+                }
             } else {
+                println!("Task started, begin loop...");
                 sthandle = Some(
-                    // tokio::task::Builder::new().name("NonStop").spawn(async move {
                     tokio::task::spawn(async move {
-                        println!("Task started, begin loop...");
                         loop {
-                            // tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
                             tokio::time::sleep(tokio::time::Duration::from_micros(1)).await;
-                            match client
+                            if let Err(e) = client
                                 .push_workitem(PushWorkitemRequest {
                                     wiq: "rustqueue".to_string(),
                                     name: "test".to_string(),
@@ -368,84 +327,23 @@ async fn doit() -> Result<(), Box<dyn std::error::Error>> {
                                 }, openiap_client::EnvConfig::new())
                                 .await
                             {
-                                Ok(response) => {
-                                    match response.workitem {
-                                        Some(_workitem) => {
-                                            // let name = _workitem.name.clone();
-                                            // let id = _workitem.id.clone();
-                                            // println!("pushed workitem {:?} {:?}", id, name);
-                                        }
-                                        None => {
-                                            let a = std::time::SystemTime::now()
-                                                .duration_since(std::time::UNIX_EPOCH)
-                                                .unwrap()
-                                                .as_secs();
-                                            println!("No workitem pushed {:?}", a);
-                                        }
-                                    };
-                                }
-                                Err(e) => {
-                                    println!("Failed to push workitem: {:?}", e);
-                                }
-                            }
-                            match client
-                                .pop_workitem(PopWorkitemRequest::bywiq("rustqueue"), openiap_client::EnvConfig::new(), None)
-                                .await
-                            {
-                                Ok(response) => {
-                                    match response.workitem {
-                                        Some(mut workitem) => {
-                                            let name = workitem.name.clone();
-                                            let id = workitem.id.clone();
-                                            // println!("popped workitem {:?} {:?}", id, name);
-                                            workitem.state = "successful".to_string();
-                                            match client
-                                                .update_workitem(UpdateWorkitemRequest {
-                                                    workitem: Some(workitem),
-                                                    ignoremaxretries: false,
-                                                    ..Default::default()
-                                                }, openiap_client::EnvConfig::new())
-                                                .await
-                                            {
-                                                Ok(_response) => {
-                                                    x = x + 1;
-                                                    if x % 500 == 0 {
-                                                        println!(
-                                                            "Updated workitem {:?} {:?}",
-                                                            id, name
-                                                        );
-                                                    }
-                                                }
-                                                Err(e) => {
-                                                    x = x + 1;
-                                                    if x % 500 == 0 {
-                                                        println!(
-                                                            "Failed to update workitem: {:?}",
-                                                            e
-                                                        );
-                                                    }
-                                                }
-                                            };
-                                        }
-                                        None => {
-                                            let a = std::time::SystemTime::now()
-                                                .duration_since(std::time::UNIX_EPOCH)
-                                                .unwrap()
-                                                .as_secs();
-                                            x = x + 1;
-                                            if x % 500 == 0 {
-                                                println!("No workitem popped {:?}", a);
-                                            }
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    println!("Failed to pop workitem: {:?}", e);
-                                }
+                                println!("Failed to push workitem: {:?}", e);
                             }
                         }
                     }),
                 );
+                let counter = Arc::new(AtomicU64::new(0));
+                let worker = WorkitemWorker::new(b.clone(), "rustqueue", move |_client, workitem, _folder| {
+                    let counter = counter.clone();
+                    async move {
+                        let x = counter.fetch_add(1, Ordering::Relaxed) + 1;
+                        if x % 500 == 0 {
+                            println!("Updated workitem {:?} {:?}", workitem.id, workitem.name);
+                        }
+                        Ok(workitem)
+                    }
+                });
+                workerhandle = Some(worker.start());
             }
         }
         if input.eq_ignore_ascii_case("st3") {
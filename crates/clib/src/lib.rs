@@ -107,6 +107,59 @@ pub extern "C" fn disable_observable_gauge(name: *const c_char) {
     let name = c_char_to_str(name);
     openiap_client::disable_observable_gauge(&name);
 }
+/// Parse metric attributes from a json object like {"queue": "myqueue"}, null or empty means no attributes.
+fn metric_attributes(attributes: *const c_char) -> Vec<(String, String)> {
+    let attributes = c_char_to_str(attributes);
+    if attributes.is_empty() {
+        return vec![];
+    }
+    match serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&attributes) {
+        Ok(map) => map
+            .into_iter()
+            .map(|(key, value)| match value {
+                serde_json::Value::String(value) => (key, value),
+                value => (key, value.to_string()),
+            })
+            .collect(),
+        Err(e) => {
+            error!("Failed to parse metric attributes {}: {}", attributes, e);
+            vec![]
+        }
+    }
+}
+#[no_mangle]
+#[tracing::instrument(skip_all)]
+pub extern "C" fn inc_counter(name: *const c_char, value: u64, description: *const c_char, attributes: *const c_char) {
+    let name = c_char_to_str(name);
+    let description = c_char_to_str(description);
+    let attributes = metric_attributes(attributes);
+    let attributes: Vec<(&str, &str)> = attributes.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    if let Err(e) = openiap_client::inc_counter(&name, value, &description, &attributes) {
+        error!("Failed to record custom metric: {}", e);
+    }
+}
+#[no_mangle]
+#[tracing::instrument(skip_all)]
+pub extern "C" fn add_up_down_counter(name: *const c_char, value: i64, description: *const c_char, attributes: *const c_char) {
+    let name = c_char_to_str(name);
+    let description = c_char_to_str(description);
+    let attributes = metric_attributes(attributes);
+    let attributes: Vec<(&str, &str)> = attributes.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    if let Err(e) = openiap_client::add_up_down_counter(&name, value, &description, &attributes) {
+        error!("Failed to record custom metric: {}", e);
+    }
+}
+#[no_mangle]
+#[tracing::instrument(skip_all)]
+pub extern "C" fn record_histogram(name: *const c_char, value: f64, description: *const c_char, attributes: *const c_char) {
+    let name = c_char_to_str(name);
+    let description = c_char_to_str(description);
+    let attributes = metric_attributes(attributes);
+    let attributes: Vec<(&str, &str)> = attributes.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    if let Err(e) = openiap_client::record_histogram(&name, value, &description, &attributes) {
+        error!("Failed to record custom metric: {}", e);
+    }
+}
 
 /// A wrapper for the client library.
 /// This struct is used to hold the client instance and the runtime instance.
@@ -7191,7 +7244,7 @@ pub extern "C" fn on_client_event_async(
                         ClientEvent::Connected => ClientEventWrapper { event: CString::new("Connected").unwrap().into_raw(),reason: std::ptr::null() },
                         ClientEvent::Disconnected(reason) => ClientEventWrapper { event: CString::new("Disconnected").unwrap().into_raw(),reason: CString::new(reason).unwrap().into_raw() },
                         ClientEvent::SignedIn => ClientEventWrapper { event: CString::new("SignedIn").unwrap().into_raw(),reason: std::ptr::null() },
-                        // ClientEvent::SignedOut => ClientEventWrapper { event: CString::new("SignedOut").unwrap().into_raw(),reason: std::ptr::null() },
+                        ClientEvent::SignedOut => ClientEventWrapper { event: CString::new("SignedOut").unwrap().into_raw(),reason: std::ptr::null() },
                     };
                     let event = Box::into_raw(Box::new(event));
 
@@ -7244,7 +7297,7 @@ pub extern "C" fn next_client_event (
                         ClientEvent::Connected => ClientEventWrapper { event: CString::new("Connected").unwrap().into_raw(),reason: std::ptr::null() },
                         ClientEvent::Disconnected(reason) => ClientEventWrapper { event: CString::new("Disconnected").unwrap().into_raw(),reason: CString::new(reason).unwrap().into_raw() },
                         ClientEvent::SignedIn => ClientEventWrapper { event: CString::new("SignedIn").unwrap().into_raw(),reason: std::ptr::null() },
-                        // ClientEvent::SignedOut => ClientEventWrapper { event: CString::new("SignedOut").unwrap().into_raw(),reason: std::ptr::null() },
+                        ClientEvent::SignedOut => ClientEventWrapper { event: CString::new("SignedOut").unwrap().into_raw(),reason: std::ptr::null() },
                     };
                     Box::into_raw(Box::new(event))
                 }
@@ -0,0 +1,341 @@
+//! Helpers for code running inside an OpenIAP agent.
+//!
+//! The agent passes identity, configuration and credentials to the package through environment
+//! variables. `AgentContext` reads them, `AgentHealth` reports the state of the client and serves
+//! it on a local health endpoint, and `Shutdown` turns SIGINT / SIGTERM into a graceful shutdown.
+use openiap_proto::errors::OpenIAPError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+use crate::{Client, ClientState, Credentials, Secret, StaticCredentials};
+
+/// Identity and configuration passed to a package by the agent, see `AgentContext::from_env`.
+/// `jwt` and `password` are kept in a `Secret`, and are never serialized.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AgentContext {
+    /// Id of the agent running the package.
+    pub agentid: String,
+    /// Slug of the agent, also used as hostname on kubernetes.
+    pub slug: String,
+    /// Id of the package being run.
+    pub packageid: String,
+    /// Id of the stream the package output is sent to.
+    pub streamid: String,
+    /// Name of the pod or container, from HOSTNAME.
+    pub podname: String,
+    /// Url of the OpenIAP server, in the order `Client::connect` looks for it.
+    pub apiurl: String,
+    /// Domain of the OpenIAP server.
+    pub domain: String,
+    /// Token to sign in with.
+    #[serde(skip)]
+    pub jwt: Secret,
+    /// Username to sign in with, when not using a token.
+    pub username: String,
+    /// Password to sign in with, when not using a token.
+    #[serde(skip)]
+    pub password: Secret,
+    /// Workitem queue the package should process, if any.
+    pub wiq: String,
+    /// All environment variables, for package specific configuration.
+    #[serde(skip)]
+    pub vars: HashMap<String, String>,
+}
+impl std::fmt::Debug for AgentContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AgentContext")
+            .field("agentid", &self.agentid)
+            .field("slug", &self.slug)
+            .field("packageid", &self.packageid)
+            .field("streamid", &self.streamid)
+            .field("podname", &self.podname)
+            .field("apiurl", &self.apiurl)
+            .field("domain", &self.domain)
+            .field("jwt", &self.jwt)
+            .field("username", &self.username)
+            .field("password", &self.password)
+            .field("wiq", &self.wiq)
+            .finish_non_exhaustive()
+    }
+}
+impl AgentContext {
+    /// Read the agent context from the environment of the current process.
+    pub fn from_env() -> Self {
+        Self::from_vars(std::env::vars())
+    }
+    /// Read the agent context from a list of variables.
+    pub fn from_vars<I: IntoIterator<Item = (String, String)>>(vars: I) -> Self {
+        let vars: HashMap<String, String> = vars.into_iter().collect();
+        let first = |keys: &[&str]| {
+            keys.iter()
+                .filter_map(|key| vars.get(*key))
+                .find(|value| !value.is_empty())
+                .cloned()
+                .unwrap_or_default()
+        };
+        AgentContext {
+            agentid: first(&["agentid", "OPENIAP_AGENTID"]),
+            slug: first(&["slug", "OPENIAP_SLUG"]),
+            packageid: first(&["packageid", "OPENIAP_PACKAGEID"]),
+            streamid: first(&["streamid", "OPENIAP_STREAMID"]),
+            podname: first(&["HOSTNAME", "COMPUTERNAME"]),
+            apiurl: first(&["apiurl", "grpcapiurl", "OPENIAP_URL", "OPENIAP_APIURL", "wsapiurl"]),
+            domain: first(&["domain", "OPENIAP_DOMAIN"]),
+            jwt: Secret::from(first(&["OPENIAP_JWT", "jwt"])),
+            username: first(&["OPENIAP_USERNAME"]),
+            password: Secret::from(first(&["OPENIAP_PASSWORD"])),
+            wiq: first(&["wiq", "queue", "OPENIAP_WIQ"]),
+            vars,
+        }
+    }
+    /// True when running inside an agent.
+    pub fn is_agent(&self) -> bool {
+        !self.agentid.is_empty()
+    }
+    /// Get any environment variable.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars.get(key).map(|v| v.as_str())
+    }
+    /// The credentials in the context, `Credentials::None` if it has none.
+    pub fn credentials(&self) -> Credentials {
+        if !self.jwt.is_empty() {
+            Credentials::jwt(self.jwt.expose())
+        } else if !self.username.is_empty() && !self.password.is_empty() {
+            Credentials::userpass(&self.username, self.password.expose())
+        } else {
+            Credentials::None
+        }
+    }
+    /// Create a client and connect to `apiurl`, signing in with the credentials in the context.
+    /// The agent name is set before connecting, so the server sees it when the client signs in.
+    pub async fn connect(&self) -> Result<Client, OpenIAPError> {
+        let client = Client::new();
+        if !self.slug.is_empty() {
+            client.set_agent_name(&self.slug);
+        }
+        let credentials = self.credentials();
+        if credentials != Credentials::None {
+            client.set_credential_provider(Arc::new(StaticCredentials(credentials)));
+        }
+        client.connect_async(&self.apiurl).await?;
+        Ok(client)
+    }
+}
+
+/// Health of an agent process, as served by `AgentHealth::serve`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentStatus {
+    /// Client state, like "Signedin".
+    pub state: String,
+    /// True when the client is signed in and the package has not marked itself as not ready.
+    pub ready: bool,
+    /// Set while shutting down.
+    pub stopping: bool,
+    /// Status message set by the package.
+    pub message: String,
+    /// Seconds since `AgentHealth` was created.
+    pub uptime: u64,
+    /// Id of the agent.
+    pub agentid: String,
+    /// Name of the pod.
+    pub podname: String,
+}
+
+struct HealthInner {
+    ready: bool,
+    stopping: bool,
+    message: String,
+}
+
+/// Tracks the health of an agent process, from the client state and what the package reports.
+#[derive(Clone)]
+pub struct AgentHealth {
+    client: Client,
+    context: Arc<AgentContext>,
+    started: Instant,
+    inner: Arc<Mutex<HealthInner>>,
+}
+impl AgentHealth {
+    /// Track the health of `client`.
+    pub fn new(client: Client, context: AgentContext) -> Self {
+        Self {
+            client,
+            context: Arc::new(context),
+            started: Instant::now(),
+            inner: Arc::new(Mutex::new(HealthInner {
+                ready: true,
+                stopping: false,
+                message: String::new(),
+            })),
+        }
+    }
+    /// Mark the package as ready or not, the agent is only ready when the client is also signed in.
+    pub fn set_ready(&self, ready: bool) {
+        self.inner.lock().unwrap().ready = ready;
+    }
+    /// Set the status message.
+    pub fn set_message(&self, message: &str) {
+        self.inner.lock().unwrap().message = message.to_string();
+    }
+    /// Mark the agent as stopping, readiness fails from now on.
+    pub fn set_stopping(&self) {
+        self.inner.lock().unwrap().stopping = true;
+    }
+    /// The current status.
+    pub fn status(&self) -> AgentStatus {
+        let state = self.client.get_state();
+        let inner = self.inner.lock().unwrap();
+        AgentStatus {
+            ready: inner.ready && !inner.stopping && state == ClientState::Signedin,
+            state: state.to_string(),
+            stopping: inner.stopping,
+            message: inner.message.clone(),
+            uptime: self.started.elapsed().as_secs(),
+            agentid: self.context.agentid.clone(),
+            podname: self.context.podname.clone(),
+        }
+    }
+    /// Log the status each time the client state or readiness changes, checked every `interval`.
+    pub fn start_reporter(&self, interval: Duration) -> JoinHandle<()> {
+        let health = self.clone();
+        tokio::task::spawn(async move {
+            let mut last: Option<(String, bool)> = None;
+            loop {
+                let status = health.status();
+                let current = (status.state.clone(), status.ready);
+                if last.as_ref() != Some(&current) {
+                    info!("Agent status {} ready: {} {}", status.state, status.ready, status.message);
+                    last = Some(current);
+                }
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+    /// Serve the health endpoint on `addr`, like "0.0.0.0:8080".
+    /// GET /healthz answers 200 while the process runs, /readyz answers 200 when ready and 503 otherwise,
+    /// and /status returns `AgentStatus` as json.
+    pub async fn serve(&self, addr: &str) -> Result<JoinHandle<()>, OpenIAPError> {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to listen on {}: {}", addr, e)))?;
+        info!("Health endpoint listening on {}", addr);
+        let health = self.clone();
+        Ok(tokio::task::spawn(async move {
+            loop {
+                let (mut stream, _) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        error!("Health endpoint failed to accept: {}", e);
+                        continue;
+                    }
+                };
+                let health = health.clone();
+                tokio::task::spawn(async move {
+                    let mut buffer = [0; 1024];
+                    let read = stream.read(&mut buffer).await.unwrap_or_default();
+                    let request = String::from_utf8_lossy(&buffer[..read]);
+                    let path = request.split_whitespace().nth(1).unwrap_or("/");
+                    let response = health_response(path, &health.status());
+                    if let Err(e) = stream.write_all(response.as_bytes()).await {
+                        debug!("Health endpoint failed to respond: {}", e);
+                    }
+                    let _ = stream.shutdown().await;
+                });
+            }
+        }))
+    }
+}
+
+/// Build the http response for a request to the health endpoint.
+pub fn health_response(path: &str, status: &AgentStatus) -> String {
+    let (code, body) = match path {
+        "/healthz" | "/livez" | "/" => ("200 OK", "ok".to_string()),
+        "/readyz" if status.ready => ("200 OK", "ok".to_string()),
+        "/readyz" => ("503 Service Unavailable", status.state.clone()),
+        "/status" => ("200 OK", serde_json::to_string(status).unwrap_or_default()),
+        _ => ("404 Not Found", "not found".to_string()),
+    };
+    let contenttype = if path == "/status" { "application/json" } else { "text/plain" };
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        contenttype,
+        body.len(),
+        body
+    )
+}
+
+/// Graceful shutdown on SIGINT or SIGTERM.
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Shutdown {
+    /// Create a shutdown handle, call `listen` to trigger it on signals.
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Self { sender: Arc::new(sender) }
+    }
+    /// Trigger shutdown when the process receives SIGINT or SIGTERM.
+    /// If `health` is set it is marked as stopping, so readiness fails while the process winds down.
+    pub fn listen(&self, health: Option<AgentHealth>) -> JoinHandle<()> {
+        let me = self.clone();
+        tokio::task::spawn(async move {
+            let signal = wait_for_signal().await;
+            info!("Received {}, shutting down", signal);
+            if let Some(health) = health {
+                health.set_stopping();
+            }
+            me.trigger();
+        })
+    }
+    /// Start shutting down.
+    pub fn trigger(&self) {
+        // send_replace, so the value is stored even when nobody is waiting yet
+        self.sender.send_replace(true);
+    }
+    /// True once shutdown has been triggered.
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+    /// Wait until shutdown is triggered.
+    pub async fn wait(&self) {
+        let mut receiver = self.sender.subscribe();
+        let _ = receiver.wait_for(|stopping| *stopping).await;
+    }
+}
+
+/// Wait for SIGINT or SIGTERM, returns the name of the signal.
+pub async fn wait_for_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = sigterm.recv() => "SIGTERM",
+            },
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl-C"
+    }
+}
@@ -0,0 +1,169 @@
+use futures::Stream;
+use openiap_proto::errors::OpenIAPError;
+use regex::Regex;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, trace};
+
+use crate::{Client, EnvConfig};
+
+/// A log line from an agent pod.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLine {
+    /// The pod the line was read from.
+    pub pod: String,
+    /// The line, without the line break.
+    pub line: String,
+    /// When the line was received.
+    pub received: SystemTime,
+}
+
+/// Options for `Client::tail_agent_pod_logs`.
+#[derive(Debug, Clone)]
+pub struct TailLogOptions {
+    /// How often the logs are fetched.
+    pub poll_interval: Duration,
+    /// Only emit lines matching this expression.
+    pub filter: Option<Regex>,
+    /// Also emit the lines already in the log when the tail starts.
+    pub from_start: bool,
+}
+impl Default for TailLogOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(2),
+            filter: None,
+            from_start: false,
+        }
+    }
+}
+impl TailLogOptions {
+    /// Only emit lines matching the regular expression `pattern`.
+    pub fn with_filter(mut self, pattern: &str) -> Result<Self, OpenIAPError> {
+        let filter = Regex::new(pattern)
+            .map_err(|e| OpenIAPError::ClientError(format!("Invalid filter: {}", e)))?;
+        self.filter = Some(filter);
+        Ok(self)
+    }
+}
+
+/// Returns the lines in `current` that were not in `previous`.
+/// The log may have been cut at the start, so the longest end of `previous` that `current` starts with is skipped.
+/// If nothing overlaps the log was restarted and all of `current` is new.
+pub fn new_log_lines<'a>(previous: &[String], current: &'a [String]) -> &'a [String] {
+    if current.starts_with(previous) {
+        return &current[previous.len()..];
+    }
+    for skip in 1..previous.len() {
+        let tail = &previous[skip..];
+        if current.starts_with(tail) {
+            return &current[tail.len()..];
+        }
+    }
+    current
+}
+
+/// A stream of new log lines from an agent pod, see `Client::tail_agent_pod_logs`.
+/// Dropping the stream stops polling.
+pub struct AgentLogStream {
+    receiver: mpsc::Receiver<LogLine>,
+    handle: JoinHandle<()>,
+}
+impl Stream for AgentLogStream {
+    type Item = LogLine;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+impl Drop for AgentLogStream {
+    fn drop(&mut self) {
+        self.handle.abort();
+        trace!("AgentLogStream dropped");
+    }
+}
+
+impl Client {
+    /// Follow the logs of an agent pod, and receive new lines as a `Stream`.
+    /// Leave podname empty to follow the first running pod. If the pod goes away, for instance after
+    /// a restart, the pod is looked up again with `get_agent_pods` and its log is followed from the start.
+    #[tracing::instrument(skip_all)]
+    pub async fn tail_agent_pod_logs(
+        &self,
+        env: EnvConfig,
+        agentid: &str,
+        podname: &str,
+        options: TailLogOptions,
+    ) -> Result<AgentLogStream, OpenIAPError> {
+        let (sender, receiver) = mpsc::channel(1000);
+        let client = self.clone();
+        let agentid = agentid.to_string();
+        let mut podname = podname.to_string();
+        if podname.is_empty() {
+            podname = client.resolve_agent_pod(env.clone(), &agentid, "").await?;
+        }
+        let handle = tokio::task::spawn(async move {
+            let mut previous: Vec<String> = Vec::new();
+            let mut first = !options.from_start;
+            while !sender.is_closed() {
+                match client.get_agent_pod_logs(env.clone(), &agentid, &podname).await {
+                    Ok(log) => {
+                        let current: Vec<String> = log.lines().map(|l| l.to_string()).collect();
+                        if !first {
+                            let received = SystemTime::now();
+                            for line in new_log_lines(&previous, &current) {
+                                if let Some(filter) = &options.filter {
+                                    if !filter.is_match(line) {
+                                        continue;
+                                    }
+                                }
+                                let line = LogLine {
+                                    pod: podname.clone(),
+                                    line: line.clone(),
+                                    received,
+                                };
+                                if sender.send(line).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                        first = false;
+                        previous = current;
+                    }
+                    Err(e) => {
+                        debug!("Failed to get logs from {}: {}", podname, e);
+                        match client.resolve_agent_pod(env.clone(), &agentid, &podname).await {
+                            Ok(name) if name != podname => {
+                                debug!("Following logs from {} instead of {}", name, podname);
+                                podname = name;
+                                previous.clear();
+                            }
+                            Ok(_) => {}
+                            Err(e) => debug!("Failed to find a pod for agent {}: {}", agentid, e),
+                        }
+                    }
+                }
+                tokio::time::sleep(options.poll_interval).await;
+            }
+        });
+        Ok(AgentLogStream { receiver, handle })
+    }
+    /// Returns `podname` if it still exists, otherwise the first running pod of the agent.
+    async fn resolve_agent_pod(
+        &self,
+        env: EnvConfig,
+        agentid: &str,
+        podname: &str,
+    ) -> Result<String, OpenIAPError> {
+        let pods = self.list_agent_pods(env, agentid, false).await?;
+        if let Some(pod) = pods.iter().find(|p| p.name == podname && !p.terminating) {
+            return Ok(pod.name.clone());
+        }
+        match pods.iter().find(|p| p.is_running()).or(pods.first()) {
+            Some(pod) => Ok(pod.name.clone()),
+            None => Err(OpenIAPError::ClientError(format!("Agent {} has no pods", agentid))),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use openiap_proto::errors::OpenIAPError;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, info};
+
+use crate::{Client, EnvConfig};
+
+/// Memory and cpu usage of a pod, as returned by `get_agent_pods` with stats.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AgentStats {
+    /// Cpu usage as reported, like "250m" on kubernetes.
+    pub cpu: Option<String>,
+    /// Memory usage as reported, like "128Mi" on kubernetes.
+    pub memory: Option<String>,
+}
+impl AgentStats {
+    /// Cpu usage in millicores.
+    pub fn cpu_millicores(&self) -> Option<f64> {
+        let cpu = self.cpu.as_deref()?.trim();
+        let (number, scale) = match cpu.char_indices().last()? {
+            (i, 'n') => (&cpu[..i], 1e-6),
+            (i, 'u') => (&cpu[..i], 1e-3),
+            (i, 'm') => (&cpu[..i], 1.0),
+            _ => (cpu, 1000.0),
+        };
+        number.parse::<f64>().ok().map(|n| n * scale)
+    }
+    /// Memory usage in bytes.
+    pub fn memory_bytes(&self) -> Option<u64> {
+        let memory = self.memory.as_deref()?.trim();
+        let split = memory.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(memory.len());
+        let (number, unit) = memory.split_at(split);
+        let scale: f64 = match unit.trim() {
+            "" | "B" => 1.0,
+            "k" | "K" | "kB" | "KB" => 1e3,
+            "M" | "MB" => 1e6,
+            "G" | "GB" => 1e9,
+            "Ki" | "KiB" => 1024.0,
+            "Mi" | "MiB" => 1024.0 * 1024.0,
+            "Gi" | "GiB" => 1024.0 * 1024.0 * 1024.0,
+            _ => return None,
+        };
+        number.trim().parse::<f64>().ok().map(|n| (n * scale) as u64)
+    }
+    fn from_value(metrics: &Value) -> Option<Self> {
+        let text = |value: &Value| match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        };
+        let stats = AgentStats {
+            cpu: text(&metrics["cpu"]),
+            memory: text(&metrics["memory"]),
+        };
+        if stats.cpu.is_none() && stats.memory.is_none() {
+            None
+        } else {
+            Some(stats)
+        }
+    }
+}
+
+/// A pod or container running an agent, parsed from the result of `get_agent_pods`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AgentPod {
+    /// Pod name, used with `get_agent_pod_logs` and `delete_agent_pod`.
+    pub name: String,
+    /// Pod phase, like "Pending" or "Running" on kubernetes, or the container state on docker.
+    pub phase: String,
+    /// True when all containers in the pod are ready.
+    pub ready: bool,
+    /// Number of container restarts.
+    pub restarts: u32,
+    /// When the pod was created.
+    pub created: Option<SystemTime>,
+    /// True when the pod is being deleted.
+    pub terminating: bool,
+    /// Memory and cpu usage, if requested.
+    pub stats: Option<AgentStats>,
+}
+impl AgentPod {
+    /// Parse a single pod from the `get_agent_pods` result.
+    pub fn from_value(pod: &Value) -> Self {
+        let metadata = &pod["metadata"];
+        let status = &pod["status"];
+        let phase = status["phase"].as_str().or(pod["State"].as_str()).unwrap_or_default().to_string();
+        let containers = status["containerStatuses"].as_array();
+        let ready = match containers {
+            Some(containers) if !containers.is_empty() => containers.iter().all(|c| c["ready"].as_bool().unwrap_or_default()),
+            // docker does not report readiness, a running container is ready
+            _ => phase.eq_ignore_ascii_case("running"),
+        };
+        let restarts = containers
+            .map(|containers| containers.iter().map(|c| c["restartCount"].as_u64().unwrap_or_default() as u32).sum())
+            .unwrap_or_default();
+        let name = metadata["name"]
+            .as_str()
+            .or(pod["Names"][0].as_str())
+            .unwrap_or_default()
+            .trim_start_matches('/')
+            .to_string();
+        AgentPod {
+            name,
+            phase,
+            ready,
+            restarts,
+            created: metadata["creationTimestamp"].as_str().and_then(crate::util::parse_iso8601),
+            terminating: !metadata["deletionTimestamp"].is_null(),
+            stats: AgentStats::from_value(&pod["metrics"]),
+        }
+    }
+    /// True when the pod is running, ready and not being deleted.
+    pub fn is_running(&self) -> bool {
+        self.phase.eq_ignore_ascii_case("running") && self.ready && !self.terminating
+    }
+}
+
+/// Parse the JSON returned by `get_agent_pods`.
+pub fn agent_pods_from_json(json: &str) -> Result<Vec<AgentPod>, OpenIAPError> {
+    let pods: Value = serde_json::from_str(json)
+        .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse agent pods: {}", e)))?;
+    match pods {
+        Value::Array(pods) => Ok(pods.iter().map(AgentPod::from_value).collect()),
+        Value::Null => Ok(vec![]),
+        _ => Err(OpenIAPError::ClientError("Agent pods is not an array".to_string())),
+    }
+}
+
+const POLL_MIN: Duration = Duration::from_millis(500);
+const POLL_MAX: Duration = Duration::from_secs(5);
+
+impl Client {
+    /// Get all pods associated with an agent as `AgentPod`, if stats is true, it will include memory and cpu usage for each pod
+    #[tracing::instrument(skip_all)]
+    pub async fn list_agent_pods(
+        &self,
+        env: EnvConfig,
+        agentid: &str,
+        stats: bool,
+    ) -> Result<Vec<AgentPod>, OpenIAPError> {
+        let json = self.get_agent_pods(env, agentid, stats).await?;
+        agent_pods_from_json(&json)
+    }
+    /// Wait until the agent has a running and ready pod, polling with backoff, and return it.
+    #[tracing::instrument(skip_all)]
+    pub async fn wait_until_running(
+        &self,
+        env: EnvConfig,
+        agentid: &str,
+        timeout: Duration,
+    ) -> Result<AgentPod, OpenIAPError> {
+        self.wait_for_pod(env, agentid, timeout, &HashSet::new(), false).await
+    }
+    /// Wait until the agent has no pods left, polling with backoff.
+    #[tracing::instrument(skip_all)]
+    pub async fn wait_until_stopped(
+        &self,
+        env: EnvConfig,
+        agentid: &str,
+        timeout: Duration,
+    ) -> Result<(), OpenIAPError> {
+        let deadline = Instant::now() + timeout;
+        let mut wait = POLL_MIN;
+        loop {
+            let pods = self.list_agent_pods(env.clone(), agentid, false).await?;
+            if pods.is_empty() {
+                return Ok(());
+            }
+            debug!("Agent {} still has {} pods", agentid, pods.len());
+            if Instant::now() + wait > deadline {
+                return Err(OpenIAPError::ClientError(format!(
+                    "Timeout waiting for agent {} to stop",
+                    agentid
+                )));
+            }
+            tokio::time::sleep(wait).await;
+            wait = (wait * 2).min(POLL_MAX);
+        }
+    }
+    /// Restart an agent by deleting its pods, and wait for a new pod to become ready.
+    /// If no pod is recreated (docker), the agent is started again.
+    #[tracing::instrument(skip_all)]
+    pub async fn restart_agent(
+        &self,
+        env: EnvConfig,
+        agentid: &str,
+        timeout: Duration,
+    ) -> Result<AgentPod, OpenIAPError> {
+        let pods = self.list_agent_pods(env.clone(), agentid, false).await?;
+        if pods.is_empty() {
+            self.start_agent(env.clone(), agentid).await?;
+        }
+        for pod in &pods {
+            info!("Deleting pod {} of agent {}", pod.name, agentid);
+            self.delete_agent_pod(env.clone(), agentid, &pod.name).await?;
+        }
+        let old: HashSet<String> = pods.into_iter().map(|p| p.name).collect();
+        self.wait_for_pod(env, agentid, timeout, &old, !old.is_empty()).await
+    }
+    async fn wait_for_pod(
+        &self,
+        env: EnvConfig,
+        agentid: &str,
+        timeout: Duration,
+        ignore: &HashSet<String>,
+        mut start_if_missing: bool,
+    ) -> Result<AgentPod, OpenIAPError> {
+        let started = Instant::now();
+        let deadline = started + timeout;
+        let mut wait = POLL_MIN;
+        loop {
+            let pods = self.list_agent_pods(env.clone(), agentid, false).await?;
+            let mut pods = pods.into_iter().filter(|p| !ignore.contains(&p.name)).peekable();
+            if start_if_missing && pods.peek().is_none() && started.elapsed() > POLL_MAX {
+                debug!("No new pod for agent {}, starting it", agentid);
+                self.start_agent(env.clone(), agentid).await?;
+                start_if_missing = false;
+            }
+            if let Some(pod) = pods.find(|p| p.is_running()) {
+                return Ok(pod);
+            }
+            if Instant::now() + wait > deadline {
+                return Err(OpenIAPError::ClientError(format!(
+                    "Timeout waiting for agent {} to be running",
+                    agentid
+                )));
+            }
+            tokio::time::sleep(wait).await;
+            wait = (wait * 2).min(POLL_MAX);
+        }
+    }
+}
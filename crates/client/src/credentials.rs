@@ -0,0 +1,225 @@
+use futures::future::BoxFuture;
+use openiap_proto::errors::OpenIAPError;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::{Client, Secret};
+
+/// Credentials returned by a `CredentialProvider`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Credentials {
+    /// No credentials, connect as guest.
+    #[default]
+    None,
+    /// Sign in with a JWT token.
+    Jwt(Secret),
+    /// Sign in with a username and password.
+    UserPass {
+        /// The username.
+        username: String,
+        /// The password.
+        password: Secret,
+    },
+}
+impl Credentials {
+    /// Sign in with a JWT token.
+    pub fn jwt(jwt: &str) -> Self {
+        Credentials::Jwt(Secret::new(jwt))
+    }
+    /// Sign in with a username and password.
+    pub fn userpass(username: &str, password: &str) -> Self {
+        Credentials::UserPass {
+            username: username.to_string(),
+            password: Secret::new(password),
+        }
+    }
+}
+
+/// Supplies the credentials used to sign in, see `Client::set_credential_provider`.
+/// It is consulted every time the client signs in, including after a reconnect, so rotated credentials are picked up.
+pub trait CredentialProvider: Send + Sync {
+    /// Get the current credentials.
+    fn credentials(&self) -> BoxFuture<'_, Result<Credentials, OpenIAPError>>;
+}
+
+/// Credentials from OPENIAP_JWT or jwt, or else OPENIAP_USERNAME and OPENIAP_PASSWORD, read on every sign in.
+#[derive(Debug, Clone, Default)]
+pub struct EnvCredentials;
+impl EnvCredentials {
+    /// Read the credentials from the environment now.
+    pub fn read() -> Credentials {
+        let var = |key: &str| std::env::var(key).unwrap_or_default();
+        let mut jwt = var("OPENIAP_JWT");
+        if jwt.is_empty() {
+            jwt = var("jwt");
+        }
+        if !jwt.is_empty() {
+            return Credentials::jwt(&jwt);
+        }
+        let username = var("OPENIAP_USERNAME");
+        let password = var("OPENIAP_PASSWORD");
+        if !username.is_empty() && !password.is_empty() {
+            return Credentials::userpass(&username, &password);
+        }
+        Credentials::None
+    }
+}
+impl CredentialProvider for EnvCredentials {
+    fn credentials(&self) -> BoxFuture<'_, Result<Credentials, OpenIAPError>> {
+        Box::pin(async { Ok(EnvCredentials::read()) })
+    }
+}
+
+/// The same credentials every time.
+#[derive(Debug, Clone, Default)]
+pub struct StaticCredentials(pub Credentials);
+impl CredentialProvider for StaticCredentials {
+    fn credentials(&self) -> BoxFuture<'_, Result<Credentials, OpenIAPError>> {
+        Box::pin(async { Ok(self.0.clone()) })
+    }
+}
+
+/// Function returning credentials, used by `CallbackCredentials`.
+pub type CredentialsFn = Arc<dyn Fn() -> BoxFuture<'static, Result<Credentials, OpenIAPError>> + Send + Sync>;
+
+/// Credentials from an async callback, for instance to fetch a token from a vault.
+#[derive(Clone)]
+pub struct CallbackCredentials(pub CredentialsFn);
+impl CallbackCredentials {
+    /// Call `callback` every time credentials are needed.
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn() -> BoxFuture<'static, Result<Credentials, OpenIAPError>> + Send + Sync + 'static,
+    {
+        Self(Arc::new(callback))
+    }
+}
+impl CredentialProvider for CallbackCredentials {
+    fn credentials(&self) -> BoxFuture<'_, Result<Credentials, OpenIAPError>> {
+        (self.0)()
+    }
+}
+
+/// A file read by `FileCredentials`, with the modified time of the cached content.
+#[derive(Debug, Default)]
+struct WatchedFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    content: Secret,
+}
+impl WatchedFile {
+    fn new(path: &Path) -> Self {
+        Self { path: path.to_path_buf(), ..Default::default() }
+    }
+    /// Return the content of the file, reading it again if it was modified or replaced since the last read.
+    fn read(&mut self) -> Result<Secret, OpenIAPError> {
+        // kubernetes rotates secrets by swapping a symlink, so follow it for the modified time
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to read {}: {}", self.path.display(), e)))?;
+        if self.modified != Some(modified) {
+            let content = std::fs::read_to_string(&self.path)
+                .map_err(|e| OpenIAPError::ClientError(format!("Failed to read {}: {}", self.path.display(), e)))?;
+            self.content = Secret::new(content.trim());
+            self.modified = Some(modified);
+        }
+        Ok(self.content.clone())
+    }
+}
+
+/// Credentials read from files, like a kubernetes secret mount.
+/// The files are read again whenever they change, so a rotated token is used on the next sign in.
+#[derive(Debug)]
+pub struct FileCredentials {
+    jwt: Option<Mutex<WatchedFile>>,
+    userpass: Option<(Mutex<WatchedFile>, Mutex<WatchedFile>)>,
+}
+impl FileCredentials {
+    /// Sign in with the JWT token in `path`.
+    pub fn jwt(path: &str) -> Self {
+        Self {
+            jwt: Some(Mutex::new(WatchedFile::new(Path::new(path)))),
+            userpass: None,
+        }
+    }
+    /// Sign in with the username in `username_path` and the password in `password_path`.
+    pub fn userpass(username_path: &str, password_path: &str) -> Self {
+        Self {
+            jwt: None,
+            userpass: Some((
+                Mutex::new(WatchedFile::new(Path::new(username_path))),
+                Mutex::new(WatchedFile::new(Path::new(password_path))),
+            )),
+        }
+    }
+    /// Read the current credentials from the files.
+    pub fn read(&self) -> Result<Credentials, OpenIAPError> {
+        if let Some(jwt) = &self.jwt {
+            let jwt = jwt.lock().unwrap().read()?;
+            if !jwt.is_empty() {
+                return Ok(Credentials::Jwt(jwt));
+            }
+        }
+        if let Some((username, password)) = &self.userpass {
+            let username = username.lock().unwrap().read()?;
+            let password = password.lock().unwrap().read()?;
+            if !username.is_empty() && !password.is_empty() {
+                return Ok(Credentials::UserPass { username: username.expose().to_string(), password });
+            }
+        }
+        Ok(Credentials::None)
+    }
+}
+impl CredentialProvider for FileCredentials {
+    fn credentials(&self) -> BoxFuture<'_, Result<Credentials, OpenIAPError>> {
+        Box::pin(async { self.read() })
+    }
+}
+
+impl Client {
+    /// Use `provider` for the credentials used when signing in, it is consulted on every connect and reconnect.
+    /// Credentials set with `set_jwt`, `set_username` and `set_password` or in the url are replaced by the ones from the provider.
+    pub fn set_credential_provider(&self, provider: Arc<dyn CredentialProvider>) {
+        *self.credential_provider.lock().unwrap() = Some(provider);
+        self.set_signed_out(false);
+    }
+    /// Stop using the credential provider, credentials set on the client and the environment are used again.
+    pub fn clear_credential_provider(&self) {
+        *self.credential_provider.lock().unwrap() = None;
+        self.set_signed_out(false);
+    }
+    /// Get the credentials from the credential provider, None if no provider is set.
+    pub async fn provided_credentials(&self) -> Result<Option<Credentials>, OpenIAPError> {
+        let provider = self.credential_provider.lock().unwrap().clone();
+        match provider {
+            Some(provider) => Ok(Some(provider.credentials().await?)),
+            None => Ok(None),
+        }
+    }
+    /// Ask the credential provider for credentials, and store them on the client for the next sign in.
+    pub(crate) async fn refresh_credentials(&self) -> Result<(), OpenIAPError> {
+        if self.is_signed_out() {
+            return Ok(());
+        }
+        match self.provided_credentials().await? {
+            Some(Credentials::Jwt(jwt)) => {
+                self.set_username("");
+                self.set_password("");
+                self.set_jwt(jwt.expose());
+            }
+            Some(Credentials::UserPass { username, password }) => {
+                self.set_username(&username);
+                self.set_password(password.expose());
+                self.set_jwt("");
+            }
+            Some(Credentials::None) => {
+                self.set_username("");
+                self.set_password("");
+                self.set_jwt("");
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
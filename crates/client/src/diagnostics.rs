@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::Client;
+
+/// Statistics for a single command, as returned by `Client::diagnostics`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CommandDiagnostics {
+    /// Number of requests sent.
+    pub count: u64,
+    /// Number of failed requests, by kind: "server", "timeout", "canceled" or "send".
+    pub errors: HashMap<String, u64>,
+    /// Total round trip time in milliseconds.
+    pub total_ms: f64,
+    /// Average round trip time in milliseconds.
+    pub average_ms: f64,
+    /// Bytes sent, as encoded envelopes.
+    pub bytes_sent: u64,
+    /// Bytes received, as encoded envelopes.
+    pub bytes_received: u64,
+}
+
+/// A snapshot of the client state and usage, returned by `Client::diagnostics`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClientDiagnostics {
+    /// Client state, like "Signedin".
+    pub state: String,
+    /// Seconds since the client connected, 0 while disconnected.
+    pub uptime: u64,
+    /// Number of times the client started connecting.
+    pub connection_attempts: u64,
+    /// Number of successful connections.
+    pub connections: u64,
+    /// Number of successful connections after the first.
+    pub reconnects: u64,
+    /// Envelopes sent.
+    pub package_tx: u64,
+    /// Envelopes received.
+    pub package_rx: u64,
+    /// Statistics for each command sent with a reply.
+    pub commands: BTreeMap<String, CommandDiagnostics>,
+    /// Requests waiting for a reply.
+    pub pending_queries: usize,
+    /// Open upload and download streams.
+    pub pending_streams: usize,
+    /// Active watches.
+    pub watches: usize,
+    /// Registered queues and exchanges.
+    pub queues: usize,
+    /// Envelopes waiting to be sent to the server.
+    pub outbound_queue: usize,
+    /// The last failed request or disconnect reason.
+    pub last_error: Option<String>,
+}
+
+impl CommandDiagnostics {
+    /// What was added since `baseline`.
+    fn since(&self, baseline: &CommandDiagnostics) -> CommandDiagnostics {
+        let count = self.count.saturating_sub(baseline.count);
+        let total_ms = (self.total_ms - baseline.total_ms).max(0.0);
+        let errors = self
+            .errors
+            .iter()
+            .map(|(kind, n)| (kind.clone(), n.saturating_sub(baseline.errors.get(kind).copied().unwrap_or_default())))
+            .filter(|(_, n)| *n > 0)
+            .collect();
+        CommandDiagnostics {
+            count,
+            errors,
+            total_ms,
+            average_ms: if count > 0 { total_ms / count as f64 } else { 0.0 },
+            bytes_sent: self.bytes_sent.saturating_sub(baseline.bytes_sent),
+            bytes_received: self.bytes_received.saturating_sub(baseline.bytes_received),
+        }
+    }
+}
+
+impl ClientDiagnostics {
+    /// Counters relative to `baseline`, commands without new requests are left out.
+    /// Everything describing the current state is kept as is.
+    fn since(&self, baseline: &ClientDiagnostics) -> ClientDiagnostics {
+        let empty = CommandDiagnostics::default();
+        let commands = self
+            .commands
+            .iter()
+            .map(|(command, s)| (command.clone(), s.since(baseline.commands.get(command).unwrap_or(&empty))))
+            .filter(|(_, s)| s.count > 0 || !s.errors.is_empty())
+            .collect();
+        ClientDiagnostics {
+            connection_attempts: self.connection_attempts.saturating_sub(baseline.connection_attempts),
+            connections: self.connections.saturating_sub(baseline.connections),
+            reconnects: self.reconnects.saturating_sub(baseline.reconnects),
+            package_tx: self.package_tx.saturating_sub(baseline.package_tx),
+            package_rx: self.package_rx.saturating_sub(baseline.package_rx),
+            commands,
+            ..self.clone()
+        }
+    }
+}
+
+impl Client {
+    /// Get a snapshot of the client state and usage statistics.
+    /// Counters are relative to the last call with `reset` set to true, and `reset` also clears the last error.
+    /// The statistics behind the `otel_commands` metrics are not touched, so those keep counting up.
+    pub async fn diagnostics(&self, reset: bool) -> ClientDiagnostics {
+        let (pending_queries, pending_streams, watches, queues) = {
+            let inner = self.inner.lock().await;
+            let pending_queries = inner.queries.lock().await.len();
+            let pending_streams = inner.streams.lock().await.len();
+            let watches = inner.watches.lock().await.len();
+            let queues = inner.queues.lock().await.len();
+            (pending_queries, pending_streams, watches, queues)
+        };
+        let state = self.get_state().to_string();
+        let outbound_queue = self.out_envelope_receiver.len();
+        let total = {
+            let mut stats = self.stats.lock().unwrap();
+            let commands = stats
+                .commands
+                .iter()
+                .map(|(command, s)| {
+                    let average_ms = if s.count > 0 { s.elapsed_ms / s.count as f64 } else { 0.0 };
+                    let diagnostics = CommandDiagnostics {
+                        count: s.count,
+                        errors: s.errors.clone(),
+                        total_ms: s.elapsed_ms,
+                        average_ms,
+                        bytes_sent: s.bytes_sent,
+                        bytes_received: s.bytes_received,
+                    };
+                    (command.clone(), diagnostics)
+                })
+                .collect();
+            let diagnostics = ClientDiagnostics {
+                state,
+                uptime: stats.connected_since.map(|t| t.elapsed().as_secs()).unwrap_or_default(),
+                connection_attempts: stats.connection_attempts,
+                connections: stats.connections,
+                reconnects: stats.reconnects,
+                package_tx: stats.package_tx,
+                package_rx: stats.package_rx,
+                commands,
+                pending_queries,
+                pending_streams,
+                watches,
+                queues,
+                outbound_queue,
+                last_error: stats.last_error.clone(),
+            };
+            if reset {
+                stats.last_error = None;
+            }
+            diagnostics
+        };
+        let mut baseline = self.diagnostics_baseline.lock().unwrap();
+        let diagnostics = total.since(&baseline);
+        if reset {
+            *baseline = total;
+        }
+        diagnostics
+    }
+}
@@ -0,0 +1,104 @@
+use openiap_proto::errors::OpenIAPError;
+use openiap_proto::openiap::{QueryRequest, UpdateDocumentRequest, UpdateWorkitemRequest};
+use openiap_proto::workitem::WorkitemState;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info};
+
+use crate::typed_workitem::workitem_from_document;
+use crate::{Client, EnvConfig};
+
+impl Client {
+    /// Renew the lease on a workitem being processed, by setting `lastrun` to now.
+    /// `owner` is stored in `errorsource`, so the sweeper knows which worker abandoned it.
+    /// Only `lastrun` and `errorsource` are written, and only while the workitem is still processing.
+    /// Returns false if the workitem is no longer processing, meaning the lease was lost.
+    #[tracing::instrument(skip_all)]
+    pub async fn touch_workitem(
+        &self,
+        id: &str,
+        env: EnvConfig,
+        owner: &str,
+    ) -> Result<bool, OpenIAPError> {
+        let request = UpdateDocumentRequest {
+            collectionname: "workitems".to_string(),
+            query: serde_json::json!({ "_id": id, "_type": "workitem", "state": WorkitemState::Processing.as_str() }).to_string(),
+            document: serde_json::json!({ "$set": {
+                "lastrun": crate::util::format_iso8601(SystemTime::now()),
+                "errorsource": owner,
+            } }).to_string(),
+            ..Default::default()
+        };
+        let response = self.update_document(request, env).await?;
+        let modified = response.opresult.map(|r| r.modified_count).unwrap_or_default();
+        Ok(modified > 0)
+    }
+    /// Set workitems in `wiq` that have been processing for longer than `lease` without being touched
+    /// back to retry, and returns their ids. The worker that held the lease is kept in `errorsource`.
+    #[tracing::instrument(skip_all)]
+    pub async fn requeue_expired_workitems(
+        &self,
+        env: EnvConfig,
+        wiq: &str,
+        lease: Duration,
+    ) -> Result<Vec<String>, OpenIAPError> {
+        let top = 1000;
+        let now = SystemTime::now();
+        let mut requeued = Vec::new();
+        // page by _id, requeued items drop out of the filter and items still leased stay in it
+        let mut last_id: Option<String> = None;
+        loop {
+            let mut query = serde_json::json!({ "_type": "workitem", "wiq": wiq, "state": WorkitemState::Processing.as_str() });
+            if let Some(last_id) = &last_id {
+                query["_id"] = serde_json::json!({ "$gt": last_id });
+            }
+            let query = QueryRequest {
+                collectionname: "workitems".to_string(),
+                query: query.to_string(),
+                orderby: "{\"_id\":1}".to_string(),
+                top,
+                ..Default::default()
+            };
+            let response = self.query(query, env.clone()).await?;
+            let documents: Vec<serde_json::Value> = serde_json::from_str(&response.results)
+                .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse workitems: {}", e)))?;
+            let count = documents.len() as i32;
+            for document in documents {
+                let mut workitem = workitem_from_document(&document);
+                last_id = Some(workitem.id.clone());
+                // items popped by workers without a heartbeat only have _modified
+                let touched = workitem.get_lastrun().or_else(|| {
+                    document["_modified"].as_str().and_then(crate::util::parse_iso8601)
+                });
+                let expired = match touched {
+                    Some(touched) => now.duration_since(touched).unwrap_or_default() > lease,
+                    None => false,
+                };
+                if !expired {
+                    continue;
+                }
+                if workitem.errorsource.is_empty() {
+                    workitem.errorsource = document["_modifiedby"].as_str().unwrap_or_default().to_string();
+                }
+                debug!("Lease expired on workitem {} held by {}", workitem.id, workitem.errorsource);
+                workitem.state = WorkitemState::Retry.to_string();
+                workitem.errortype = "application".to_string();
+                workitem.errormessage = format!("Lease expired after {} seconds", lease.as_secs());
+                let id = workitem.id.clone();
+                let request = UpdateWorkitemRequest {
+                    workitem: Some(workitem),
+                    ignoremaxretries: false,
+                    ..Default::default()
+                };
+                self.update_workitem(request, env.clone()).await?;
+                requeued.push(id);
+            }
+            if count < top {
+                break;
+            }
+        }
+        if !requeued.is_empty() {
+            info!("Requeued {} workitems with expired lease in {}", requeued.len(), wiq);
+        }
+        Ok(requeued)
+    }
+}
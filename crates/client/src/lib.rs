@@ -51,8 +51,60 @@ mod tests;
 mod ws;
 mod grpc;
 mod util;
+mod sync;
+mod worker;
+mod subscription;
+mod typed_workitem;
+mod scheduler;
+mod lease;
+mod wiq;
+mod loader;
+mod pop;
+mod agent_pods;
+mod agent_logs;
+mod agent_context;
+mod package;
+mod diagnostics;
+mod trace_context;
+mod telemetry;
+mod secret;
+mod credentials;
+mod session;
+mod log_sink;
+#[cfg(feature = "otel")]
+mod spool;
+#[cfg(feature = "prometheus")]
+mod prometheus;
 pub use crate::util::{set_otel_url, enable_tracing, disable_tracing};
+pub use crate::sync::{SyncDirection, SyncOptions, SyncEntry, SyncAction, SyncActionKind, SyncReport, plan_sync, sync_local_path};
+pub use crate::worker::{WorkitemWorker, WorkitemWorkerOptions, WorkitemWorkerHandle, WorkitemError, WorkitemHandlerFn};
+pub use crate::subscription::{WorkitemSubscription, WorkitemSubscriptionOptions, WorkitemNotification, workitem_watch_pipeline};
+pub use crate::typed_workitem::{TypedWorkitem, workitem_from_document, validate_workitem_transition};
+pub use crate::wiq::{WorkitemQueueStats, WorkitemQueueSpec, workitemqueue_from_document, WorkitemFilter, WorkitemBulkOptions, WorkitemBulkResult, BulkProgressFn};
+#[cfg(feature = "otel")]
+pub use crate::wiq::publish_workitem_queue_stats;
+pub use crate::loader::{WorkitemImportOptions, WorkitemImportReport, WorkitemImportError, ImportRows, parse_csv, csv_to_rows, ndjson_to_rows, row_to_workitem, render_name_template};
+pub use crate::pop::{PopWorkitemOptions, PoppedWorkitem, PoppedFile, DownloadErrorPolicy};
+pub use crate::agent_pods::{AgentPod, AgentStats, agent_pods_from_json};
+pub use crate::agent_logs::{LogLine, TailLogOptions, AgentLogStream, new_log_lines};
+pub use crate::secret::Secret;
+pub use crate::session::{SessionClaims, decode_jwt_claims};
+pub use crate::credentials::{Credentials, CredentialProvider, CredentialsFn, EnvCredentials, StaticCredentials, CallbackCredentials, FileCredentials};
+pub use crate::log_sink::{CollectionLogLayer, CollectionLogOptions, is_client_event};
+pub use crate::agent_context::{AgentContext, AgentStatus, AgentHealth, Shutdown, health_response, wait_for_signal};
+pub use crate::package::{Package, PackageManifest, PackagePort, PACKAGE_LANGUAGES, parse_version, bump_version};
+pub use crate::diagnostics::{ClientDiagnostics, CommandDiagnostics};
+pub use crate::trace_context::current_trace_ids;
+pub use crate::telemetry::{TelemetryConfig, DEFAULT_ANALYTICS_URL};
+#[cfg(feature = "otel")]
+pub use crate::spool::TelemetrySpool;
+#[cfg(feature = "prometheus")]
+pub use crate::prometheus::{ProcessMetrics, render_prometheus, prometheus_name};
+pub use crate::scheduler::{CronSchedule, CatchUpPolicy, RecurringWorkitem, WorkitemScheduler, WorkitemSchedulerOptions, WorkitemSchedulerHandle};
+pub use openiap_proto::workitem::{WorkitemState, timestamp_to_systemtime, systemtime_to_timestamp};
 pub use crate::otel::{set_f64_observable_gauge, set_u64_observable_gauge, set_i64_observable_gauge, disable_observable_gauge};
+#[cfg(feature = "otel")]
+pub use crate::otel::{inc_counter, add_up_down_counter, record_histogram};
 
 type QuerySender = oneshot::Sender<Envelope>;
 type StreamSender = mpsc::Sender<Vec<u8>>;
@@ -73,6 +125,16 @@ pub struct Client {
 
     /// Keep track of usage of the client
     stats: Arc<std::sync::Mutex<ClientStatistics>>,
+    /// Counters at the last `diagnostics` reset, `diagnostics` reports what was added since
+    diagnostics_baseline: Arc<std::sync::Mutex<ClientDiagnostics>>,
+    /// Where and how telemetry is exported
+    telemetry: Arc<std::sync::Mutex<TelemetryConfig>>,
+    /// Token issued by the server for the current session
+    session_jwt: Arc<std::sync::Mutex<Secret>>,
+    /// Supplies credentials on every sign in, if set
+    credential_provider: Arc<std::sync::Mutex<Option<Arc<dyn CredentialProvider>>>>,
+    /// Set by `signout`, the client connects as guest until credentials are given again
+    signed_out: Arc<std::sync::Mutex<bool>>,
 
     task_handles: Arc<std::sync::Mutex<Vec<JoinHandle<()>>>>,
     /// The inner client object
@@ -87,12 +149,9 @@ pub struct Client {
     pub auto_reconnect: Arc<std::sync::Mutex<bool>>,
     /// URL used to connect to server, processed and without credentials
     pub url: Arc<std::sync::Mutex<String>>,
-    /// Username used to connect to server
-    pub username: Arc<std::sync::Mutex<String>>,
-    /// Password used to connect to server
-    pub password: Arc<std::sync::Mutex<String>>,
-    /// JWT token used to connect to server
-    pub jwt: Arc<std::sync::Mutex<String>>,
+    username: Arc<std::sync::Mutex<String>>,
+    password: Arc<std::sync::Mutex<Secret>>,
+    jwt: Arc<std::sync::Mutex<Secret>>,
     service_name: Arc<std::sync::Mutex<String>>,
     agent_name: Arc<std::sync::Mutex<String>>,
     agent_version: Arc<std::sync::Mutex<String>>,
@@ -112,11 +171,25 @@ pub struct Client {
     /// The default timeout for requests
     pub default_timeout: Arc<std::sync::Mutex<tokio::time::Duration>>,
 }
+/// Round trip statistics for a single command, collected in `send`
+#[derive(Clone, Default)]
+struct CommandStatistics {
+    count: u64,
+    elapsed_ms: f64,
+    bytes_sent: u64,
+    bytes_received: u64,
+    errors: std::collections::HashMap<String, u64>,
+}
 /// The `ClientStatistics` struct provides the statistics for usage of the client
 #[derive(Clone, Default)]
 pub struct ClientStatistics {
+    commands: std::collections::HashMap<String, CommandStatistics>,
     connection_attempts: u64,
     connections: u64,
+    reconnects: u64,
+    connected_once: bool,
+    connected_since: Option<std::time::Instant>,
+    last_error: Option<String>,
     package_tx: u64,
     package_rx: u64,
     signin: u64,
@@ -202,8 +275,8 @@ pub enum ClientEvent {
     Disconnected(String),
     /// The client has signed in
     SignedIn,
-    // The client has signed out
-    // SignedOut,
+    /// The client has signed out
+    SignedOut,
     // The client has received a message
     // Message(Envelope),
     // The client has received a ping event from the server
@@ -347,6 +420,11 @@ impl Client {
         Self {
             task_handles: Arc::new(std::sync::Mutex::new(Vec::new())),
             stats: Arc::new(std::sync::Mutex::new(ClientStatistics::default())),
+            diagnostics_baseline: Arc::new(std::sync::Mutex::new(ClientDiagnostics::default())),
+            telemetry: Arc::new(std::sync::Mutex::new(TelemetryConfig::default())),
+            session_jwt: Arc::new(std::sync::Mutex::new(Secret::default())),
+            credential_provider: Arc::new(std::sync::Mutex::new(None)),
+            signed_out: Arc::new(std::sync::Mutex::new(false)),
             user: Arc::new(std::sync::Mutex::new(None)),
             client: Arc::new(std::sync::Mutex::new(ClientEnum::None)),
             connect_called: Arc::new(std::sync::Mutex::new(false)),
@@ -365,8 +443,8 @@ impl Client {
             auto_reconnect: Arc::new(std::sync::Mutex::new(true)),
             url: Arc::new(std::sync::Mutex::new("".to_string())),
             username: Arc::new(std::sync::Mutex::new("".to_string())),
-            password: Arc::new(std::sync::Mutex::new("".to_string())),
-            jwt: Arc::new(std::sync::Mutex::new("".to_string())),
+            password: Arc::new(std::sync::Mutex::new(Secret::default())),
+            jwt: Arc::new(std::sync::Mutex::new(Secret::default())),
             service_name: Arc::new(std::sync::Mutex::new("rust".to_string())),
             agent_name: Arc::new(std::sync::Mutex::new("rust".to_string())),
             agent_version: Arc::new(std::sync::Mutex::new(version.to_string())),
@@ -491,13 +569,16 @@ impl Client {
             let agent_name = self.get_agent_name();
             let agent_version = self.get_agent_version();
             let version = env!("CARGO_PKG_VERSION");
-            match otel::init_telemetry(&service_name, &agent_name, &agent_version, &version, &apihostname, _otel_metric_url.as_str(),
-            _otel_trace_url.as_str(),  _otel_log_url.as_str(),
-            &self.stats) {
+            let mut telemetry = self.get_telemetry_config();
+            telemetry.metric_url.get_or_insert(_otel_metric_url);
+            telemetry.trace_url.get_or_insert(_otel_trace_url);
+            telemetry.log_url.get_or_insert(_otel_log_url);
+            match otel::init_telemetry(&service_name, &agent_name, &agent_version, &version, &apihostname,
+            &telemetry, &self.stats) {
                 Ok(_) => (),
                 Err(e) => {
+                    // telemetry is optional, keep connecting without the exporters that failed
                     error!("Failed to initialize telemetry: {}", e);
-                    return None;
                 }
             }
         }
@@ -726,13 +807,19 @@ impl Client {
     }
     /// Handle auto-signin after a connection has been established.
     pub async fn post_connected(&self) -> Result<(), OpenIAPError> {
-        if self.get_username().is_empty() && self.get_password().is_empty() {
+        if let Err(e) = self.refresh_credentials().await {
+            self.set_connected(ClientState::Disconnected, Some(&e.to_string()));
+            return Err(e);
+        }
+        // after a sign out, neither the provider nor the environment is used
+        let provided = self.credential_provider.lock().unwrap().is_some() || self.is_signed_out();
+        if !provided && self.get_username().is_empty() && self.get_password().is_empty() {
             self.set_username(&std::env::var("OPENIAP_USERNAME").unwrap_or_default());
             self.set_password(&std::env::var("OPENIAP_PASSWORD").unwrap_or_default());
         }
         if !self.get_username().is_empty() && !self.get_password().is_empty() {
-            debug!("Signing in with username: {}", self.get_username());
-            let signin = SigninRequest::with_userpass(self.get_username().as_str(), self.get_password().as_str());
+            debug!("Signing in with username and password");
+            let signin = SigninRequest::with_userpass(self.get_username().as_str(), self.get_password().expose());
             let loginresponse = self.signin(signin).await;
             match loginresponse {
                 Ok(response) => {
@@ -747,13 +834,15 @@ impl Client {
                 }
             }
         } else {
-            self.set_jwt(&std::env::var("OPENIAP_JWT").unwrap_or_default());
-            if self.get_jwt().is_empty() {
+            if !provided && self.get_jwt().is_empty() {
+                self.set_jwt(&std::env::var("OPENIAP_JWT").unwrap_or_default());
+            }
+            if !provided && self.get_jwt().is_empty() {
                 self.set_jwt(&std::env::var("jwt").unwrap_or_default());
             }
             if !self.get_jwt().is_empty() {
                 debug!("Signing in with JWT");
-                let signin = SigninRequest::with_jwt(self.get_jwt().as_str());
+                let signin = SigninRequest::with_jwt(self.get_jwt().expose());
                 let loginresponse = self.signin(signin).await;
                 match loginresponse {
                     Ok(response) => match response.user {
@@ -868,6 +957,14 @@ impl Client {
 
             }
             if (state == ClientState::Connected|| state == ClientState::Signedin) && (current == ClientState::Disconnected || current == ClientState::Connecting) { 
+                {
+                    let mut stats = self.stats.lock().unwrap();
+                    if stats.connected_once {
+                        stats.reconnects += 1;
+                    }
+                    stats.connected_once = true;
+                    stats.connected_since = Some(std::time::Instant::now());
+                }
                 if let Ok(_handle) = tokio::runtime::Handle::try_current() {
                     self.stats.lock().unwrap().connections += 1;
                     let me = self.clone();
@@ -890,6 +987,13 @@ impl Client {
                 } else {
                     debug!("Disconnected");
                 }
+                {
+                    let mut stats = self.stats.lock().unwrap();
+                    stats.connected_since = None;
+                    if let Some(message) = message {
+                        stats.last_error = Some(message.to_string());
+                    }
+                }
                 if let Ok(_handle) = tokio::runtime::Handle::try_current() {
                     let me = self.clone();
                     let message = match message {
@@ -1086,44 +1190,44 @@ impl Client {
         let url = self.url.lock().unwrap();
         url.to_string()
     }
-    /// Set the username flag to true or false
+    /// Set the username used to connect to server
     #[tracing::instrument(skip_all)]
     pub fn set_username(&self, username: &str) {
         let mut current = self.username.lock().unwrap();
-        trace!("Set username: {} from {}", username, *current);
+        trace!("Set username");
         *current = username.to_string();
     }
-    /// Return value of the username string
+    /// Return the username used to connect to server
     #[tracing::instrument(skip_all)]
-    fn get_username(&self) -> String {
+    pub fn get_username(&self) -> String {
         let username = self.username.lock().unwrap();
         username.to_string()
     }
-    /// Set the password value
+    /// Set the password used to connect to server
     #[tracing::instrument(skip_all)]
     pub fn set_password(&self, password: &str) {
         let mut current = self.password.lock().unwrap();
-        trace!("Set password: {} from {}", password, *current);
-        *current = password.to_string();
+        trace!("Set password");
+        *current = Secret::new(password);
     }
-    /// Return value of the password string
+    /// Return the password used to connect to server
     #[tracing::instrument(skip_all)]
-    fn get_password(&self) -> String {
+    pub fn get_password(&self) -> Secret {
         let password = self.password.lock().unwrap();
-        password.to_string()
+        password.clone()
     }
-    /// Set the jwt flag to true or false
+    /// Set the JWT token used to connect to server
     #[tracing::instrument(skip_all)]
     pub fn set_jwt(&self, jwt: &str) {
         let mut current = self.jwt.lock().unwrap();
-        trace!("Set jwt: {} from {}", jwt, *current);
-        *current = jwt.to_string();
+        trace!("Set jwt");
+        *current = Secret::new(jwt);
     }
-    /// Return value of the jwt string
+    /// Return the JWT token used to connect to server
     #[tracing::instrument(skip_all)]
-    fn get_jwt(&self) -> String {
+    pub fn get_jwt(&self) -> Secret {
         let jwt = self.jwt.lock().unwrap();
-        jwt.to_string()
+        jwt.clone()
     }
     
     /// Set the service name
@@ -1259,10 +1363,14 @@ impl Client {
         sqids.encode(&[num1, num2, num3 ]).unwrap().to_string()
     }
     /// Internal function, Send a message to the OpenIAP server, and wait for a response.
-    #[tracing::instrument(skip_all)]
+    #[tracing::instrument(skip_all, fields(otel.kind = "client", command = %msg.command, size = tracing::field::Empty, outcome = tracing::field::Empty))]
     async fn send(&self, msg: Envelope, timeout: Option<tokio::time::Duration>) -> Result<Envelope, OpenIAPError> {
+        let command = msg.command.clone();
+        let bytes_sent = prost::Message::encoded_len(&msg) as u64;
+        tracing::Span::current().record("size", bytes_sent);
+        let started = std::time::Instant::now();
         let response = self.send_noawait(msg).await;
-        match response {
+        let (result, errorkind) = match response {
             Ok((response_rx, id)) => {
                 let timeout = match timeout {
                     Some(t) => t,
@@ -1274,9 +1382,12 @@ impl Client {
                 inner.queries.lock().await.remove(&id);
 
                 match result {
-                    Ok(Ok(response)) => Ok(response),
-                    Ok(Err(e)) => Err(OpenIAPError::CustomError(e.to_string())),
-                    Err(_) => Err(OpenIAPError::ClientError("Request timed out".to_string())),
+                    Ok(Ok(response)) => {
+                        let errorkind = if response.command == "error" { Some("server") } else { None };
+                        (Ok(response), errorkind)
+                    }
+                    Ok(Err(e)) => (Err(OpenIAPError::CustomError(e.to_string())), Some("canceled")),
+                    Err(_) => (Err(OpenIAPError::ClientError("Request timed out".to_string())), Some("timeout")),
                 }
                 // // Await the response
                 // let response = response_rx.await;
@@ -1291,8 +1402,41 @@ impl Client {
                 //     Err(e) => Err(OpenIAPError::CustomError(e.to_string())),
                 // }
             }
-            Err(e) => Err(OpenIAPError::CustomError(e.to_string())),
+            Err(e) => (Err(OpenIAPError::CustomError(e.to_string())), Some("send")),
+        };
+        let bytes_received = match &result {
+            Ok(response) => prost::Message::encoded_len(response) as u64,
+            Err(_) => 0,
+        };
+        self.record_command(&command, started.elapsed(), bytes_sent, bytes_received, errorkind);
+        tracing::Span::current().record("outcome", errorkind.unwrap_or("ok"));
+        if errorkind.is_some() {
+            let message = match &result {
+                Ok(response) => match response.data.as_ref().map(|data| <ErrorResponse as prost::Message>::decode(data.value.as_ref())) {
+                    Some(Ok(e)) => e.message,
+                    _ => "Unknown server error".to_string(),
+                },
+                Err(e) => e.to_string(),
+            };
+            self.stats.lock().unwrap().last_error = Some(format!("{}: {}", command, message));
         }
+        result
+    }
+    /// Internal function, update the per command statistics after a round trip to the server
+    fn record_command(&self, command: &str, elapsed: Duration, bytes_sent: u64, bytes_received: u64, errorkind: Option<&str>) {
+        {
+            let mut stats = self.stats.lock().unwrap();
+            let entry = stats.commands.entry(command.to_string()).or_default();
+            entry.count += 1;
+            entry.elapsed_ms += elapsed.as_secs_f64() * 1000.0;
+            entry.bytes_sent += bytes_sent;
+            entry.bytes_received += bytes_received;
+            if let Some(kind) = errorkind {
+                *entry.errors.entry(kind.to_string()).or_default() += 1;
+            }
+        }
+        #[cfg(feature = "otel_elapsed")]
+        otel::record_command_duration(command, elapsed.as_secs_f64() * 1000.0, errorkind.is_none());
     }
     /// Internal function, Send a message to the OpenIAP server, and do not wait for a response.
     /// used when sending a stream of data, or when we do not need a response.
@@ -1350,6 +1494,7 @@ impl Client {
             && envelope.command != "signin" && envelope.command != "getelement" && envelope.command != "pong" {
             return Err(OpenIAPError::ClientError(format!("Not connected ( {:?} )", self.get_state())));
         }
+        crate::trace_context::apply_trace_context(&mut envelope);
         let env = envelope.clone();
         let command = envelope.command.clone();
         self.stats.lock().unwrap().package_tx += 1;
@@ -1441,7 +1586,13 @@ impl Client {
             self.pong(&received.id).await;
             // self.event_sender.send(crate::ClientEvent::Ping).await.unwrap();
         } else if command == "refreshtoken" {
-            // TODO: Do we store the new jwt at some point in the future
+            if let Some(data) = received.data {
+                match <RefreshToken as prost::Message>::decode(data.value.as_ref()) {
+                    Ok(refresh) if !refresh.jwt.is_empty() => self.set_session_jwt(&refresh.jwt),
+                    Ok(_) => {}
+                    Err(e) => debug!("Failed to decode refreshtoken: {}", e),
+                }
+            }
         } else if command == "beginstream"
             || command == "stream"
             || command == "endstream"
@@ -1473,7 +1624,8 @@ impl Client {
                 let queuename = queueevent.replyto.clone();
                 let correlation_id = queueevent.correlation_id.clone();
                 let me = self.clone();
-                tokio::spawn(async move {
+                let span = crate::trace_context::queue_event_span(&queueevent.queuename, &received.traceid, &received.spanid);
+                tokio::spawn(tracing::Instrument::instrument(async move {
                     let result_fut = callback(Arc::new(me.clone()), queueevent);
                     let result = result_fut.await;
                     if result.is_some() && !queuename.is_empty() {
@@ -1492,7 +1644,7 @@ impl Client {
                             error!("Failed to send queue event response: {}", e);
                         }
                     }
-                });
+                }, span));
             }
         } else if let Some(response_tx) = queries.remove(&rid) {
             let stream = streams.get(rid.as_str());
@@ -1570,21 +1722,32 @@ impl Client {
     /// will prefere OPENIAP_JWT (or jwt) over OPENIAP_USERNAME and OPENIAP_PASSWORD.
     #[tracing::instrument(skip_all)]
     pub async fn signin(&self, mut config: SigninRequest) -> Result<SigninResponse, OpenIAPError> {
-        // autodetect how to signin using environment variables
+        // use the credential provider if set, else autodetect how to signin using environment variables
         if config.username.is_empty() && config.password.is_empty() && config.jwt.is_empty() {
-            if config.jwt.is_empty() {
-                config.jwt = std::env::var("OPENIAP_JWT").unwrap_or_default();
-            }
-            if config.jwt.is_empty() {
-                config.jwt = std::env::var("jwt").unwrap_or_default();
-            }
-            // if no jwt was found, test for username and password
-            if config.jwt.is_empty() {
-                if config.username.is_empty() {
-                    config.username = std::env::var("OPENIAP_USERNAME").unwrap_or_default();
+            if let Some(credentials) = self.provided_credentials().await? {
+                match credentials {
+                    Credentials::Jwt(jwt) => config.jwt = jwt.expose().to_string(),
+                    Credentials::UserPass { username, password } => {
+                        config.username = username;
+                        config.password = password.expose().to_string();
+                    }
+                    Credentials::None => {}
                 }
-                if config.password.is_empty() {
-                    config.password = std::env::var("OPENIAP_PASSWORD").unwrap_or_default();
+            } else {
+                if config.jwt.is_empty() {
+                    config.jwt = std::env::var("OPENIAP_JWT").unwrap_or_default();
+                }
+                if config.jwt.is_empty() {
+                    config.jwt = std::env::var("jwt").unwrap_or_default();
+                }
+                // if no jwt was found, test for username and password
+                if config.jwt.is_empty() {
+                    if config.username.is_empty() {
+                        config.username = std::env::var("OPENIAP_USERNAME").unwrap_or_default();
+                    }
+                    if config.password.is_empty() {
+                        config.password = std::env::var("OPENIAP_PASSWORD").unwrap_or_default();
+                    }
                 }
             }
         }
@@ -1614,8 +1777,10 @@ impl Client {
                     prost::Message::decode(m.data.as_ref().unwrap().value.as_ref())
                         .map_err(|e| OpenIAPError::CustomError(e.to_string()))?;
                 if !config.validateonly {
+                    self.set_signed_out(false);
                     self.set_connected(ClientState::Signedin, None);
                     self.set_user(Some(response.user.as_ref().unwrap().clone()));
+                    self.set_session_jwt(&response.jwt);
                 }
                 Ok(response)
             }
@@ -1624,6 +1789,7 @@ impl Client {
                 debug!("Sign-in failed: {}", e.to_string());
                 if !config.validateonly {
                     self.set_user(None);
+                    self.set_session_jwt("");
                 }
                 Err(OpenIAPError::ClientError(e.to_string()))
             }
@@ -3601,12 +3767,54 @@ impl Client {
     }
     /// Pop a workitem from a workitem queue, return None if no workitem is available
     /// Any files attached to the workitem will be downloaded to the downloadfolder ( default "." )
+    /// A file that fails to download is only logged, and the workitem is returned without it.
+    /// Use `pop_workitem_with_files` to fail, or get the download errors per file, instead.
     #[tracing::instrument(skip_all)]
     pub async fn pop_workitem(
         &self,
         config: PopWorkitemRequest,
         env: EnvConfig,
         downloadfolder: Option<&str>,
+    ) -> Result<PopWorkitemResponse, OpenIAPError> {
+        let response = self.pop_workitem_nofiles(config, env).await?;
+        match &response.workitem {
+            Some(wi) => {
+                for f in &wi.files {
+                    if !f.id.is_empty() {
+                        let downloadconfig = DownloadRequest {
+                            id: f.id.clone(),
+                            collectionname: "fs.files".to_string(),
+                            ..Default::default()
+                        };
+                        let downloadresult =
+                            match self.download(downloadconfig,
+                                crate::EnvConfig::new(),
+                                downloadfolder, None).await
+                            {
+                                Ok(r) => r,
+                                Err(e) => {
+                                    error!("Failed to download file {} for workitem {}: {}", f.filename, wi.id, e);
+                                    continue;
+                                }
+                            };
+                        debug!(
+                            "File {} was downloaded as {}",
+                            f.filename, downloadresult.filename
+                        );
+                    }
+                }
+            }
+            None => {
+                debug!("No workitem found");
+            }
+        }
+        Ok(response)
+    }
+    /// Pop a workitem from a workitem queue without downloading its files
+    pub(crate) async fn pop_workitem_nofiles(
+        &self,
+        config: PopWorkitemRequest,
+        env: EnvConfig,
     ) -> Result<PopWorkitemResponse, OpenIAPError> {
         if config.wiq.is_empty() && config.wiqid.is_empty() {
             return Err(OpenIAPError::ClientError(
@@ -3639,38 +3847,6 @@ impl Client {
                 }
                 let response: PopWorkitemResponse = prost::Message::decode(data.value.as_ref())
                     .map_err(|e| OpenIAPError::CustomError(e.to_string()))?;
-
-                match &response.workitem {
-                    Some(wi) => {
-                        for f in &wi.files {
-                            if !f.id.is_empty() {
-                                let downloadconfig = DownloadRequest {
-                                    id: f.id.clone(),
-                                    collectionname: "fs.files".to_string(),
-                                    ..Default::default()
-                                };
-                                let downloadresult =
-                                    match self.download(downloadconfig,
-                                        crate::EnvConfig::new(),
-                                        downloadfolder, None).await
-                                    {
-                                        Ok(r) => r,
-                                        Err(e) => {
-                                            debug!("Failed to download file: {}", e);
-                                            continue;
-                                        }
-                                    };
-                                debug!(
-                                    "File {} was downloaded as {}",
-                                    f.filename, downloadresult.filename
-                                );
-                            }
-                        }
-                    }
-                    None => {
-                        debug!("No workitem found");
-                    }
-                }
                 Ok(response)
             }
             Err(e) => Err(OpenIAPError::ClientError(e.to_string())),
@@ -3681,6 +3857,7 @@ impl Client {
     /// If the file is larger than 5 megabytes it will be uploaded to the database and attached to the workitem
     /// If a fileid is provided it will be used to update the file
     /// if a filename is provided without the id, it will be deleted
+    /// The state transition is not checked, use `update_workitem_checked` or `set_workitem_state` for that
     #[tracing::instrument(skip_all)]
     pub async fn update_workitem(
         &self,
@@ -3694,6 +3871,9 @@ impl Client {
                         "No workitem id provided".to_string(),
                     ));
                 }
+                if !wiq.state.is_empty() {
+                    wiq.get_state()?;
+                }
             }
             None => {
                 return Err(OpenIAPError::ClientError(
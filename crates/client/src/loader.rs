@@ -0,0 +1,442 @@
+use openiap_proto::errors::OpenIAPError;
+use openiap_proto::openiap::{PushWorkitemsRequest, QueryRequest, Workitem, WorkitemFile};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, info};
+
+use crate::{Client, EnvConfig};
+
+/// Options for `push_workitems_from_csv` and `push_workitems_from_ndjson`.
+#[derive(Debug, Clone)]
+pub struct WorkitemImportOptions {
+    /// Name of the workitem queue to push to.
+    pub wiq: String,
+    /// Map of column name to payload key, None copies every column into the payload.
+    pub columns: Option<Vec<(String, String)>>,
+    /// Workitem name, `{column}` is replaced with the column value and `{row}` with the row number.
+    pub name_template: String,
+    /// Column holding a local file path to attach to the workitem.
+    pub file_column: Option<String>,
+    /// Skip rows whose value in this column was already seen, or already exists in the queue.
+    pub key_column: Option<String>,
+    /// Number of workitems sent per `push_workitems` call.
+    pub chunk_size: usize,
+    /// Convert CSV values that look like numbers or booleans, instead of keeping them as strings.
+    pub infer_types: bool,
+    /// CSV field delimiter.
+    pub delimiter: char,
+}
+impl WorkitemImportOptions {
+    /// Import into `wiq` with default options.
+    pub fn new(wiq: &str) -> Self {
+        Self {
+            wiq: wiq.to_string(),
+            ..Default::default()
+        }
+    }
+}
+impl Default for WorkitemImportOptions {
+    fn default() -> Self {
+        Self {
+            wiq: String::new(),
+            columns: None,
+            name_template: "Row {row}".to_string(),
+            file_column: None,
+            key_column: None,
+            chunk_size: 100,
+            infer_types: false,
+            delimiter: ',',
+        }
+    }
+}
+
+/// A row that could not be imported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkitemImportError {
+    /// Row number in the source file, the first data row is 1.
+    pub row: usize,
+    /// Why the row was not imported.
+    pub message: String,
+}
+
+/// Result of a bulk import.
+#[derive(Debug, Clone, Default)]
+pub struct WorkitemImportReport {
+    /// Number of data rows read from the file.
+    pub rows: usize,
+    /// Ids of the created workitems.
+    pub created: Vec<String>,
+    /// Row numbers skipped because of a duplicate key.
+    pub duplicates: Vec<usize>,
+    /// Rows that failed to parse or push.
+    pub errors: Vec<WorkitemImportError>,
+}
+
+/// Rows read from a file with their row numbers, and the rows that failed to parse.
+pub type ImportRows = (Vec<(usize, Map<String, Value>)>, Vec<WorkitemImportError>);
+
+/// Parse CSV text into records, supports quoted fields with escaped quotes and line breaks.
+pub fn parse_csv(text: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    quoted = false;
+                }
+            } else {
+                field.push(c);
+            }
+        } else if c == '"' && field.is_empty() {
+            quoted = true;
+        } else if c == delimiter {
+            record.push(std::mem::take(&mut field));
+        } else if c == '\r' || c == '\n' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            record.push(std::mem::take(&mut field));
+            let line = std::mem::take(&mut record);
+            if !(line.len() == 1 && line[0].is_empty()) {
+                records.push(line);
+            }
+        } else {
+            field.push(c);
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+fn infer_value(value: &str) -> Value {
+    match value {
+        "true" | "TRUE" | "True" => return Value::Bool(true),
+        "false" | "FALSE" | "False" => return Value::Bool(false),
+        "" => return Value::Null,
+        _ => {}
+    }
+    if let Ok(n) = value.parse::<i64>() {
+        return Value::from(n);
+    }
+    if let Ok(n) = value.parse::<f64>() {
+        if n.is_finite() {
+            return Value::from(n);
+        }
+    }
+    Value::String(value.to_string())
+}
+
+fn value_to_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+/// Replace `{column}` and `{row}` in `template` with values from `row`.
+pub fn render_name_template(template: &str, rownumber: usize, row: &Map<String, Value>) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        match rest[start..].find('}') {
+            Some(end) => {
+                let key = &rest[start + 1..start + end];
+                if key == "row" {
+                    result.push_str(&rownumber.to_string());
+                } else {
+                    result.push_str(&row.get(key).map(value_to_text).unwrap_or_default());
+                }
+                rest = &rest[start + end + 1..];
+            }
+            None => {
+                result.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Read CSV text into rows keyed by the header.
+pub fn csv_to_rows(text: &str, options: &WorkitemImportOptions) -> ImportRows {
+    let mut records = parse_csv(text, options.delimiter).into_iter();
+    let header: Vec<String> = match records.next() {
+        Some(header) => header.into_iter().map(|h| h.trim().to_string()).collect(),
+        None => return (vec![], vec![]),
+    };
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (index, record) in records.enumerate() {
+        let rownumber = index + 1;
+        if record.len() != header.len() {
+            errors.push(WorkitemImportError {
+                row: rownumber,
+                message: format!("Expected {} columns, found {}", header.len(), record.len()),
+            });
+            continue;
+        }
+        let row = header
+            .iter()
+            .zip(record)
+            .map(|(key, value)| {
+                let value = if options.infer_types { infer_value(&value) } else { Value::String(value) };
+                (key.clone(), value)
+            })
+            .collect();
+        rows.push((rownumber, row));
+    }
+    (rows, errors)
+}
+
+/// Read NDJSON text into rows, one JSON object per line, empty lines are skipped.
+pub fn ndjson_to_rows(text: &str) -> ImportRows {
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let rownumber = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Value>(line) {
+            Ok(Value::Object(row)) => rows.push((rownumber, row)),
+            Ok(_) => errors.push(WorkitemImportError {
+                row: rownumber,
+                message: "Line is not a JSON object".to_string(),
+            }),
+            Err(e) => errors.push(WorkitemImportError {
+                row: rownumber,
+                message: format!("Failed to parse line: {}", e),
+            }),
+        }
+    }
+    (rows, errors)
+}
+
+/// Build the workitem for a row, using the column mapping, name template and file column from `options`.
+pub fn row_to_workitem(
+    rownumber: usize,
+    row: &Map<String, Value>,
+    options: &WorkitemImportOptions,
+) -> Result<Workitem, String> {
+    let mut payload = Map::new();
+    match &options.columns {
+        Some(columns) => {
+            for (column, key) in columns {
+                match row.get(column) {
+                    Some(value) => {
+                        payload.insert(key.clone(), value.clone());
+                    }
+                    None => return Err(format!("Missing column {}", column)),
+                }
+            }
+        }
+        None => {
+            for (key, value) in row {
+                if Some(key) != options.file_column.as_ref() {
+                    payload.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+    let mut files = vec![];
+    if let Some(column) = &options.file_column {
+        let path = row.get(column).map(value_to_text).unwrap_or_default();
+        if !path.is_empty() {
+            if !std::path::Path::new(&path).is_file() {
+                return Err(format!("File does not exist: {}", path));
+            }
+            files.push(WorkitemFile {
+                filename: path,
+                ..Default::default()
+            });
+        }
+    }
+    Ok(Workitem {
+        name: render_name_template(&options.name_template, rownumber, row),
+        payload: Value::Object(payload).to_string(),
+        files,
+        ..Default::default()
+    })
+}
+
+impl Client {
+    /// Push a workitem for each row in a CSV file, the first line is the header.
+    #[tracing::instrument(skip_all)]
+    pub async fn push_workitems_from_csv(
+        &self,
+        env: EnvConfig,
+        path: &str,
+        options: WorkitemImportOptions,
+    ) -> Result<WorkitemImportReport, OpenIAPError> {
+        let text = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to read {}: {}", path, e)))?;
+        let (rows, errors) = csv_to_rows(&text, &options);
+        self.push_rows(env, rows, errors, options).await
+    }
+    /// Push a workitem for each line in a NDJSON file.
+    #[tracing::instrument(skip_all)]
+    pub async fn push_workitems_from_ndjson(
+        &self,
+        env: EnvConfig,
+        path: &str,
+        options: WorkitemImportOptions,
+    ) -> Result<WorkitemImportReport, OpenIAPError> {
+        let text = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to read {}: {}", path, e)))?;
+        let (rows, errors) = ndjson_to_rows(&text);
+        self.push_rows(env, rows, errors, options).await
+    }
+    /// Returns the values of `payload.<key>` that already exist in `wiq`, out of `values`.
+    async fn existing_workitem_keys(
+        &self,
+        env: EnvConfig,
+        wiq: &str,
+        key: &str,
+        values: Vec<Value>,
+    ) -> Result<HashSet<String>, OpenIAPError> {
+        let field = format!("payload.{}", key);
+        let mut query = Map::new();
+        query.insert("_type".to_string(), Value::from("workitem"));
+        query.insert("wiq".to_string(), Value::from(wiq));
+        query.insert(field.clone(), serde_json::json!({ "$in": values }));
+        let request = QueryRequest {
+            collectionname: "workitems".to_string(),
+            query: Value::Object(query).to_string(),
+            projection: serde_json::json!({ field: 1 }).to_string(),
+            top: values.len() as i32,
+            ..Default::default()
+        };
+        let response = self.query(request, env).await?;
+        let documents: Vec<Value> = serde_json::from_str(&response.results)
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse workitems: {}", e)))?;
+        Ok(documents
+            .iter()
+            .map(|d| value_to_text(&d["payload"][key]))
+            .collect())
+    }
+    async fn push_rows(
+        &self,
+        env: EnvConfig,
+        rows: Vec<(usize, Map<String, Value>)>,
+        errors: Vec<WorkitemImportError>,
+        options: WorkitemImportOptions,
+    ) -> Result<WorkitemImportReport, OpenIAPError> {
+        if options.wiq.is_empty() {
+            return Err(OpenIAPError::ClientError("No queue name provided".to_string()));
+        }
+        let mut report = WorkitemImportReport {
+            rows: rows.len() + errors.len(),
+            errors,
+            ..Default::default()
+        };
+        let mut seen = HashSet::new();
+        for chunk in rows.chunks(options.chunk_size.max(1)) {
+            let mut items = Vec::new();
+            let mut rownumbers = Vec::new();
+            let mut keys = HashMap::new();
+            let mut pending = HashSet::new();
+            for (rownumber, row) in chunk {
+                let mut key = None;
+                if let Some(column) = &options.key_column {
+                    let value = row.get(column).map(value_to_text).unwrap_or_default();
+                    if value.is_empty() {
+                        report.errors.push(WorkitemImportError {
+                            row: *rownumber,
+                            message: format!("Missing value for key column {}", column),
+                        });
+                        continue;
+                    }
+                    if seen.contains(&value) || pending.contains(&value) {
+                        report.duplicates.push(*rownumber);
+                        continue;
+                    }
+                    key = Some((value, row[column].clone()));
+                }
+                match row_to_workitem(*rownumber, row, &options) {
+                    Ok(item) => {
+                        // a valid row makes later rows in this chunk with the same key duplicates
+                        if let Some(key) = key {
+                            pending.insert(key.0.clone());
+                            keys.insert(*rownumber, key);
+                        }
+                        items.push(item);
+                        rownumbers.push(*rownumber);
+                    }
+                    Err(message) => report.errors.push(WorkitemImportError { row: *rownumber, message }),
+                }
+            }
+            if let Some(key) = &options.key_column {
+                // the payload key the column is stored under
+                let payloadkey = match &options.columns {
+                    Some(columns) => columns.iter().find(|(c, _)| c == key).map(|(_, k)| k.clone()),
+                    None => Some(key.clone()),
+                };
+                if let Some(payloadkey) = payloadkey {
+                    let values = rownumbers.iter().filter_map(|r| keys.get(r).map(|k| k.1.clone())).collect();
+                    let existing = self
+                        .existing_workitem_keys(env.clone(), &options.wiq, &payloadkey, values)
+                        .await?;
+                    let mut index = 0;
+                    while index < items.len() {
+                        if existing.contains(&keys[&rownumbers[index]].0) {
+                            report.duplicates.push(rownumbers.remove(index));
+                            items.remove(index);
+                        } else {
+                            index += 1;
+                        }
+                    }
+                }
+            }
+            if items.is_empty() {
+                continue;
+            }
+            let request = PushWorkitemsRequest {
+                wiq: options.wiq.clone(),
+                items,
+                ..Default::default()
+            };
+            match self.push_workitems(request, env.clone()).await {
+                Ok(response) => {
+                    debug!("Pushed {} workitems to {}", response.workitems.len(), options.wiq);
+                    // only keys that were pushed make rows in later chunks duplicates, a failed chunk can be retried
+                    seen.extend(rownumbers.iter().filter_map(|r| keys.remove(r).map(|k| k.0)));
+                    report.created.extend(response.workitems.into_iter().map(|w| w.id));
+                }
+                Err(e) => {
+                    for rownumber in rownumbers {
+                        report.errors.push(WorkitemImportError {
+                            row: rownumber,
+                            message: e.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        report.errors.sort_by_key(|e| e.row);
+        report.duplicates.sort();
+        info!(
+            "Imported {} of {} rows into {}, {} duplicates, {} errors",
+            report.created.len(),
+            report.rows,
+            options.wiq,
+            report.duplicates.len(),
+            report.errors.len()
+        );
+        Ok(report)
+    }
+}
@@ -0,0 +1,276 @@
+use openiap_proto::errors::OpenIAPError;
+use openiap_proto::openiap::{CreateCollectionRequest, InsertManyRequest};
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::{Client, EnvConfig};
+
+/// Options for `Client::collection_log_layer`.
+#[derive(Debug, Clone)]
+pub struct CollectionLogOptions {
+    /// Collection log events are written to.
+    pub collectionname: String,
+    /// Remove log events after this long, only used when the collection is created.
+    pub ttl: Option<Duration>,
+    /// Most verbose level written.
+    pub level: Level,
+    /// Write a batch once it has this many events.
+    pub batch_size: usize,
+    /// Write a batch at least this often.
+    pub flush_interval: Duration,
+    /// Events accepted per second, events above the limit are dropped and counted.
+    pub max_per_second: u64,
+    /// Events waiting to be written, events are dropped while the buffer is full.
+    pub max_buffer: usize,
+}
+impl Default for CollectionLogOptions {
+    fn default() -> Self {
+        Self {
+            collectionname: "logs".to_string(),
+            ttl: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+            level: Level::INFO,
+            batch_size: 100,
+            flush_interval: Duration::from_secs(2),
+            max_per_second: 100,
+            max_buffer: 10000,
+        }
+    }
+}
+impl CollectionLogOptions {
+    /// Write log events to `collectionname`.
+    pub fn new(collectionname: &str) -> Self {
+        Self {
+            collectionname: collectionname.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Collects the fields of an event as json.
+#[derive(Default)]
+struct JsonVisitor {
+    message: String,
+    fields: Map<String, Value>,
+}
+impl Visit for JsonVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields.insert(field.name().to_string(), value.into());
+        }
+    }
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+/// Counts events per second, and events dropped by the limit or a full buffer.
+pub(crate) struct RateLimiter {
+    window: Mutex<(Instant, u64)>,
+    max_per_second: u64,
+    pub(crate) dropped: AtomicU64,
+}
+impl RateLimiter {
+    fn new(max_per_second: u64) -> Self {
+        Self {
+            window: Mutex::new((Instant::now(), 0)),
+            max_per_second,
+            dropped: AtomicU64::new(0),
+        }
+    }
+    /// Returns true if another event may be written this second.
+    fn allow(&self) -> bool {
+        let mut window = self.window.lock().unwrap();
+        if window.0.elapsed() >= Duration::from_secs(1) {
+            *window = (Instant::now(), 0);
+        }
+        if window.1 >= self.max_per_second {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        window.1 += 1;
+        true
+    }
+}
+
+tokio::task_local! {
+    /// Set while the writer task runs, events it causes are not written to the collection.
+    pub(crate) static FLUSHING: bool;
+}
+
+/// True when an event from the client itself should be skipped.
+/// Writing a batch makes the client log, so only warnings and errors from the client are kept, to avoid a feedback loop.
+pub fn is_client_event(target: &str, level: &Level) -> bool {
+    let client = target == "openiap_client" || target.starts_with("openiap_client::") || target.starts_with("otel::");
+    client && *level > Level::WARN
+}
+
+/// A tracing `Layer` writing log events to a collection, created with `Client::collection_log_layer`.
+/// Dropping the layer, for instance when the subscriber is dropped, writes what is left and stops the writer.
+pub struct CollectionLogLayer {
+    sender: mpsc::Sender<Value>,
+    limiter: Arc<RateLimiter>,
+    level: Level,
+    agent: String,
+    agentversion: String,
+}
+impl CollectionLogLayer {
+    /// Create the layer, and the receiver and limiter used by the writer.
+    pub(crate) fn new(options: &CollectionLogOptions, agent: &str, agentversion: &str) -> (Self, mpsc::Receiver<Value>, Arc<RateLimiter>) {
+        let (sender, receiver) = mpsc::channel(options.max_buffer.max(1));
+        let limiter = Arc::new(RateLimiter::new(options.max_per_second));
+        let layer = Self {
+            sender,
+            limiter: limiter.clone(),
+            level: options.level,
+            agent: agent.to_string(),
+            agentversion: agentversion.to_string(),
+        };
+        (layer, receiver, limiter)
+    }
+    /// Turn an event into the document written to the collection.
+    fn to_document<S>(&self, event: &Event<'_>, ctx: &Context<'_, S>) -> Value
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    {
+        let metadata = event.metadata();
+        let mut visitor = JsonVisitor::default();
+        event.record(&mut visitor);
+        let spans: Vec<Value> = ctx
+            .event_scope(event)
+            .map(|scope| scope.from_root().map(|span| Value::from(span.name())).collect())
+            .unwrap_or_default();
+        let mut document = serde_json::json!({
+            "_type": "log",
+            "name": visitor.message,
+            "level": metadata.level().as_str(),
+            "target": metadata.target(),
+            "fields": visitor.fields,
+            "spans": spans,
+            "agent": self.agent,
+            "agentversion": self.agentversion,
+            "timestamp": crate::util::format_iso8601(SystemTime::now()),
+        });
+        if let Some((traceid, spanid)) = crate::current_trace_ids() {
+            document["traceid"] = traceid.into();
+            document["spanid"] = spanid.into();
+        }
+        document
+    }
+}
+impl<S> Layer<S> for CollectionLogLayer
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if FLUSHING.try_with(|flushing| *flushing).unwrap_or_default() {
+            return;
+        }
+        let metadata = event.metadata();
+        if *metadata.level() > self.level || is_client_event(metadata.target(), metadata.level()) {
+            return;
+        }
+        if !self.limiter.allow() {
+            return;
+        }
+        let document = self.to_document(event, &ctx);
+        if self.sender.try_send(document).is_err() {
+            self.limiter.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Client {
+    /// Create a tracing `Layer` that writes log events to a collection, add it to your subscriber to use it.
+    /// The collection is created with `options.ttl` if it does not exist.
+    /// Events are written in batches with `insert_many`, and anything the client logs while writing them is ignored.
+    #[tracing::instrument(skip_all)]
+    pub async fn collection_log_layer(
+        &self,
+        options: CollectionLogOptions,
+    ) -> Result<(CollectionLogLayer, JoinHandle<()>), OpenIAPError> {
+        self.ensure_log_collection(&options).await?;
+        let (layer, receiver, limiter) = CollectionLogLayer::new(&options, &self.get_agent_name(), &self.get_agent_version());
+        let client = self.clone();
+        // events from the writer still reach the other layers, but are not written to the collection
+        let handle = tokio::task::spawn(
+            FLUSHING.scope(true, async move { client.write_log_batches(receiver, limiter, options).await }),
+        );
+        Ok((layer, handle))
+    }
+    async fn ensure_log_collection(&self, options: &CollectionLogOptions) -> Result<(), OpenIAPError> {
+        let collections = self.list_collections(false, EnvConfig::new()).await?;
+        let collections: Vec<Value> = serde_json::from_str(&collections)
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse collections: {}", e)))?;
+        if collections.iter().any(|c| c["name"].as_str() == Some(options.collectionname.as_str())) {
+            return Ok(());
+        }
+        let request = match options.ttl {
+            Some(ttl) => CreateCollectionRequest::with_ttl(&options.collectionname, ttl.as_secs().min(i32::MAX as u64) as i32),
+            None => CreateCollectionRequest::byname(&options.collectionname),
+        };
+        self.create_collection(request, EnvConfig::new()).await
+    }
+    async fn write_log_batches(&self, mut receiver: mpsc::Receiver<Value>, limiter: Arc<RateLimiter>, options: CollectionLogOptions) {
+        let mut closed = false;
+        while !closed {
+            let mut batch = Vec::new();
+            let deadline = tokio::time::Instant::now() + options.flush_interval;
+            while batch.len() < options.batch_size {
+                match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                    Ok(Some(document)) => batch.push(document),
+                    Ok(None) => {
+                        closed = true;
+                        break;
+                    }
+                    Err(_) => break,
+                }
+            }
+            let dropped = limiter.dropped.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                batch.push(serde_json::json!({
+                    "_type": "log",
+                    "name": format!("Dropped {} log events", dropped),
+                    "level": "WARN",
+                    "target": "openiap_client::log_sink",
+                    "timestamp": crate::util::format_iso8601(SystemTime::now()),
+                }));
+            }
+            if batch.is_empty() {
+                continue;
+            }
+            let count = batch.len() as u64;
+            let request = InsertManyRequest {
+                collectionname: options.collectionname.clone(),
+                items: Value::Array(batch).to_string(),
+                skipresults: true,
+                ..Default::default()
+            };
+            if self.insert_many(request, EnvConfig::new()).await.is_err() {
+                // counted, and reported with the next batch that gets written
+                limiter.dropped.fetch_add(count, Ordering::Relaxed);
+            }
+        }
+    }
+}
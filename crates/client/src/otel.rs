@@ -1,7 +1,7 @@
 // https://github.com/open-telemetry/opentelemetry-rust/blob/fcd7cae39b6730e5f5a907f29e9b0af3ff34d5ce/opentelemetry/CHANGELOG.md?plain=1#L101
 use crate::{otel, ClientStatistics};
 use openiap_proto::errors::OpenIAPError;
-use opentelemetry::metrics::Meter;
+use opentelemetry::metrics::{AsyncInstrument, Meter};
 use opentelemetry::Key;
 use opentelemetry_sdk::metrics::SdkMeterProvider;
 use tracing::{debug, error, info};
@@ -30,12 +30,24 @@ const CLIENT_COMMANDS : &str = "client.commands";
 const CLIENT_CONNECTIONS : &str = "client.connections";
 #[cfg(feature = "otel_commands")]
 const CLIENT_CONNECTION_ATTEMPTS : &str = "client.connection_attempts";
+#[cfg(feature = "otel_commands")]
+const CLIENT_COMMAND_ERRORS : &str = "client.command.errors";
+#[cfg(feature = "otel_commands")]
+const CLIENT_COMMAND_BYTES_SENT : &str = "client.command.bytes_sent";
+#[cfg(feature = "otel_commands")]
+const CLIENT_COMMAND_BYTES_RECEIVED : &str = "client.command.bytes_received";
+#[cfg(feature = "otel_elapsed")]
+const CLIENT_COMMAND_DURATION : &str = "client.command.duration";
 #[cfg(feature = "otel_package_stats")]
 const CLIENT_PACKAGE_TX : &str = "client.package_tx";
 #[cfg(feature = "otel_package_stats")]
 const CLIENT_PACKAGE_RX : &str = "client.package_rx";
 #[allow(dead_code)]
 const COMMAND: Key = Key::from_static_str("command");
+#[cfg(feature = "otel_commands")]
+const ERROR_KIND: Key = Key::from_static_str("error.kind");
+#[cfg(feature = "otel_elapsed")]
+const STATUS: Key = Key::from_static_str("status");
 #[cfg(feature = "otel_network")]
 const DIRECTION: Key = Key::from_static_str("direction");
 const HOSTNAME: Key = Key::from_static_str("hostname");
@@ -275,6 +287,79 @@ pub fn register_metrics(meter: Meter, ofid: &str, stats: &Arc<std::sync::Mutex<C
         })
         .build();
 
+    #[cfg(feature = "otel_elapsed")]
+    {
+        let histogram = meter
+            .f64_histogram(CLIENT_COMMAND_DURATION)
+            .with_description("Round trip time of each command in milliseconds.")
+            .with_unit("ms")
+            .with_boundaries(vec![1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0, 30000.0])
+            .build();
+        let common_attributes = vec![
+            KeyValue::new(HOSTNAME, hostname::get().unwrap_or_default().into_string().unwrap()),
+            KeyValue::new(OFID, ofid.to_string()),
+            KeyValue::new("PID", std::process::id().to_string()),
+        ];
+        COMMAND_HISTOGRAMS.lock().unwrap().push((histogram, common_attributes));
+    }
+    #[cfg(feature = "otel_commands")]
+    let common_attributes = [
+        KeyValue::new(HOSTNAME, hostname::get().unwrap_or_default().into_string().unwrap()),
+        KeyValue::new(OFID, ofid.to_string()),
+        KeyValue::new("PID", std::process::id().to_string()),
+    ];
+    #[cfg(feature = "otel_commands")]
+    meter
+        .u64_observable_counter(CLIENT_COMMAND_ERRORS)
+        .with_description("Client Command Errors")
+        .with_callback({
+            let stats = Arc::clone(stats);
+            let common_attributes = common_attributes.clone();
+            move |counter| {
+                let stats = stats.lock().unwrap();
+                for (command, command_stats) in &stats.commands {
+                    for (kind, count) in &command_stats.errors {
+                        counter.observe(*count,
+                            &[common_attributes.as_slice(), &[KeyValue::new(COMMAND, command.clone()), KeyValue::new(ERROR_KIND, kind.clone())]].concat());
+                    }
+                }
+            }
+        })
+        .build();
+    #[cfg(feature = "otel_commands")]
+    meter
+        .u64_observable_counter(CLIENT_COMMAND_BYTES_SENT)
+        .with_description("Client Command Bytes Sent")
+        .with_unit("By")
+        .with_callback({
+            let stats = Arc::clone(stats);
+            let common_attributes = common_attributes.clone();
+            move |counter| {
+                let stats = stats.lock().unwrap();
+                for (command, command_stats) in &stats.commands {
+                    counter.observe(command_stats.bytes_sent,
+                        &[common_attributes.as_slice(), &[KeyValue::new(COMMAND, command.clone())]].concat());
+                }
+            }
+        })
+        .build();
+    #[cfg(feature = "otel_commands")]
+    meter
+        .u64_observable_counter(CLIENT_COMMAND_BYTES_RECEIVED)
+        .with_description("Client Command Bytes Received")
+        .with_unit("By")
+        .with_callback({
+            let stats = Arc::clone(stats);
+            let common_attributes = common_attributes.clone();
+            move |counter| {
+                let stats = stats.lock().unwrap();
+                for (command, command_stats) in &stats.commands {
+                    counter.observe(command_stats.bytes_received,
+                        &[common_attributes.as_slice(), &[KeyValue::new(COMMAND, command.clone())]].concat());
+                }
+            }
+        })
+        .build();
     #[cfg(feature = "otel_commands")]
     let common_attributes = [
         KeyValue::new(HOSTNAME, hostname::get().unwrap_or_default().into_string().unwrap()),
@@ -456,6 +541,9 @@ use opentelemetry_otlp::{WithExportConfig, WithTonicConfig};
 use opentelemetry_sdk::Resource;
 use std::time::SystemTime;
 use opentelemetry_otlp::MetricExporter;
+use opentelemetry_sdk::metrics::PeriodicReader;
+use crate::spool::{headers_to_metadata, SpoolingMetricExporter, TelemetrySpool};
+use crate::TelemetryConfig;
 use opentelemetry::metrics::MeterProvider;
 use tracing_subscriber::EnvFilter;
 #[allow(dead_code)]
@@ -478,6 +566,30 @@ lazy_static! {
     });
 }
 
+/// Command duration histograms, one for each meter provider, with the attributes to record them with.
+#[cfg(feature = "otel_elapsed")]
+#[allow(clippy::type_complexity)]
+static COMMAND_HISTOGRAMS: Lazy<std::sync::Mutex<Vec<(opentelemetry::metrics::Histogram<f64>, Vec<KeyValue>)>>> = Lazy::new(|| {
+    std::sync::Mutex::new(Vec::new())
+});
+/// Record the round trip time of a command in the command duration histograms.
+#[cfg(feature = "otel_elapsed")]
+pub(crate) fn record_command_duration(command: &str, elapsed_ms: f64, success: bool) {
+    let histograms = COMMAND_HISTOGRAMS.lock().unwrap();
+    for (histogram, common_attributes) in histograms.iter() {
+        let status = if success { "ok" } else { "error" };
+        histogram.record(elapsed_ms,
+            &[common_attributes.as_slice(), &[KeyValue::new(COMMAND, command.to_string()), KeyValue::new(STATUS, status)]].concat());
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum GaugeKind {
+    F64,
+    U64,
+    I64,
+}
+
 #[derive(Clone)]
 struct MetricValue {
     f64value: f64,
@@ -485,6 +597,20 @@ struct MetricValue {
     i64value: i64,
     description: String,
     enabled: bool,
+    kind: GaugeKind,
+    /// Registered on the meter provider, gauges set before `init_telemetry` are registered later
+    registered: bool,
+}
+
+impl MetricValue {
+    /// The value the gauge was last set to, as f64 whatever type it was set as.
+    fn value(&self) -> f64 {
+        match self.kind {
+            GaugeKind::F64 => self.f64value,
+            GaugeKind::U64 => self.u64value as f64,
+            GaugeKind::I64 => self.i64value as f64,
+        }
+    }
 }
 
 static METRIC_VALUES: Lazy<std::sync::Mutex<HashMap<String, MetricValue>>> = Lazy::new(|| {
@@ -503,14 +629,37 @@ static METRICS_CONTEXT: Lazy<std::sync::Mutex<Option<MetricsContext>>> = Lazy::n
     std::sync::Mutex::new(None)
 });
 
+/// Build a meter provider exporting to `endpoint`, spooling to `spool` while the endpoint is unreachable.
+fn build_meter_provider(endpoint: &str, telemetry: &TelemetryConfig, headers: bool, spool: Option<TelemetrySpool>,
+    resource: &Resource) -> Result<SdkMeterProvider, String> {
+    let metadata = if headers { headers_to_metadata(&telemetry.headers)? } else { tonic::metadata::MetadataMap::new() };
+    let exporter = MetricExporter::builder()
+        .with_tonic()
+        .with_tls_config(tonic::transport::ClientTlsConfig::new().with_native_roots())
+        .with_endpoint(endpoint)
+        .with_metadata(metadata.clone())
+        .build()
+        .map_err(|e| format!("Failed to create metric exporter for {}: {}", endpoint, e))?;
+    let exporter = SpoolingMetricExporter::new(exporter, spool, endpoint, metadata)?;
+    let reader = PeriodicReader::builder(exporter)
+        .with_interval(telemetry.metric_interval)
+        .build();
+    Ok(SdkMeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(resource.clone())
+        .build())
+}
+
 /// Initialize telemetry
+/// Failing exporters are skipped, the others are still initialized, and the errors are returned together.
 #[allow(unused_variables)]
 #[tracing::instrument(skip_all, target = "otel::init_telemetry")]
-pub fn init_telemetry(service_name: &str, agent_name: &str, agent_version: &str, version: &str, apihostname: &str, 
-    metric_url: &str, trace_url: &str, log_url: &str, 
+pub fn init_telemetry(service_name: &str, agent_name: &str, agent_version: &str, version: &str, apihostname: &str,
+    telemetry: &TelemetryConfig,
     stats: &Arc<std::sync::Mutex<ClientStatistics>>) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let enable_analytics = std::env::var("enable_analytics").unwrap_or("".to_string());
-    let enable_analytics: bool = !enable_analytics.eq_ignore_ascii_case("false");
+    let metric_url = telemetry.metric_url.clone().unwrap_or_default();
+    let trace_url = telemetry.trace_url.clone().unwrap_or_default();
+    let log_url = telemetry.log_url.clone().unwrap_or_default();
     let resource = Resource::builder().with_service_name(service_name.to_string())
         .with_attribute(KeyValue::new("service.version", version.to_string() ))
         .with_attribute(KeyValue::new("agent.name", agent_name.to_string() ))
@@ -526,11 +675,6 @@ pub fn init_telemetry(service_name: &str, agent_name: &str, agent_version: &str,
     }
     let ofid = format!("{:x}", hasher.compute());
 
-    // if OTEL_METRIC_EXPORT_INTERVAL is not set, set it to 10000 ( instad of 60000 )
-    if std::env::var("OTEL_METRIC_EXPORT_INTERVAL").is_err() {
-        std::env::set_var("OTEL_METRIC_EXPORT_INTERVAL", "10000");
-    }
-    
     // Store the context
     *METRICS_CONTEXT.lock().unwrap() = Some(MetricsContext {
         version: version.to_string(),
@@ -539,275 +683,226 @@ pub fn init_telemetry(service_name: &str, agent_name: &str, agent_version: &str,
         ofid: ofid.clone(),
     });
 
-    if enable_analytics {
+    let mut errors: Vec<String> = Vec::new();
+    let spool = match &telemetry.spool_dir {
+        Some(dir) => match TelemetrySpool::new(dir, telemetry.spool_max_bytes) {
+            Ok(spool) => Some(spool),
+            Err(e) => {
+                errors.push(format!("Failed to create telemetry spool {}: {}", dir.display(), e));
+                None
+            }
+        },
+        None => None,
+    };
+
+    if telemetry.analytics && !telemetry.analytics_url.is_empty() {
         debug!("Initializing generic telemetry");
         let mut providers1 = provider1.lock().unwrap();
         if providers1.provider.is_none() {
-            let exporter1 = MetricExporter::builder()
-                .with_tonic()
-                .with_tls_config(tonic::transport::ClientTlsConfig::new().with_native_roots())
-                .with_endpoint("https://otel.stats.openiap.io:443")
-                .build()
-                .expect("Failed to create metric exporter");
-            let provider = SdkMeterProvider::builder()
-                .with_periodic_exporter(exporter1)
-                .with_resource(resource.clone())
-                .build();
-            let meter1 = provider.meter("process-meter1");
-            // let meter: opentelemetry::metrics::Meter = meterprovider1.meter("process-meter1");
-            // when not using global::set_meter_provider we need to keep it alive using ProivderWrapper
-            match otel::register_metrics(meter1, &ofid, stats) {
-                Ok(_) => (),
-                Err(e) => {
-                    debug!("Failed to initialize process observer: {}", e);
+            // analytics is not spooled, and does not get the headers meant for our own collector
+            match build_meter_provider(&telemetry.analytics_url, telemetry, false, None, &resource) {
+                Ok(provider) => {
+                    let meter1 = provider.meter("process-meter1");
+                    // let meter: opentelemetry::metrics::Meter = meterprovider1.meter("process-meter1");
+                    // when not using global::set_meter_provider we need to keep it alive using ProivderWrapper
+                    match otel::register_metrics(meter1, &ofid, stats) {
+                        Ok(_) => (),
+                        Err(e) => {
+                            debug!("Failed to initialize process observer: {}", e);
+                        }
+                    }
+                    providers1.provider = Some(provider);
                 }
+                Err(e) => errors.push(e),
             }
-            providers1.provider = Some(provider);
         }
     }
 
-    if !log_url.is_empty() {
+    // logs and traces are exported by the tracing layers, either can be set without the other
+    if !log_url.is_empty() || !trace_url.is_empty() {
+        crate::util::set_otel_log_telemetry(telemetry);
         #[cfg(not(test))]
         {   
-            crate::set_otel_url(log_url, trace_url, &ofid, version, service_name, agent_name, agent_version);
+            crate::set_otel_url(&log_url, &trace_url, &ofid, version, service_name, agent_name, agent_version);
         }
-        debug!("added {} for logging observability", log_url);
+        debug!("added {} {} for logging and tracing observability", log_url, trace_url);
     }
     if !metric_url.is_empty() {
         let mut providers2 = provider2.lock().unwrap();
         if providers2.provider.is_none() {
-            let exporter2 = MetricExporter::builder()
-                .with_tonic()
-                .with_tls_config(tonic::transport::ClientTlsConfig::new().with_native_roots())
-                .with_endpoint(metric_url)
-                .build()
-                .expect("Failed to create metric exporter");
-            let provider = SdkMeterProvider::builder()
-                .with_periodic_exporter(exporter2)
-                .with_resource(resource.clone())
-                .build();
-
-            let meter2 = provider.meter("process-meter2");
-            // when not using global::set_meter_provider we need to keep it alive using ProivderWrapper
-            match otel::register_metrics(meter2, &ofid, stats) {
-                Ok(_) => (),
-                Err(e) => {
-                    error!("Failed to initialize process observer: {}", e);
+            match build_meter_provider(&metric_url, telemetry, true, spool, &resource) {
+                Ok(provider) => {
+                    let meter2 = provider.meter("process-meter2");
+                    // when not using global::set_meter_provider we need to keep it alive using ProivderWrapper
+                    match otel::register_metrics(meter2, &ofid, stats) {
+                        Ok(_) => (),
+                        Err(e) => {
+                            error!("Failed to initialize process observer: {}", e);
+                        }
+                    }
+                    providers2.provider = Some(provider);
+                    debug!("added {} for performance observability", metric_url);
                 }
+                Err(e) => errors.push(e),
             }
-            providers2.provider = Some(provider);
-            debug!("added {} for performance observability", metric_url);
         }
     }
 
-    Ok(())
-}
-/// Create/Update an onservable gauge metric that can be updated dynamically.
-/// this means the value will be stored and send doing each metric update.
-pub fn set_f64_observable_gauge(name: &str, value: f64, description: &str) -> Result<(), String> {
-    let providers2 = provider2.lock().unwrap();
-    if providers2.provider.is_none() {
-        return Err("Provider not initialized".to_string());
-    }
-    
-    let name_owned = name.to_string();
-    
-    // Check if metric already exists and update if it does
-    let mut metrics = METRIC_VALUES.lock().unwrap();
-    if let Some(metric) = metrics.get_mut(&name_owned) {
-        metric.f64value = value;
-        metric.enabled = true;
-        return Ok(());
+    // gauges set before the provider existed were only stored, register them now
+    let pending: Vec<(String, MetricValue)> = METRIC_VALUES.lock().unwrap()
+        .iter()
+        .filter(|(_, metric)| !metric.registered)
+        .map(|(name, metric)| (name.clone(), metric.clone()))
+        .collect();
+    for (name, metric) in pending {
+        let result = match metric.kind {
+            GaugeKind::F64 => set_f64_observable_gauge(&name, metric.f64value, &metric.description),
+            GaugeKind::U64 => set_u64_observable_gauge(&name, metric.u64value, &metric.description),
+            GaugeKind::I64 => set_i64_observable_gauge(&name, metric.i64value, &metric.description),
+        };
+        if let Err(e) = result {
+            debug!("Failed to register gauge {}: {}", name, e);
+        }
+        if !metric.enabled {
+            disable_observable_gauge(&name);
+        }
     }
-    
-    // Store metric info in our static map for new metrics
-    metrics.insert(name_owned.clone(), MetricValue {
-        f64value: value,
-        u64value: 0,
-        i64value: 0,
-        description: description.to_string(),
-        enabled: true,
-    });
-    
-    if let Some(provider) = &providers2.provider {
-        let meter = provider.meter("custommeter");
-        let name_for_callback = name_owned.clone();
-        
-        // Get the metrics context
-        let context = METRICS_CONTEXT.lock().unwrap().clone();
-        
-        meter
-            .f64_observable_gauge(name_owned)
-            .with_description(description.to_string())
-            .with_callback(move |gauge| {
-                let enabled = METRIC_VALUES.lock().unwrap().get(&name_for_callback).map(|m| m.enabled).unwrap_or_default();
-                if !enabled {
-                    return;
-                }                
-                if let Some(metric) = METRIC_VALUES.lock().unwrap().get(&name_for_callback) {
-                    let mut attributes = vec![
-                        KeyValue::new(HOSTNAME, hostname::get().unwrap_or_default().into_string().unwrap()),
-                        KeyValue::new("metric_name", name_for_callback.clone()),
-                        KeyValue::new("PID", std::process::id().to_string()),
-                    ];
-                    
-                    // Add context attributes if available
-                    if let Some(ctx) = &context {
-                        attributes.extend_from_slice(&[
-                            KeyValue::new("service.version", ctx.version.clone()),
-                            KeyValue::new("agent.name", ctx.agent_name.clone()),
-                            KeyValue::new("agent.version", ctx.agent_version.clone()),
-                            KeyValue::new(OFID, ctx.ofid.clone()),
-                        ]);
-                    }
-                    
-                    gauge.observe(metric.f64value, &attributes);
-                }
-            })
-            .build();
-        
+
+    if errors.is_empty() {
         Ok(())
     } else {
-        Err("Provider is None".to_string())
+        Err(Box::new(OpenIAPError::ClientError(errors.join(", "))))
     }
 }
-/// Create/Update an onservable gauge metric that can be updated dynamically.
-/// this means the value will be stored and send doing each metric update.
-pub fn set_u64_observable_gauge(name: &str, value: u64, description: &str) -> Result<(), String> {
-    let providers2 = provider2.lock().unwrap();
-    if providers2.provider.is_none() {
-        return Err("Provider not initialized".to_string());
+/// A value type an observable gauge can be created for.
+trait GaugeValue: Copy + Send + Sync + 'static {
+    const KIND: GaugeKind;
+    fn store(self, metric: &mut MetricValue);
+    fn load(metric: &MetricValue) -> Self;
+    fn register(meter: &Meter, name: String, description: String, callback: GaugeCallback<Self>);
+}
+type GaugeCallback<T> = Box<dyn Fn(&dyn AsyncInstrument<T>) + Send + Sync>;
+impl GaugeValue for f64 {
+    const KIND: GaugeKind = GaugeKind::F64;
+    fn store(self, metric: &mut MetricValue) {
+        metric.f64value = self;
     }
-    
-    let name_owned = name.to_string();
-    
-    // Check if metric already exists and update if it does
-    let mut metrics = METRIC_VALUES.lock().unwrap();
-    if let Some(metric) = metrics.get_mut(&name_owned) {
-        metric.u64value = value;
-        metric.enabled = true;
-        return Ok(());
+    fn load(metric: &MetricValue) -> Self {
+        metric.f64value
     }
-    
-    // Store metric info in our static map for new metrics
-    metrics.insert(name_owned.clone(), MetricValue {
-        f64value: 0.0,
-        u64value: value,
-        i64value: 0,
-        description: description.to_string(),
-        enabled: true,
-    });
-    
-    if let Some(provider) = &providers2.provider {
-        let meter = provider.meter("custommeter");
-        let name_for_callback = name_owned.clone();
-        
-        // Get the metrics context
-        let context = METRICS_CONTEXT.lock().unwrap().clone();
-        
-        meter
-            .u64_observable_gauge(name_owned)
-            .with_description(description.to_string())
-            .with_callback(move |gauge| {
-                let enabled = METRIC_VALUES.lock().unwrap().get(&name_for_callback).map(|m| m.enabled).unwrap_or_default();
-                if !enabled {
-                    return;
-                }                
-                if let Some(metric) = METRIC_VALUES.lock().unwrap().get(&name_for_callback) {
-                    let mut attributes = vec![
-                        KeyValue::new(HOSTNAME, hostname::get().unwrap_or_default().into_string().unwrap()),
-                        KeyValue::new("metric_name", name_for_callback.clone()),
-                        KeyValue::new("PID", std::process::id().to_string()),
-                    ];
-                    
-                    // Add context attributes if available
-                    if let Some(ctx) = &context {
-                        attributes.extend_from_slice(&[
-                            KeyValue::new("service.version", ctx.version.clone()),
-                            KeyValue::new("agent.name", ctx.agent_name.clone()),
-                            KeyValue::new("agent.version", ctx.agent_version.clone()),
-                            KeyValue::new(OFID, ctx.ofid.clone()),
-                        ]);
-                    }
-                    
-                    gauge.observe(metric.u64value, &attributes);
-                }
-            })
-            .build();
-        
-        Ok(())
-    } else {
-        Err("Provider is None".to_string())
+    fn register(meter: &Meter, name: String, description: String, callback: GaugeCallback<Self>) {
+        meter.f64_observable_gauge(name).with_description(description).with_callback(callback).build();
     }
 }
-/// Create/Update an onservable gauge metric that can be updated dynamically.
-/// this means the value will be stored and send doing each metric update.
-pub fn set_i64_observable_gauge(name: &str, value: i64, description: &str) -> Result<(), String> {
-    let providers2 = provider2.lock().unwrap();
-    if providers2.provider.is_none() {
-        return Err("Provider not initialized".to_string());
+impl GaugeValue for u64 {
+    const KIND: GaugeKind = GaugeKind::U64;
+    fn store(self, metric: &mut MetricValue) {
+        metric.u64value = self;
     }
-    
+    fn load(metric: &MetricValue) -> Self {
+        metric.u64value
+    }
+    fn register(meter: &Meter, name: String, description: String, callback: GaugeCallback<Self>) {
+        meter.u64_observable_gauge(name).with_description(description).with_callback(callback).build();
+    }
+}
+impl GaugeValue for i64 {
+    const KIND: GaugeKind = GaugeKind::I64;
+    fn store(self, metric: &mut MetricValue) {
+        metric.i64value = self;
+    }
+    fn load(metric: &MetricValue) -> Self {
+        metric.i64value
+    }
+    fn register(meter: &Meter, name: String, description: String, callback: GaugeCallback<Self>) {
+        meter.i64_observable_gauge(name).with_description(description).with_callback(callback).build();
+    }
+}
+/// Store the value of a gauge, and register it on the meter provider the first time it is set after the provider exists.
+/// The value is always stored, a gauge set before `init_telemetry` is registered once the provider exists.
+fn set_observable_gauge<T: GaugeValue>(name: &str, value: T, description: &str) -> Result<(), String> {
+    let providers2 = provider2.lock().unwrap();
     let name_owned = name.to_string();
     
     // Check if metric already exists and update if it does
     let mut metrics = METRIC_VALUES.lock().unwrap();
     if let Some(metric) = metrics.get_mut(&name_owned) {
-        metric.i64value = value;
+        value.store(metric);
         metric.enabled = true;
-        return Ok(());
+        if metric.registered {
+            return Ok(());
+        }
+        // set before there was a provider, register it now if there is one
+    } else {
+        // Store metric info in our static map for new metrics
+        let mut metric = MetricValue {
+            f64value: 0.0,
+            u64value: 0,
+            i64value: 0,
+            description: description.to_string(),
+            enabled: true,
+            kind: T::KIND,
+            registered: false,
+        };
+        value.store(&mut metric);
+        metrics.insert(name_owned.clone(), metric);
     }
     
-    // Store metric info in our static map for new metrics
-    metrics.insert(name_owned.clone(), MetricValue {
-        f64value: 0.0,
-        u64value: 0,
-        i64value: value,
-        description: description.to_string(),
-        enabled: true,
-    });
+    let Some(provider) = &providers2.provider else {
+        // init_telemetry registers it when the provider is created
+        return Ok(());
+    };
+    let meter = provider.meter("custommeter");
+    let name_for_callback = name_owned.clone();
     
-    if let Some(provider) = &providers2.provider {
-        let meter = provider.meter("custommeter");
-        let name_for_callback = name_owned.clone();
-        
-        // Get the metrics context
-        let context = METRICS_CONTEXT.lock().unwrap().clone();
-        
-        meter
-            .i64_observable_gauge(name_owned)
-            .with_description(description.to_string())
-            .with_callback(move |gauge| {
-                let enabled = METRIC_VALUES.lock().unwrap().get(&name_for_callback).map(|m| m.enabled).unwrap_or_default();
-                if !enabled {
-                    return;
-                }                
-                if let Some(metric) = METRIC_VALUES.lock().unwrap().get(&name_for_callback) {
-                    let mut attributes = vec![
-                        KeyValue::new(HOSTNAME, hostname::get().unwrap_or_default().into_string().unwrap()),
-                        KeyValue::new("metric_name", name_for_callback.clone()),
-                        KeyValue::new("PID", std::process::id().to_string()),
-                    ];
-                    
-                    // Add context attributes if available
-                    if let Some(ctx) = &context {
-                        attributes.extend_from_slice(&[
-                            KeyValue::new("service.version", ctx.version.clone()),
-                            KeyValue::new("agent.name", ctx.agent_name.clone()),
-                            KeyValue::new("agent.version", ctx.agent_version.clone()),
-                            KeyValue::new(OFID, ctx.ofid.clone()),
-                        ]);
-                    }
-                    
-                    gauge.observe(metric.i64value, &attributes);
-                }
-            })
-            .build();
-        
-        Ok(())
-    } else {
-        Err("Provider is None".to_string())
+    // Get the metrics context
+    let context = METRICS_CONTEXT.lock().unwrap().clone();
+    
+    let callback = move |gauge: &dyn AsyncInstrument<T>| {
+        let enabled = METRIC_VALUES.lock().unwrap().get(&name_for_callback).map(|m| m.enabled).unwrap_or_default();
+        if !enabled {
+            return;
+        }
+        if let Some(metric) = METRIC_VALUES.lock().unwrap().get(&name_for_callback) {
+            let mut attributes = vec![
+                KeyValue::new(HOSTNAME, hostname::get().unwrap_or_default().into_string().unwrap()),
+                KeyValue::new("metric_name", name_for_callback.clone()),
+                KeyValue::new("PID", std::process::id().to_string()),
+            ];
+            
+            // Add context attributes if available
+            if let Some(ctx) = &context {
+                attributes.extend_from_slice(&[
+                    KeyValue::new("service.version", ctx.version.clone()),
+                    KeyValue::new("agent.name", ctx.agent_name.clone()),
+                    KeyValue::new("agent.version", ctx.agent_version.clone()),
+                    KeyValue::new(OFID, ctx.ofid.clone()),
+                ]);
+            }
+            
+            gauge.observe(T::load(metric), &attributes);
+        }
+    };
+    T::register(&meter, name_owned.clone(), description.to_string(), Box::new(callback));
+    if let Some(metric) = metrics.get_mut(&name_owned) {
+        metric.registered = true;
     }
+    Ok(())
+}
+/// Create/Update an onservable gauge metric that can be updated dynamically.
+/// this means the value will be stored and send doing each metric update.
+pub fn set_f64_observable_gauge(name: &str, value: f64, description: &str) -> Result<(), String> {
+    set_observable_gauge(name, value, description)
+}
+/// Create/Update an onservable gauge metric that can be updated dynamically.
+/// this means the value will be stored and send doing each metric update.
+pub fn set_u64_observable_gauge(name: &str, value: u64, description: &str) -> Result<(), String> {
+    set_observable_gauge(name, value, description)
+}
+/// Create/Update an onservable gauge metric that can be updated dynamically.
+/// this means the value will be stored and send doing each metric update.
+pub fn set_i64_observable_gauge(name: &str, value: i64, description: &str) -> Result<(), String> {
+    set_observable_gauge(name, value, description)
 }
 /// Disable an observable gauge metric. 
 pub fn disable_observable_gauge(name: &str) {
@@ -817,18 +912,139 @@ pub fn disable_observable_gauge(name: &str) {
         metric.enabled = false;
     }
 }
+enum CustomInstrument {
+    Counter(opentelemetry::metrics::Counter<u64>),
+    UpDownCounter(opentelemetry::metrics::UpDownCounter<i64>),
+    Histogram(opentelemetry::metrics::Histogram<f64>),
+}
+impl CustomInstrument {
+    fn kind(&self) -> &'static str {
+        match self {
+            CustomInstrument::Counter(_) => "counter",
+            CustomInstrument::UpDownCounter(_) => "up-down counter",
+            CustomInstrument::Histogram(_) => "histogram",
+        }
+    }
+}
+
+static CUSTOM_INSTRUMENTS: Lazy<std::sync::Mutex<HashMap<String, CustomInstrument>>> = Lazy::new(|| {
+    std::sync::Mutex::new(HashMap::new())
+});
+
+/// Attributes added to custom metrics, the same as for the observable gauges plus `attributes`.
+fn custom_metric_attributes(name: &str, attributes: &[(&str, &str)]) -> Vec<KeyValue> {
+    let mut result = vec![
+        KeyValue::new(HOSTNAME, hostname::get().unwrap_or_default().to_string_lossy().to_string()),
+        KeyValue::new("metric_name", name.to_string()),
+        KeyValue::new("PID", std::process::id().to_string()),
+    ];
+    if let Some(ctx) = METRICS_CONTEXT.lock().unwrap().as_ref() {
+        result.extend_from_slice(&[
+            KeyValue::new("service.version", ctx.version.clone()),
+            KeyValue::new("agent.name", ctx.agent_name.clone()),
+            KeyValue::new("agent.version", ctx.agent_version.clone()),
+            KeyValue::new(OFID, ctx.ofid.clone()),
+        ]);
+    }
+    for (key, value) in attributes {
+        result.push(KeyValue::new(key.to_string(), value.to_string()));
+    }
+    result
+}
+
+/// Look up the instrument for `name`, or create it on the custom meter provider.
+fn with_custom_instrument(
+    name: &str,
+    kind: &'static str,
+    create: impl FnOnce(&Meter) -> CustomInstrument,
+    record: impl FnOnce(&CustomInstrument),
+) -> Result<(), String> {
+    let mut instruments = CUSTOM_INSTRUMENTS.lock().unwrap();
+    if !instruments.contains_key(name) {
+        let providers2 = provider2.lock().unwrap();
+        match &providers2.provider {
+            Some(provider) => {
+                let meter = provider.meter("custommeter");
+                instruments.insert(name.to_string(), create(&meter));
+            }
+            None => return Err("Provider not initialized".to_string()),
+        }
+    }
+    let instrument = &instruments[name];
+    if instrument.kind() != kind {
+        return Err(format!("Metric {} is already registered as a {}", name, instrument.kind()));
+    }
+    record(instrument);
+    Ok(())
+}
+
+/// Add `value` to a counter metric, the counter is created on first use.
+/// `attributes` are added to this measurement, so the same counter can be split by for instance queue name.
+pub fn inc_counter(name: &str, value: u64, description: &str, attributes: &[(&str, &str)]) -> Result<(), String> {
+    let attributes = custom_metric_attributes(name, attributes);
+    with_custom_instrument(
+        name,
+        "counter",
+        |meter| CustomInstrument::Counter(meter.u64_counter(name.to_string()).with_description(description.to_string()).build()),
+        |instrument| {
+            if let CustomInstrument::Counter(counter) = instrument {
+                counter.add(value, &attributes);
+            }
+        },
+    )
+}
+/// Add `value` to an up-down counter metric, use a negative value to decrease it.
+/// The counter is created on first use.
+pub fn add_up_down_counter(name: &str, value: i64, description: &str, attributes: &[(&str, &str)]) -> Result<(), String> {
+    let attributes = custom_metric_attributes(name, attributes);
+    with_custom_instrument(
+        name,
+        "up-down counter",
+        |meter| CustomInstrument::UpDownCounter(meter.i64_up_down_counter(name.to_string()).with_description(description.to_string()).build()),
+        |instrument| {
+            if let CustomInstrument::UpDownCounter(counter) = instrument {
+                counter.add(value, &attributes);
+            }
+        },
+    )
+}
+/// Record `value` in a histogram metric, like a duration in milliseconds.
+/// The histogram is created on first use.
+pub fn record_histogram(name: &str, value: f64, description: &str, attributes: &[(&str, &str)]) -> Result<(), String> {
+    let attributes = custom_metric_attributes(name, attributes);
+    with_custom_instrument(
+        name,
+        "histogram",
+        |meter| CustomInstrument::Histogram(meter.f64_histogram(name.to_string()).with_description(description.to_string()).build()),
+        |instrument| {
+            if let CustomInstrument::Histogram(histogram) = instrument {
+                histogram.record(value, &attributes);
+            }
+        },
+    )
+}
 #[allow(dead_code)]
 pub fn get_metric_value(name: &str) -> Option<f64> {
     METRIC_VALUES.lock().unwrap()
         .get(name)
-        .map(|m| m.f64value)
+        .map(|m| m.value())
 }
 
 #[allow(dead_code)]
 pub fn list_metrics() -> Vec<(String, f64, String)> {
     METRIC_VALUES.lock().unwrap()
         .iter()
-        .map(|(name, metric)| (name.clone(), metric.f64value, metric.description.clone()))
+        .map(|(name, metric)| (name.clone(), metric.value(), metric.description.clone()))
+        .collect()
+}
+
+/// List the enabled observable gauges as (name, value, description), with the value of whichever type the gauge was set as.
+#[cfg(feature = "prometheus")]
+pub(crate) fn observable_gauge_values() -> Vec<(String, f64, String)> {
+    METRIC_VALUES.lock().unwrap()
+        .iter()
+        .filter(|(_, metric)| metric.enabled)
+        .map(|(name, metric)| (name.clone(), metric.value(), metric.description.clone()))
         .collect()
 }
 
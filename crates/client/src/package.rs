@@ -0,0 +1,369 @@
+use openiap_proto::errors::OpenIAPError;
+use openiap_proto::openiap::{
+    DeleteOneRequest, GetDocumentVersionRequest, InsertOrUpdateOneRequest, QueryRequest, UpdateOneRequest,
+    UploadRequest,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::Path;
+use tracing::{debug, info};
+
+use crate::{Client, EnvConfig};
+
+/// Languages an agent can run a package in.
+pub const PACKAGE_LANGUAGES: [&str; 9] = [
+    "nodejs", "python", "dotnet", "powershell", "exe", "rust", "go", "java", "php",
+];
+
+/// Read null as the default value, older package documents have null in some fields.
+fn null_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// A port exposed by a package.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PackagePort {
+    /// Port number inside the agent.
+    pub port: u16,
+    /// Name of the port.
+    pub portname: String,
+    /// Protocol, like "TCP" or "UDP".
+    pub protocol: String,
+    /// Expose the port through the web ingress.
+    pub web: bool,
+}
+
+/// Metadata for a package, stored in the package document in the agents collection.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PackageManifest {
+    /// Unique name of the package.
+    #[serde(deserialize_with = "null_default")]
+    pub name: String,
+    /// Version as major.minor.patch, leave empty to bump the published version.
+    #[serde(deserialize_with = "null_default")]
+    pub version: String,
+    /// One of `PACKAGE_LANGUAGES`.
+    #[serde(deserialize_with = "null_default")]
+    pub language: String,
+    /// File to run inside the package, if not using the language default.
+    #[serde(skip_serializing_if = "String::is_empty", deserialize_with = "null_default")]
+    pub main: String,
+    /// Short description.
+    #[serde(skip_serializing_if = "String::is_empty", deserialize_with = "null_default")]
+    pub description: String,
+    /// Keep the package running, instead of running it on a schedule.
+    #[serde(deserialize_with = "null_default")]
+    pub daemon: bool,
+    /// The package needs a browser.
+    #[serde(deserialize_with = "null_default")]
+    pub chromium: bool,
+    /// Ports exposed by the package.
+    #[serde(deserialize_with = "null_default")]
+    pub ports: Vec<PackagePort>,
+}
+impl PackageManifest {
+    /// Create a manifest for `name` running in `language`.
+    pub fn new(name: &str, language: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            language: language.to_string(),
+            ..Default::default()
+        }
+    }
+    /// Check the manifest is complete, returns the first problem found.
+    pub fn validate(&self) -> Result<(), OpenIAPError> {
+        let invalid = |message: String| Err(OpenIAPError::ClientError(message));
+        if self.name.is_empty() {
+            return invalid("Package name is required".to_string());
+        }
+        // plain names like my-package, or scoped names like @org/my-package
+        let valid = |part: &str| {
+            part.chars().next().is_some_and(|c| c.is_ascii_alphanumeric())
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+        };
+        let valid = match self.name.strip_prefix('@') {
+            Some(scoped) => matches!(scoped.split_once('/'), Some((scope, name)) if valid(scope) && valid(name)),
+            None => valid(&self.name),
+        };
+        if !valid {
+            return invalid(format!("Invalid package name {}", self.name));
+        }
+        if !PACKAGE_LANGUAGES.contains(&self.language.as_str()) {
+            return invalid(format!(
+                "Invalid language {}, must be one of {}",
+                self.language,
+                PACKAGE_LANGUAGES.join(", ")
+            ));
+        }
+        if !self.version.is_empty() && parse_version(&self.version).is_none() {
+            return invalid(format!("Invalid version {}, expected major.minor.patch", self.version));
+        }
+        for port in &self.ports {
+            if port.port == 0 || port.portname.is_empty() {
+                return invalid(format!("Port {} needs a number and a name", port.portname));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A package document, as returned by `list_packages`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Package {
+    /// Id of the package document.
+    #[serde(rename = "_id", default)]
+    pub id: String,
+    /// Package metadata.
+    #[serde(flatten)]
+    pub manifest: PackageManifest,
+    /// Id of the package file in fs.files.
+    #[serde(default, deserialize_with = "null_default")]
+    pub fileid: String,
+    /// md5 checksum of the package file.
+    #[serde(default, deserialize_with = "null_default")]
+    pub checksum: String,
+    /// Document version, increases each time the document is updated.
+    #[serde(rename = "_version", default)]
+    pub docversion: i32,
+}
+
+/// Parse a major.minor.patch version, a leading "v" is allowed.
+pub fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.trim_start_matches('v').split('.').map(|p| p.parse::<u64>());
+    let version = (parts.next()?.ok()?, parts.next()?.ok()?, parts.next()?.ok()?);
+    match parts.next() {
+        Some(_) => None,
+        None => Some(version),
+    }
+}
+
+/// Increase the patch number of `version`, an empty or invalid version becomes 0.0.1.
+pub fn bump_version(version: &str) -> String {
+    match parse_version(version) {
+        Some((major, minor, patch)) => format!("{}.{}.{}", major, minor, patch + 1),
+        None => "0.0.1".to_string(),
+    }
+}
+
+impl Client {
+    /// Publish a package, uploads the file at `path` and creates or updates the package document.
+    /// If `manifest.version` is empty, the version of the published package is bumped.
+    /// Publishing the same file again does nothing, and returns the existing package.
+    #[tracing::instrument(skip_all)]
+    pub async fn publish_package(
+        &self,
+        env: EnvConfig,
+        mut manifest: PackageManifest,
+        path: &str,
+    ) -> Result<Package, OpenIAPError> {
+        manifest.validate()?;
+        let file = Path::new(path);
+        let is_file = tokio::fs::metadata(file).await.map(|m| m.is_file()).unwrap_or(false);
+        let filename = match file.file_name() {
+            Some(name) if is_file => name.to_string_lossy().to_string(),
+            _ => return Err(OpenIAPError::ClientError(format!("Package file {} not found", path))),
+        };
+        // hashing the package is blocking io, keep it off the async executor
+        let checksum_path = file.to_path_buf();
+        let checksum = tokio::task::spawn_blocking(move || crate::sync::file_checksum(&checksum_path))
+            .await
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to read {}: {}", path, e)))?
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to read {}: {}", path, e)))?;
+        let existing = self.get_package(env.clone(), &manifest.name).await?;
+        if let Some(existing) = &existing {
+            if existing.checksum == checksum && (manifest.version.is_empty() || manifest.version == existing.manifest.version) {
+                info!("Package {} {} is already published", existing.manifest.name, existing.manifest.version);
+                return Ok(existing.clone());
+            }
+            let current = parse_version(&existing.manifest.version);
+            if manifest.version.is_empty() {
+                manifest.version = bump_version(&existing.manifest.version);
+            } else if current.is_some() && parse_version(&manifest.version) <= current {
+                return Err(OpenIAPError::ClientError(format!(
+                    "Version {} must be higher than the published version {}",
+                    manifest.version, existing.manifest.version
+                )));
+            }
+        } else if manifest.version.is_empty() {
+            manifest.version = bump_version("");
+        }
+        let mimetype = if filename.ends_with(".zip") { "application/zip" } else { "application/gzip" };
+        let upload = UploadRequest {
+            filename: filename.clone(),
+            mimetype: mimetype.to_string(),
+            metadata: serde_json::json!({
+                "checksum": checksum,
+                "package": manifest.name,
+                "version": manifest.version,
+            })
+            .to_string(),
+            collectionname: "fs.files".to_string(),
+        };
+        let uploaded = self.upload(upload, env.clone(), path).await?;
+        debug!("Uploaded {} as {}", filename, uploaded.id);
+        let mut item = serde_json::to_value(&manifest)
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to serialize package: {}", e)))?;
+        item["_type"] = "package".into();
+        item["fileid"] = uploaded.id.clone().into();
+        item["checksum"] = checksum.into();
+        if let Some(existing) = &existing {
+            item["_id"] = existing.id.clone().into();
+        }
+        let request = InsertOrUpdateOneRequest {
+            collectionname: "agents".to_string(),
+            uniqeness: "_type,name".to_string(),
+            item: item.to_string(),
+            ..Default::default()
+        };
+        let result = match self.insert_or_update_one(request, env.clone()).await {
+            Ok(result) => result,
+            Err(e) => {
+                // nothing references the uploaded file yet
+                let request = DeleteOneRequest {
+                    collectionname: "fs.files".to_string(),
+                    id: uploaded.id.clone(),
+                    ..Default::default()
+                };
+                if let Err(e) = self.delete_one(request, env).await {
+                    debug!("Failed to delete package file {}: {}", uploaded.id, e);
+                }
+                return Err(e);
+            }
+        };
+        let package: Package = serde_json::from_str(&result)
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse package: {}", e)))?;
+        if let Some(existing) = existing {
+            if !existing.fileid.is_empty() && existing.fileid != package.fileid {
+                let request = DeleteOneRequest {
+                    collectionname: "fs.files".to_string(),
+                    id: existing.fileid.clone(),
+                    ..Default::default()
+                };
+                if let Err(e) = self.delete_one(request, env).await {
+                    debug!("Failed to delete old package file {}: {}", existing.fileid, e);
+                }
+            }
+        }
+        info!("Published package {} {}", package.manifest.name, package.manifest.version);
+        Ok(package)
+    }
+    /// Run `package` on the agent with id `agentid`, by adding it to the schedules of the agent document.
+    /// A package already on the agent is updated to the current package, keeping the rest of its schedule.
+    #[tracing::instrument(skip_all)]
+    pub async fn deploy_package(&self, env: EnvConfig, agentid: &str, package: &Package) -> Result<(), OpenIAPError> {
+        if agentid.is_empty() {
+            return Err(OpenIAPError::ClientError("No agent id provided".to_string()));
+        }
+        if package.id.is_empty() {
+            return Err(OpenIAPError::ClientError("Package has no id, publish it first".to_string()));
+        }
+        let request = QueryRequest {
+            collectionname: "agents".to_string(),
+            query: serde_json::json!({ "_type": "agent", "_id": agentid }).to_string(),
+            top: 1,
+            ..Default::default()
+        };
+        let response = self.query(request, env.clone()).await?;
+        let agents: Vec<serde_json::Value> = serde_json::from_str(&response.results)
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse agents: {}", e)))?;
+        let mut agent = match agents.into_iter().next() {
+            Some(agent) => agent,
+            None => return Err(OpenIAPError::ClientError(format!("Agent {} not found", agentid))),
+        };
+        if !agent["schedules"].is_array() {
+            agent["schedules"] = serde_json::json!([]);
+        }
+        let schedules = agent["schedules"].as_array_mut().unwrap();
+        match schedules.iter_mut().find(|s| s["packageid"].as_str() == Some(package.id.as_str())) {
+            Some(schedule) => {
+                schedule["name"] = package.manifest.name.clone().into();
+                schedule["enabled"] = true.into();
+            }
+            None => schedules.push(serde_json::json!({
+                "name": package.manifest.name,
+                "packageid": package.id,
+                "enabled": true,
+                "cron": "",
+                "env": {},
+            })),
+        }
+        let request = UpdateOneRequest {
+            collectionname: "agents".to_string(),
+            item: agent.to_string(),
+            ..Default::default()
+        };
+        self.update_one(request, env).await?;
+        info!("Deployed package {} {} to agent {}", package.manifest.name, package.manifest.version, agentid);
+        Ok(())
+    }
+    /// List packages, optionally only those for `language`.
+    #[tracing::instrument(skip_all)]
+    pub async fn list_packages(
+        &self,
+        env: EnvConfig,
+        language: Option<&str>,
+    ) -> Result<Vec<Package>, OpenIAPError> {
+        let mut query = serde_json::json!({ "_type": "package" });
+        if let Some(language) = language {
+            query["language"] = language.into();
+        }
+        self.query_packages(env, query).await
+    }
+    /// Get a package by name, return None if it does not exist.
+    #[tracing::instrument(skip_all)]
+    pub async fn get_package(&self, env: EnvConfig, name: &str) -> Result<Option<Package>, OpenIAPError> {
+        let query = serde_json::json!({ "_type": "package", "name": name });
+        Ok(self.query_packages(env, query).await?.into_iter().next())
+    }
+    /// Get an earlier version of a package document, `docversion` is the `_version` of the document.
+    #[tracing::instrument(skip_all)]
+    pub async fn get_package_version(
+        &self,
+        env: EnvConfig,
+        packageid: &str,
+        docversion: i32,
+    ) -> Result<Package, OpenIAPError> {
+        let request = GetDocumentVersionRequest {
+            collectionname: "agents".to_string(),
+            id: packageid.to_string(),
+            version: docversion,
+            ..Default::default()
+        };
+        let result = self.get_document_version(request, env).await?;
+        serde_json::from_str(&result)
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse package: {}", e)))
+    }
+    async fn query_packages(&self, env: EnvConfig, query: serde_json::Value) -> Result<Vec<Package>, OpenIAPError> {
+        let mut packages = Vec::new();
+        let mut skip = 0;
+        loop {
+            let request = QueryRequest {
+                collectionname: "agents".to_string(),
+                query: query.to_string(),
+                orderby: "{\"name\": 1}".to_string(),
+                top: 100,
+                skip,
+                ..Default::default()
+            };
+            let response = self.query(request, env.clone()).await?;
+            let page: Vec<serde_json::Value> = serde_json::from_str(&response.results)
+                .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse packages: {}", e)))?;
+            let done = page.len() < 100;
+            skip += page.len() as i32;
+            for document in page {
+                match serde_json::from_value::<Package>(document.clone()) {
+                    Ok(package) => packages.push(package),
+                    Err(e) => debug!("Skipping package {}: {}", document["_id"], e),
+                }
+            }
+            if done {
+                return Ok(packages);
+            }
+        }
+    }
+}
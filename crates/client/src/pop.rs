@@ -0,0 +1,173 @@
+use openiap_proto::errors::OpenIAPError;
+use openiap_proto::openiap::{DownloadRequest, ErrorResponse, PopWorkitemRequest, UpdateWorkitemRequest, Workitem, WorkitemFile};
+use openiap_proto::workitem::WorkitemState;
+use std::path::PathBuf;
+use tracing::{debug, error};
+
+use crate::{Client, EnvConfig};
+
+/// What `pop_workitem_with_files` does when a file cannot be downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DownloadErrorPolicy {
+    /// Set the workitem back to retry and return the error.
+    #[default]
+    Fail,
+    /// Return the workitem, with the error on the `PoppedFile`.
+    Report,
+}
+
+/// Options for `pop_workitem_with_files`.
+#[derive(Debug, Clone, Default)]
+pub struct PopWorkitemOptions {
+    /// Folder files are saved to, defaults to ".". Ignored when `in_memory` is set.
+    pub downloadfolder: Option<String>,
+    /// Keep file content in `PoppedFile::content` instead of writing it to disk.
+    pub in_memory: bool,
+    /// What to do when a download fails.
+    pub on_error: DownloadErrorPolicy,
+}
+
+/// A file attached to a popped workitem.
+#[derive(Debug, Clone, Default)]
+pub struct PoppedFile {
+    /// The file as returned on the workitem.
+    pub file: WorkitemFile,
+    /// Where the file was saved, None when kept in memory or the download failed.
+    pub path: Option<PathBuf>,
+    /// The file content, when `in_memory` was set.
+    pub content: Option<Vec<u8>>,
+    /// Why the download failed.
+    pub error: Option<String>,
+}
+
+/// A workitem returned by `pop_workitem_with_files`.
+#[derive(Debug, Clone, Default)]
+pub struct PoppedWorkitem {
+    /// The popped workitem.
+    pub workitem: Workitem,
+    /// One entry for each file on the workitem, in the same order.
+    pub files: Vec<PoppedFile>,
+}
+impl PoppedWorkitem {
+    /// Returns the local path of the file with `id`.
+    pub fn path_of(&self, id: &str) -> Option<&PathBuf> {
+        self.files.iter().find(|f| f.file.id == id).and_then(|f| f.path.as_ref())
+    }
+    /// Returns true if every file was downloaded.
+    pub fn all_downloaded(&self) -> bool {
+        self.files.iter().all(|f| f.error.is_none())
+    }
+}
+
+impl Client {
+    /// Pop a workitem from a workitem queue and download its files, return None if no workitem is available.
+    /// Unlike `pop_workitem` the local path or content of each file is returned, and failed downloads are not ignored.
+    #[tracing::instrument(skip_all)]
+    pub async fn pop_workitem_with_files(
+        &self,
+        config: PopWorkitemRequest,
+        env: EnvConfig,
+        options: PopWorkitemOptions,
+    ) -> Result<Option<PoppedWorkitem>, OpenIAPError> {
+        let response = self.pop_workitem_nofiles(config, env.clone()).await?;
+        let workitem = match response.workitem {
+            Some(workitem) => workitem,
+            None => {
+                debug!("No workitem found");
+                return Ok(None);
+            }
+        };
+        let mut files = Vec::new();
+        for file in &workitem.files {
+            let mut popped = PoppedFile {
+                file: file.clone(),
+                ..Default::default()
+            };
+            if file.id.is_empty() {
+                files.push(popped);
+                continue;
+            }
+            let result = if options.in_memory {
+                self.download_to_memory(&file.id, env.clone()).await.map(|content| {
+                    popped.content = Some(content);
+                })
+            } else {
+                let downloadconfig = DownloadRequest {
+                    id: file.id.clone(),
+                    collectionname: "fs.files".to_string(),
+                    ..Default::default()
+                };
+                self.download(downloadconfig, env.clone(), options.downloadfolder.as_deref(), None)
+                    .await
+                    .map(|r| {
+                        debug!("File {} was downloaded as {}", file.filename, r.filename);
+                        popped.path = Some(PathBuf::from(r.filename));
+                    })
+            };
+            if let Err(e) = result {
+                let message = format!("Failed to download file {}: {}", file.filename, e);
+                if options.on_error == DownloadErrorPolicy::Fail {
+                    let mut failed = workitem.clone();
+                    failed.state = WorkitemState::Retry.to_string();
+                    failed.errortype = "application".to_string();
+                    failed.errormessage = message.clone();
+                    let request = UpdateWorkitemRequest {
+                        workitem: Some(failed),
+                        ignoremaxretries: false,
+                        ..Default::default()
+                    };
+                    if let Err(e) = self.update_workitem(request, env.clone()).await {
+                        error!("Failed to return workitem {} to the queue: {}", workitem.id, e);
+                    }
+                    for f in files.iter().filter_map(|f: &PoppedFile| f.path.as_ref()) {
+                        let _ = std::fs::remove_file(f);
+                    }
+                    return Err(OpenIAPError::ClientError(message));
+                }
+                error!("{}", message);
+                popped.error = Some(message);
+            }
+            files.push(popped);
+        }
+        Ok(Some(PoppedWorkitem { workitem, files }))
+    }
+    /// Download a file from fs.files into memory, without writing it to disk.
+    async fn download_to_memory(&self, id: &str, env: EnvConfig) -> Result<Vec<u8>, OpenIAPError> {
+        let mut envelope = DownloadRequest {
+            id: id.to_string(),
+            collectionname: "fs.files".to_string(),
+            ..Default::default()
+        }
+        .to_envelope();
+        if !env.jwt.is_empty() {
+            envelope.jwt = env.jwt;
+        }
+        if !env.spanid.is_empty() {
+            envelope.spanid = env.spanid;
+        }
+        if !env.traceid.is_empty() {
+            envelope.traceid = env.traceid;
+        }
+        let (response_rx, mut stream_rx) = self.sendwithstream(envelope).await?;
+        let mut content = Vec::new();
+        while let Some(received) = stream_rx.recv().await {
+            if received.is_empty() {
+                break;
+            }
+            content.extend_from_slice(&received);
+        }
+        let response = response_rx
+            .await
+            .map_err(|_| OpenIAPError::ClientError("Failed to receive response".to_string()))?;
+        if response.command == "error" {
+            let data = response
+                .data
+                .ok_or_else(|| OpenIAPError::ClientError("No data returned for SERVER error".to_string()))?;
+            let e: ErrorResponse = prost::Message::decode(data.value.as_ref())
+                .map_err(|e| OpenIAPError::CustomError(e.to_string()))?;
+            return Err(OpenIAPError::ServerError(e.message));
+        }
+        debug!("Downloaded {} bytes of file {}", content.len(), id);
+        Ok(content)
+    }
+}
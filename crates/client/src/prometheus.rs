@@ -0,0 +1,210 @@
+use openiap_proto::errors::OpenIAPError;
+use perf_monitor::cpu::{processor_numbers, ProcessStat};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+use crate::{Client, ClientDiagnostics};
+
+/// Process metrics included on the Prometheus endpoint.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessMetrics {
+    /// Physical memory in use, in bytes.
+    pub resident_memory: u64,
+    /// Committed virtual memory, in bytes.
+    pub virtual_memory: u64,
+    /// Cpu usage in percent of one core.
+    pub cpu_usage: f64,
+    /// Cpu usage in percent of all cores.
+    pub cpu_utilization: f64,
+    /// Seconds since the endpoint was started.
+    pub uptime: f64,
+}
+
+/// Turn a metric name like "my.metric" into a valid Prometheus name.
+pub fn prometheus_name(name: &str) -> String {
+    let mut result: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' })
+        .collect();
+    if result.starts_with(|c: char| c.is_ascii_digit()) {
+        result.insert(0, '_');
+    }
+    result
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help.replace('\n', " "));
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Render client statistics, process metrics and observable gauges in the Prometheus text format.
+pub fn render_prometheus(
+    diagnostics: &ClientDiagnostics,
+    process: &ProcessMetrics,
+    gauges: &[(String, f64, String)],
+) -> String {
+    let mut out = String::new();
+    let counters = [
+        ("openiap_client_connection_attempts_total", "Number of times the client started connecting.", diagnostics.connection_attempts),
+        ("openiap_client_connections_total", "Number of successful connections.", diagnostics.connections),
+        ("openiap_client_reconnects_total", "Number of successful connections after the first.", diagnostics.reconnects),
+        ("openiap_client_package_tx_total", "Envelopes sent.", diagnostics.package_tx),
+        ("openiap_client_package_rx_total", "Envelopes received.", diagnostics.package_rx),
+    ];
+    for (name, help, value) in counters {
+        write_header(&mut out, name, "counter", help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+    write_header(&mut out, "openiap_client_state", "gauge", "Client state, 1 for the current state.");
+    for state in ["Disconnected", "Connecting", "Connected", "Signedin"] {
+        let value = if diagnostics.state == state { 1 } else { 0 };
+        let _ = writeln!(out, "openiap_client_state{{state=\"{}\"}} {}", state, value);
+    }
+    let gauges_client = [
+        ("openiap_client_uptime_seconds", "Seconds since the client connected.", diagnostics.uptime as f64),
+        ("openiap_client_pending_queries", "Requests waiting for a reply.", diagnostics.pending_queries as f64),
+        ("openiap_client_pending_streams", "Open upload and download streams.", diagnostics.pending_streams as f64),
+        ("openiap_client_watches", "Active watches.", diagnostics.watches as f64),
+        ("openiap_client_queues", "Registered queues and exchanges.", diagnostics.queues as f64),
+        ("openiap_client_outbound_queue", "Envelopes waiting to be sent.", diagnostics.outbound_queue as f64),
+    ];
+    for (name, help, value) in gauges_client {
+        write_header(&mut out, name, "gauge", help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+    write_header(&mut out, "openiap_client_commands_total", "counter", "Requests sent, by command.");
+    for (command, stats) in &diagnostics.commands {
+        let _ = writeln!(out, "openiap_client_commands_total{{command=\"{}\"}} {}", escape_label(command), stats.count);
+    }
+    write_header(&mut out, "openiap_client_command_errors_total", "counter", "Failed requests, by command and kind.");
+    for (command, stats) in &diagnostics.commands {
+        let mut errors: Vec<_> = stats.errors.iter().collect();
+        errors.sort();
+        for (kind, count) in errors {
+            let _ = writeln!(out, "openiap_client_command_errors_total{{command=\"{}\",kind=\"{}\"}} {}",
+                escape_label(command), escape_label(kind), count);
+        }
+    }
+    write_header(&mut out, "openiap_client_command_duration_seconds", "summary", "Round trip time of requests, by command.");
+    for (command, stats) in &diagnostics.commands {
+        let command = escape_label(command);
+        let _ = writeln!(out, "openiap_client_command_duration_seconds_sum{{command=\"{}\"}} {}", command, stats.total_ms / 1000.0);
+        let _ = writeln!(out, "openiap_client_command_duration_seconds_count{{command=\"{}\"}} {}", command, stats.count);
+    }
+    write_header(&mut out, "openiap_client_command_sent_bytes_total", "counter", "Bytes sent, by command.");
+    for (command, stats) in &diagnostics.commands {
+        let _ = writeln!(out, "openiap_client_command_sent_bytes_total{{command=\"{}\"}} {}", escape_label(command), stats.bytes_sent);
+    }
+    write_header(&mut out, "openiap_client_command_received_bytes_total", "counter", "Bytes received, by command.");
+    for (command, stats) in &diagnostics.commands {
+        let _ = writeln!(out, "openiap_client_command_received_bytes_total{{command=\"{}\"}} {}", escape_label(command), stats.bytes_received);
+    }
+    let process_gauges = [
+        ("process_resident_memory_bytes", "The amount of physical memory in use.", process.resident_memory as f64),
+        ("process_virtual_memory_bytes", "The amount of committed virtual memory.", process.virtual_memory as f64),
+        ("process_cpu_usage_percent", "The percentage of one CPU core in use.", process.cpu_usage),
+        ("process_cpu_utilization_percent", "The percentage of all CPU cores in use.", process.cpu_utilization),
+        ("process_uptime_seconds", "Seconds since the metrics endpoint was started.", process.uptime),
+    ];
+    for (name, help, value) in process_gauges {
+        write_header(&mut out, name, "gauge", help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+    let mut gauges: Vec<_> = gauges.iter().collect();
+    gauges.sort_by(|a, b| a.0.cmp(&b.0));
+    for (name, value, description) in gauges {
+        let name = prometheus_name(name);
+        write_header(&mut out, &name, "gauge", description);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+    out
+}
+
+/// Collects process metrics between scrapes, cpu usage is measured since the previous scrape.
+struct ProcessCollector {
+    started: Instant,
+    process_stat: Option<ProcessStat>,
+    core_count: usize,
+}
+impl ProcessCollector {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            process_stat: ProcessStat::cur().map_err(|e| debug!("Could not retrieve process stat: {}", e)).ok(),
+            core_count: processor_numbers().unwrap_or(1).max(1),
+        }
+    }
+    fn collect(&mut self) -> ProcessMetrics {
+        let mut metrics = ProcessMetrics {
+            uptime: self.started.elapsed().as_secs_f64(),
+            ..Default::default()
+        };
+        if let Some(usage) = memory_stats::memory_stats() {
+            metrics.resident_memory = usage.physical_mem as u64;
+            metrics.virtual_memory = usage.virtual_mem as u64;
+        }
+        if let Some(process_stat) = &mut self.process_stat {
+            metrics.cpu_usage = process_stat.cpu().unwrap_or_default() * 100.0;
+            metrics.cpu_utilization = metrics.cpu_usage / self.core_count as f64;
+        }
+        metrics
+    }
+}
+
+impl Client {
+    /// Serve client and process metrics in the Prometheus text format on `addr`, like "0.0.0.0:9464".
+    /// GET /metrics returns the `diagnostics` counters, process memory and cpu, and all observable gauges.
+    pub async fn serve_prometheus(&self, addr: &str) -> Result<JoinHandle<()>, OpenIAPError> {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to listen on {}: {}", addr, e)))?;
+        info!("Prometheus metrics listening on {}/metrics", addr);
+        let client = self.clone();
+        let collector = Arc::new(Mutex::new(ProcessCollector::new()));
+        Ok(tokio::task::spawn(async move {
+            loop {
+                let (mut stream, _) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        error!("Prometheus endpoint failed to accept: {}", e);
+                        continue;
+                    }
+                };
+                let client = client.clone();
+                let collector = collector.clone();
+                tokio::task::spawn(async move {
+                    let mut buffer = [0; 1024];
+                    let read = stream.read(&mut buffer).await.unwrap_or_default();
+                    let request = String::from_utf8_lossy(&buffer[..read]);
+                    let path = request.split_whitespace().nth(1).unwrap_or("/");
+                    let (code, body) = if path == "/metrics" {
+                        let diagnostics = client.diagnostics(false).await;
+                        let process = collector.lock().unwrap().collect();
+                        let gauges = crate::otel::observable_gauge_values();
+                        ("200 OK", render_prometheus(&diagnostics, &process, &gauges))
+                    } else {
+                        ("404 Not Found", "not found".to_string())
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        code,
+                        body.len(),
+                        body
+                    );
+                    if let Err(e) = stream.write_all(response.as_bytes()).await {
+                        debug!("Prometheus endpoint failed to respond: {}", e);
+                    }
+                    let _ = stream.shutdown().await;
+                });
+            }
+        }))
+    }
+}
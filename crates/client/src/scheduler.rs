@@ -0,0 +1,489 @@
+use openiap_proto::errors::OpenIAPError;
+use openiap_proto::openiap::{
+    DeleteManyRequest, InsertOneRequest, InsertOrUpdateOneRequest, PushWorkitemRequest,
+    PushWorkitemResponse, QueryRequest, UpdateDocumentRequest,
+};
+use openiap_proto::workitem::systemtime_to_timestamp;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+use crate::util::civil_from_days;
+use crate::{Client, EnvConfig};
+
+impl Client {
+    /// Push a workitem that will not be popped before `time`
+    #[tracing::instrument(skip_all)]
+    pub async fn push_workitem_at(
+        &self,
+        mut config: PushWorkitemRequest,
+        env: EnvConfig,
+        time: SystemTime,
+    ) -> Result<PushWorkitemResponse, OpenIAPError> {
+        config.nextrun = Some(systemtime_to_timestamp(time));
+        self.push_workitem(config, env).await
+    }
+    /// Push a workitem that will not be popped before `delay` has passed
+    #[tracing::instrument(skip_all)]
+    pub async fn push_workitem_after(
+        &self,
+        config: PushWorkitemRequest,
+        env: EnvConfig,
+        delay: Duration,
+    ) -> Result<PushWorkitemResponse, OpenIAPError> {
+        self.push_workitem_at(config, env, SystemTime::now() + delay).await
+    }
+}
+
+/// A parsed 5 field cron expression ( minute hour day-of-month month day-of-week ), evaluated in UTC.
+/// Supports `*`, numbers, ranges `a-b`, lists `a,b` and steps `*/n` or `a-b/n`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<(Vec<bool>, bool), OpenIAPError> {
+    let invalid = || OpenIAPError::ClientError(format!("Invalid cron field {:?}", field));
+    let mut values = vec![false; max as usize + 1];
+    let mut restricted = false;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else {
+            restricted = true;
+            match range.split_once('-') {
+                Some((a, b)) => (
+                    a.parse::<u32>().map_err(|_| invalid())?,
+                    b.parse::<u32>().map_err(|_| invalid())?,
+                ),
+                None => {
+                    let a = range.parse::<u32>().map_err(|_| invalid())?;
+                    // "5/15" means from 5 to the end in steps of 15
+                    if part.contains('/') { (a, max) } else { (a, a) }
+                }
+            }
+        };
+        if start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        let mut value = start;
+        while value <= end {
+            values[value as usize] = true;
+            value += step;
+        }
+    }
+    Ok((values, restricted))
+}
+impl CronSchedule {
+    /// Parse a 5 field cron expression.
+    pub fn parse(expression: &str) -> Result<Self, OpenIAPError> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(OpenIAPError::ClientError(format!(
+                "Cron expression {:?} must have 5 fields",
+                expression
+            )));
+        }
+        let (minutes, _) = parse_cron_field(fields[0], 0, 59)?;
+        let (hours, _) = parse_cron_field(fields[1], 0, 23)?;
+        let (days, days_restricted) = parse_cron_field(fields[2], 1, 31)?;
+        let (months, _) = parse_cron_field(fields[3], 1, 12)?;
+        let (mut weekdays, weekdays_restricted) = parse_cron_field(fields[4], 0, 7)?;
+        // both 0 and 7 mean sunday
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        Ok(Self {
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            days_restricted,
+            weekdays_restricted,
+        })
+    }
+    fn day_matches(&self, days: i64, day: u32) -> bool {
+        let weekday = (days + 4).rem_euclid(7) as usize; // 1970-01-01 was a thursday
+        let day_match = self.days[day as usize];
+        let weekday_match = self.weekdays[weekday];
+        // like cron, when both fields are restricted either one may match
+        if self.days_restricted && self.weekdays_restricted {
+            day_match || weekday_match
+        } else {
+            day_match && weekday_match
+        }
+    }
+    /// Returns the first time strictly after `after` that matches the schedule.
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let secs = after.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        let mut minute = secs / 60 + 1;
+        // give up after 5 years, an expression like "0 0 31 2 *" never matches
+        let limit = minute + 5 * 366 * 24 * 60;
+        while minute < limit {
+            let days = minute.div_euclid(24 * 60);
+            let (_, month, day) = civil_from_days(days);
+            if !self.months[month as usize] || !self.day_matches(days, day) {
+                minute = (days + 1) * 24 * 60;
+                continue;
+            }
+            let hour = (minute / 60).rem_euclid(24) as usize;
+            if !self.hours[hour] {
+                minute = (minute / 60 + 1) * 60;
+                continue;
+            }
+            if self.minutes[minute.rem_euclid(60) as usize] {
+                return Some(UNIX_EPOCH + Duration::from_secs(minute as u64 * 60));
+            }
+            minute += 1;
+        }
+        None
+    }
+}
+
+/// What to do with runs that were missed while no scheduler was running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUpPolicy {
+    /// Ignore missed runs, only push a run that became due within the last minute.
+    Skip,
+    /// Push a single workitem for all missed runs.
+    RunOnce,
+    /// Push one workitem per missed run, but at most this many.
+    RunAll(usize),
+}
+/// A workitem pushed on a cron schedule.
+#[derive(Debug, Clone)]
+pub struct RecurringWorkitem {
+    /// Unique name of the schedule, used as key for the persisted state.
+    pub name: String,
+    /// The cron expression.
+    pub schedule: CronSchedule,
+    /// The template pushed for each run, `nextrun` is set to the scheduled time.
+    pub request: PushWorkitemRequest,
+    /// How missed runs are handled.
+    pub catch_up: CatchUpPolicy,
+}
+impl RecurringWorkitem {
+    /// Create a recurring workitem that pushes `name` with `payload` into `wiq`.
+    pub fn new(name: &str, cron: &str, wiq: &str, payload: &str) -> Result<Self, OpenIAPError> {
+        Ok(Self {
+            name: name.to_string(),
+            schedule: CronSchedule::parse(cron)?,
+            request: PushWorkitemRequest {
+                wiq: wiq.to_string(),
+                name: name.to_string(),
+                payload: payload.to_string(),
+                ..Default::default()
+            },
+            catch_up: CatchUpPolicy::RunOnce,
+        })
+    }
+    /// Return the scheduled times to push for given the last run and the current time,
+    /// and the latest due time, to persist as the new last run.
+    pub fn due_runs(&self, lastrun: SystemTime, now: SystemTime) -> (Vec<SystemTime>, Option<SystemTime>) {
+        let keep = match self.catch_up {
+            CatchUpPolicy::RunAll(max) => max.max(1),
+            _ => 1,
+        };
+        let mut runs = std::collections::VecDeque::new();
+        let mut current = lastrun;
+        while let Some(next) = self.schedule.next_after(current) {
+            if next > now {
+                break;
+            }
+            if runs.len() == keep {
+                runs.pop_front();
+            }
+            runs.push_back(next);
+            current = next;
+        }
+        let latest = runs.back().copied();
+        let runs: Vec<SystemTime> = match self.catch_up {
+            // only push a run that became due within the last minute
+            CatchUpPolicy::Skip => runs
+                .into_iter()
+                .filter(|run| now.duration_since(*run).unwrap_or_default() < Duration::from_secs(60))
+                .collect(),
+            _ => runs.into_iter().collect(),
+        };
+        (runs, latest)
+    }
+}
+
+/// Options for `WorkitemScheduler`.
+#[derive(Debug, Clone)]
+pub struct WorkitemSchedulerOptions {
+    /// Collection holding the leader lock and the last run of each schedule.
+    pub collectionname: String,
+    /// Name of the scheduler, clients using the same name elect one leader.
+    pub name: String,
+    /// How long the leader lock is valid without being renewed.
+    pub lease: Duration,
+    /// How often schedules are checked.
+    pub interval: Duration,
+}
+impl Default for WorkitemSchedulerOptions {
+    fn default() -> Self {
+        Self {
+            collectionname: "workitemschedules".to_string(),
+            name: "default".to_string(),
+            lease: Duration::from_secs(60),
+            interval: Duration::from_secs(15),
+        }
+    }
+}
+/// Pushes `RecurringWorkitem`s on schedule. Every client can run a scheduler with the same name,
+/// only the elected leader pushes workitems.
+pub struct WorkitemScheduler {
+    client: Client,
+    options: WorkitemSchedulerOptions,
+    schedules: Vec<RecurringWorkitem>,
+    owner: String,
+    shutdown: Arc<watch::Sender<bool>>,
+}
+/// Handle to a started `WorkitemScheduler`.
+pub struct WorkitemSchedulerHandle {
+    shutdown: Arc<watch::Sender<bool>>,
+    handle: JoinHandle<()>,
+}
+impl WorkitemSchedulerHandle {
+    /// Stop the scheduler and release the leader lock.
+    pub async fn stop(self) {
+        let _ = self.shutdown.send(true);
+        let _ = self.handle.await;
+    }
+}
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+impl WorkitemScheduler {
+    /// Create a scheduler for the given recurring workitems.
+    pub fn new(client: Client, schedules: Vec<RecurringWorkitem>, options: WorkitemSchedulerOptions) -> Self {
+        let host = hostname::get()
+            .map(|h| h.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+        let (shutdown, _) = watch::channel(false);
+        Self {
+            client,
+            options,
+            schedules,
+            owner: format!("{}:{}:{}", host, std::process::id(), nanos),
+            shutdown: Arc::new(shutdown),
+        }
+    }
+    /// Run the scheduler in a background task.
+    pub fn start(self) -> WorkitemSchedulerHandle {
+        let shutdown = self.shutdown.clone();
+        let handle = tokio::task::spawn(async move { self.run().await });
+        WorkitemSchedulerHandle { shutdown, handle }
+    }
+    fn lock_id(&self) -> String {
+        format!("schedulerlock_{}", self.options.name)
+    }
+    /// Try to become, or stay, the leader. Returns true if this scheduler holds the lock.
+    async fn elect(&self) -> Result<bool, OpenIAPError> {
+        let env = EnvConfig::new();
+        let now = to_millis(SystemTime::now());
+        let expires = now + self.options.lease.as_millis() as u64;
+        let lock = serde_json::json!({
+            "_id": self.lock_id(),
+            "_type": "workitemschedulerlock",
+            "name": self.options.name,
+            "owner": self.owner,
+            "expires": expires,
+        });
+        let current = self
+            .client
+            .query(
+                QueryRequest {
+                    collectionname: self.options.collectionname.clone(),
+                    query: serde_json::json!({ "_id": self.lock_id() }).to_string(),
+                    top: 1,
+                    ..Default::default()
+                },
+                env.clone(),
+            )
+            .await?;
+        let current: serde_json::Value = serde_json::from_str(&current.results).unwrap_or_default();
+        let current = &current[0];
+        if current.is_object() {
+            if current["owner"].as_str() == Some(self.owner.as_str()) {
+                // only renew a lock we still hold, another client may have taken it over after it expired
+                let renewed = self
+                    .client
+                    .update_document(
+                        UpdateDocumentRequest {
+                            collectionname: self.options.collectionname.clone(),
+                            query: serde_json::json!({ "_id": self.lock_id(), "owner": self.owner, "expires": { "$gt": now } }).to_string(),
+                            document: serde_json::json!({ "$set": { "expires": expires } }).to_string(),
+                            ..Default::default()
+                        },
+                        env,
+                    )
+                    .await?;
+                let modified = renewed.opresult.map(|r| r.modified_count).unwrap_or_default();
+                return Ok(modified > 0);
+            }
+            if current["expires"].as_u64().unwrap_or_default() > now {
+                return Ok(false);
+            }
+            // expired, only remove it if nobody renewed it in the meantime
+            self.client
+                .delete_many(
+                    DeleteManyRequest {
+                        collectionname: self.options.collectionname.clone(),
+                        query: serde_json::json!({ "_id": self.lock_id(), "expires": { "$lte": now } }).to_string(),
+                        ..Default::default()
+                    },
+                    env.clone(),
+                )
+                .await?;
+        }
+        // the _id is fixed, so only one client can insert the lock
+        let inserted = self
+            .client
+            .insert_one(
+                InsertOneRequest {
+                    collectionname: self.options.collectionname.clone(),
+                    item: lock.to_string(),
+                    ..Default::default()
+                },
+                env,
+            )
+            .await;
+        Ok(inserted.is_ok())
+    }
+    async fn release(&self) {
+        let request = DeleteManyRequest {
+            collectionname: self.options.collectionname.clone(),
+            query: serde_json::json!({ "_id": self.lock_id(), "owner": self.owner }).to_string(),
+            ..Default::default()
+        };
+        if let Err(e) = self.client.delete_many(request, EnvConfig::new()).await {
+            debug!("Failed to release scheduler lock: {}", e);
+        }
+    }
+    async fn load_lastrun(&self, name: &str) -> Result<Option<SystemTime>, OpenIAPError> {
+        let response = self
+            .client
+            .query(
+                QueryRequest {
+                    collectionname: self.options.collectionname.clone(),
+                    query: serde_json::json!({ "_type": "workitemschedule", "scheduler": self.options.name, "name": name }).to_string(),
+                    top: 1,
+                    ..Default::default()
+                },
+                EnvConfig::new(),
+            )
+            .await?;
+        let items: serde_json::Value = serde_json::from_str(&response.results).unwrap_or_default();
+        Ok(items[0]["lastrun"].as_u64().map(from_millis))
+    }
+    async fn save_lastrun(&self, name: &str, lastrun: SystemTime) -> Result<(), OpenIAPError> {
+        let item = serde_json::json!({
+            "_type": "workitemschedule",
+            "scheduler": self.options.name,
+            "name": name,
+            "lastrun": to_millis(lastrun),
+        });
+        self.client
+            .insert_or_update_one(
+                InsertOrUpdateOneRequest {
+                    collectionname: self.options.collectionname.clone(),
+                    uniqeness: "_type,scheduler,name".to_string(),
+                    item: item.to_string(),
+                    ..Default::default()
+                },
+                EnvConfig::new(),
+            )
+            .await?;
+        Ok(())
+    }
+    async fn tick(&self, lastruns: &mut HashMap<String, SystemTime>) -> Result<(), OpenIAPError> {
+        let now = SystemTime::now();
+        for schedule in self.schedules.iter() {
+            let lastrun = match lastruns.get(&schedule.name) {
+                Some(lastrun) => *lastrun,
+                None => match self.load_lastrun(&schedule.name).await? {
+                    Some(lastrun) => lastrun,
+                    None => {
+                        // a new schedule starts now instead of catching up since 1970
+                        self.save_lastrun(&schedule.name, now).await?;
+                        now
+                    }
+                },
+            };
+            lastruns.insert(schedule.name.clone(), lastrun);
+            let (runs, latest) = schedule.due_runs(lastrun, now);
+            for run in runs.iter() {
+                let mut request = schedule.request.clone();
+                request.nextrun = Some(systemtime_to_timestamp(*run));
+                self.client.push_workitem(request, EnvConfig::new()).await?;
+                debug!("Pushed scheduled workitem {} for {:?}", schedule.name, run);
+                // saved after every push, so a failure midway does not push the same runs again
+                self.save_lastrun(&schedule.name, *run).await?;
+                lastruns.insert(schedule.name.clone(), *run);
+            }
+            if let Some(latest) = latest {
+                if lastruns.get(&schedule.name) != Some(&latest) {
+                    self.save_lastrun(&schedule.name, latest).await?;
+                    lastruns.insert(schedule.name.clone(), latest);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Run the scheduler until stopped.
+    pub async fn run(&self) {
+        let mut shutdown = self.shutdown.subscribe();
+        let mut leader = false;
+        let mut lastruns: HashMap<String, SystemTime> = HashMap::new();
+        while !*shutdown.borrow() {
+            match self.elect().await {
+                Ok(elected) => {
+                    if elected != leader {
+                        info!("Scheduler {} leader: {}", self.options.name, elected);
+                        // another client may have pushed while we were not leader
+                        lastruns.clear();
+                    }
+                    leader = elected;
+                }
+                Err(e) => {
+                    error!("Scheduler {} election failed: {}", self.options.name, e);
+                    leader = false;
+                }
+            }
+            if leader {
+                if let Err(e) = self.tick(&mut lastruns).await {
+                    error!("Scheduler {} failed: {}", self.options.name, e);
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(self.options.interval) => {},
+                _ = shutdown.changed() => {},
+            }
+        }
+        if leader {
+            self.release().await;
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use zeroize::Zeroize;
+
+/// A credential, like a password or jwt.
+/// `Debug` and `Display` never show the value, and the memory is overwritten when it is dropped.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+impl Secret {
+    /// Wrap a credential.
+    pub fn new(value: &str) -> Self {
+        Self(value.to_string())
+    }
+    /// The credential itself, avoid keeping copies of it around.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+    /// True if no credential is set.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret({})", openiap_proto::signin::redact(&self.0))
+    }
+}
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(openiap_proto::signin::redact(&self.0))
+    }
+}
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
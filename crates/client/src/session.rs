@@ -0,0 +1,142 @@
+use base64::Engine;
+use openiap_proto::errors::OpenIAPError;
+use openiap_proto::openiap::{SigninRequest, User};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+use crate::{Client, ClientEvent, ClientState, Secret};
+
+/// Claims of a JWT token issued by the OpenIAP server, see `Client::session` and `decode_jwt_claims`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionClaims {
+    /// Id of the user the token was issued to.
+    pub userid: String,
+    /// Username of the user.
+    pub username: String,
+    /// Name of the user.
+    pub name: String,
+    /// Names of the roles the user is a member of.
+    pub roles: Vec<String>,
+    /// Id of the customer the user belongs to, if any.
+    pub customerid: String,
+    /// Issuer of the token.
+    pub issuer: String,
+    /// When the token was issued.
+    pub issued_at: Option<SystemTime>,
+    /// When the token expires.
+    pub expires_at: Option<SystemTime>,
+    /// The full decoded payload.
+    pub claims: Value,
+}
+impl SessionClaims {
+    /// Time left before the token expires, zero once it has expired and None if it does not expire.
+    pub fn expires_in(&self) -> Option<Duration> {
+        self.expires_at.map(|expires_at| expires_at.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+    /// True if the token has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires_in() == Some(Duration::ZERO)
+    }
+}
+
+/// Decode the claims of a JWT token, without verifying the signature.
+/// Use `Client::validate_token` to have the server check the token.
+pub fn decode_jwt_claims(jwt: &str) -> Result<SessionClaims, OpenIAPError> {
+    let payload = jwt.split('.').nth(1)
+        .ok_or_else(|| OpenIAPError::ClientError("Invalid JWT, expected three parts".to_string()))?;
+    let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|e| OpenIAPError::ClientError(format!("Invalid JWT payload: {}", e)))?;
+    let claims: Value = serde_json::from_slice(&payload)
+        .map_err(|e| OpenIAPError::ClientError(format!("Invalid JWT payload: {}", e)))?;
+    // the server puts the user in "data", older versions in "data.user"
+    let user = match &claims["data"]["user"] {
+        Value::Object(_) => &claims["data"]["user"],
+        _ => &claims["data"],
+    };
+    let text = |value: &Value| value.as_str().unwrap_or_default().to_string();
+    let time = |value: &Value| value.as_u64().map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    let mut userid = text(&user["_id"]);
+    if userid.is_empty() {
+        userid = text(&claims["sub"]);
+    }
+    let roles = user["roles"].as_array()
+        .map(|roles| roles.iter().map(|role| text(&role["name"])).filter(|name| !name.is_empty()).collect())
+        .unwrap_or_default();
+    Ok(SessionClaims {
+        userid,
+        username: text(&user["username"]),
+        name: text(&user["name"]),
+        roles,
+        customerid: text(&user["customerid"]),
+        issuer: text(&claims["iss"]),
+        issued_at: time(&claims["iat"]),
+        expires_at: time(&claims["exp"]),
+        claims,
+    })
+}
+
+impl Client {
+    /// Set the token issued by the server for the current session.
+    pub(crate) fn set_session_jwt(&self, jwt: &str) {
+        *self.session_jwt.lock().unwrap() = Secret::new(jwt);
+    }
+    /// The token issued by the server at sign in, or by the latest token refresh. Empty if not signed in.
+    pub fn get_session_jwt(&self) -> Secret {
+        self.session_jwt.lock().unwrap().clone()
+    }
+    /// Return the decoded claims of the current session token, like expiry, user id and roles.
+    pub fn session(&self) -> Result<SessionClaims, OpenIAPError> {
+        let jwt = self.get_session_jwt();
+        if jwt.is_empty() {
+            return Err(OpenIAPError::ClientError("Not signed in".to_string()));
+        }
+        decode_jwt_claims(jwt.expose())
+    }
+    /// Have the server validate `jwt` and return the user it belongs to.
+    /// The client stays signed in as the current user.
+    #[tracing::instrument(skip_all)]
+    pub async fn validate_token(&self, jwt: &str) -> Result<User, OpenIAPError> {
+        if jwt.is_empty() {
+            return Err(OpenIAPError::ClientError("No token provided".to_string()));
+        }
+        let request = SigninRequest {
+            validateonly: true,
+            ..SigninRequest::with_jwt(jwt)
+        };
+        let response = self.signin(request).await?;
+        response.user.ok_or_else(|| OpenIAPError::ServerError("Token validation returned no user".to_string()))
+    }
+    /// True after `signout`, until credentials are given again or the client signs in.
+    pub fn is_signed_out(&self) -> bool {
+        *self.signed_out.lock().unwrap()
+    }
+    pub(crate) fn set_signed_out(&self, signed_out: bool) {
+        *self.signed_out.lock().unwrap() = signed_out;
+    }
+    /// Forget the signed in user and the credentials used to sign in, drop the connection and emit `ClientEvent::SignedOut`.
+    /// The server has no sign out command, so the connection is closed to drop the identity, and the client
+    /// reconnects as guest if auto reconnect is enabled. Watches and queues must be registered again after that.
+    /// The credential provider is kept, but not used until `set_credential_provider` or `clear_credential_provider`
+    /// is called, or the client signs in with `signin`.
+    #[tracing::instrument(skip_all)]
+    pub async fn signout(&self) -> Result<(), OpenIAPError> {
+        if self.get_state() != ClientState::Signedin {
+            return Err(OpenIAPError::ClientError("Not signed in".to_string()));
+        }
+        self.set_signed_out(true);
+        self.set_username("");
+        self.set_password("");
+        self.set_jwt("");
+        self.set_session_jwt("");
+        self.set_user(None);
+        self.set_connected(ClientState::Disconnected, Some("Signed out"));
+        debug!("Signed out");
+        if let Err(e) = self.event_sender.send(ClientEvent::SignedOut).await {
+            debug!("Failed to send signed out event: {}", e);
+        }
+        Ok(())
+    }
+}
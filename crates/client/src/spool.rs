@@ -0,0 +1,292 @@
+use opentelemetry_proto::tonic::collector::logs::v1::{logs_service_client::LogsServiceClient, ExportLogsServiceRequest};
+use opentelemetry_proto::tonic::collector::metrics::v1::{metrics_service_client::MetricsServiceClient, ExportMetricsServiceRequest};
+use opentelemetry_proto::transform::common::tonic::ResourceAttributesWithSchema;
+use opentelemetry_proto::transform::logs::tonic::group_logs_by_resource_and_scope;
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::logs::{LogBatch, LogExporter};
+use opentelemetry_sdk::metrics::data::ResourceMetrics;
+use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+use opentelemetry_sdk::metrics::Temporality;
+use opentelemetry_sdk::Resource;
+use prost::Message;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use tonic::codegen::InterceptedService;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tonic::transport::Channel;
+
+/// Number of spooled exports sent after each successful export.
+const REPLAY_BATCH: usize = 10;
+
+/// A bounded folder of encoded OTLP export requests, kept while the collector is unreachable.
+#[derive(Debug, Clone)]
+pub struct TelemetrySpool {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+static SPOOL_SEQ: AtomicU64 = AtomicU64::new(0);
+impl TelemetrySpool {
+    /// Use `dir` as spool, creating it if needed.
+    pub fn new(dir: &Path, max_bytes: u64) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self { dir: dir.to_path_buf(), max_bytes })
+    }
+    /// Store an encoded request of `kind`, like "metrics" or "logs", then drop the oldest files if the spool is too big.
+    pub fn push(&self, kind: &str, data: &[u8]) -> std::io::Result<()> {
+        let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let seq = SPOOL_SEQ.fetch_add(1, Ordering::Relaxed) % 1_000_000;
+        let path = self.dir.join(format!("{:024}{:06}.{}", nanos, seq, kind));
+        std::fs::write(path, data)?;
+        self.enforce_limit()
+    }
+    /// Spooled files of `kind`, oldest first.
+    pub fn pending(&self, kind: &str) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.files().into_iter()
+            .map(|(path, _)| path)
+            .filter(|path| path.extension().and_then(|e| e.to_str()) == Some(kind))
+            .collect();
+        files.sort();
+        files
+    }
+    /// Total size of the spool in bytes.
+    pub fn size(&self) -> u64 {
+        self.files().iter().map(|(_, size)| size).sum()
+    }
+    fn files(&self) -> Vec<(PathBuf, u64)> {
+        match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| Some((entry.path(), entry.metadata().ok().filter(|m| m.is_file())?.len())))
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+    /// `push` without blocking the async executor.
+    pub async fn push_async(&self, kind: &str, data: Vec<u8>) -> std::io::Result<()> {
+        let spool = self.clone();
+        let kind = kind.to_string();
+        blocking(move || spool.push(&kind, &data)).await
+    }
+    /// `pending` without blocking the async executor.
+    pub async fn pending_async(&self, kind: &str) -> Vec<PathBuf> {
+        let spool = self.clone();
+        let kind = kind.to_string();
+        blocking(move || Ok(spool.pending(&kind))).await.unwrap_or_default()
+    }
+    fn enforce_limit(&self) -> std::io::Result<()> {
+        let mut files = self.files();
+        // names start with the time they were written, so sorting by name puts the oldest first
+        files.sort();
+        let mut size: u64 = files.iter().map(|(_, size)| size).sum();
+        for (path, length) in files {
+            if size <= self.max_bytes {
+                break;
+            }
+            std::fs::remove_file(path)?;
+            size -= length;
+        }
+        Ok(())
+    }
+}
+
+/// Run file IO on the blocking thread pool. The batch processors export on their own thread,
+/// without a tokio runtime, so there it just runs in place.
+async fn blocking<T, F>(f: F) -> std::io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> std::io::Result<T> + Send + 'static,
+{
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => handle.spawn_blocking(f).await.map_err(std::io::Error::other)?,
+        Err(_) => f(),
+    }
+}
+async fn read_spooled(path: &Path) -> std::io::Result<Vec<u8>> {
+    let path = path.to_path_buf();
+    blocking(move || std::fs::read(path)).await
+}
+async fn remove_spooled(path: &Path) {
+    let path = path.to_path_buf();
+    let _ = blocking(move || std::fs::remove_file(path)).await;
+}
+
+/// Adds the configured headers to replayed requests.
+#[derive(Clone)]
+pub struct HeaderInterceptor(MetadataMap);
+impl tonic::service::Interceptor for HeaderInterceptor {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> {
+        for entry in self.0.iter() {
+            if let tonic::metadata::KeyAndValueRef::Ascii(key, value) = entry {
+                request.metadata_mut().insert(key.clone(), value.clone());
+            }
+        }
+        Ok(request)
+    }
+}
+impl std::fmt::Debug for HeaderInterceptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // header values may hold credentials
+        f.write_str("HeaderInterceptor")
+    }
+}
+
+/// Turn configured headers into gRPC metadata, invalid headers are reported as an error.
+pub fn headers_to_metadata(headers: &HashMap<String, String>) -> Result<MetadataMap, String> {
+    let mut metadata = MetadataMap::new();
+    for (name, value) in headers {
+        let key = MetadataKey::from_bytes(name.to_lowercase().as_bytes())
+            .map_err(|e| format!("Invalid header name {}: {}", name, e))?;
+        let value = MetadataValue::try_from(value.as_str())
+            .map_err(|e| format!("Invalid header value for {}: {}", name, e))?;
+        metadata.insert(key, value);
+    }
+    Ok(metadata)
+}
+
+/// Open a lazy channel to an OTLP endpoint, used to send spooled data.
+/// Must be called inside a tokio runtime.
+fn replay_channel(endpoint: &str) -> Result<Channel, String> {
+    let tls = tonic::transport::ClientTlsConfig::new().with_native_roots();
+    let endpoint = Channel::from_shared(endpoint.to_string())
+        .map_err(|e| format!("Invalid endpoint {}: {}", endpoint, e))?;
+    let endpoint = if endpoint.uri().scheme_str() == Some("https") {
+        endpoint.tls_config(tls).map_err(|e| format!("Failed to configure tls: {}", e))?
+    } else {
+        endpoint
+    };
+    Ok(endpoint.connect_lazy())
+}
+
+/// Metric exporter that writes to the spool when the inner exporter fails, and sends the spooled data once exports succeed again.
+pub struct SpoolingMetricExporter<E: PushMetricExporter> {
+    inner: E,
+    spool: Option<TelemetrySpool>,
+    client: Option<MetricsServiceClient<InterceptedService<Channel, HeaderInterceptor>>>,
+}
+impl<E: PushMetricExporter> SpoolingMetricExporter<E> {
+    /// Wrap `inner`, spooling to `spool` if set. Spooled data is sent to `endpoint` with `metadata`.
+    pub fn new(inner: E, spool: Option<TelemetrySpool>, endpoint: &str, metadata: MetadataMap) -> Result<Self, String> {
+        let client = match spool {
+            Some(_) => Some(MetricsServiceClient::with_interceptor(replay_channel(endpoint)?, HeaderInterceptor(metadata))),
+            None => None,
+        };
+        Ok(Self { inner, spool, client })
+    }
+    async fn replay(&self) {
+        let (Some(spool), Some(client)) = (&self.spool, &self.client) else {
+            return;
+        };
+        for path in spool.pending_async("metrics").await.into_iter().take(REPLAY_BATCH) {
+            let request = match read_spooled(&path).await.map(|data| ExportMetricsServiceRequest::decode(data.as_slice())) {
+                Ok(Ok(request)) => request,
+                _ => {
+                    remove_spooled(&path).await;
+                    continue;
+                }
+            };
+            if client.clone().export(request).await.is_err() {
+                return;
+            }
+            remove_spooled(&path).await;
+        }
+    }
+}
+#[async_trait::async_trait]
+impl<E: PushMetricExporter> PushMetricExporter for SpoolingMetricExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> OTelSdkResult {
+        match self.inner.export(metrics).await {
+            Ok(()) => {
+                self.replay().await;
+                Ok(())
+            }
+            Err(e) => match &self.spool {
+                Some(spool) => {
+                    let request = ExportMetricsServiceRequest::from(&*metrics);
+                    spool.push_async("metrics", request.encode_to_vec()).await
+                        .map_err(|spool_error| opentelemetry_sdk::error::OTelSdkError::InternalFailure(
+                            format!("{}, and failed to spool metrics: {}", e, spool_error)))
+                }
+                None => Err(e),
+            },
+        }
+    }
+    async fn force_flush(&self) -> OTelSdkResult {
+        self.inner.force_flush().await
+    }
+    fn shutdown(&self) -> OTelSdkResult {
+        self.inner.shutdown()
+    }
+    fn temporality(&self) -> Temporality {
+        self.inner.temporality()
+    }
+}
+
+/// Log exporter that writes to the spool when the inner exporter fails, and sends the spooled data once exports succeed again.
+/// It does not log itself, as that would feed back into the exporter.
+#[derive(Debug)]
+pub struct SpoolingLogExporter<E: LogExporter> {
+    inner: E,
+    spool: Option<TelemetrySpool>,
+    client: Option<LogsServiceClient<InterceptedService<Channel, HeaderInterceptor>>>,
+    resource: ResourceAttributesWithSchema,
+}
+impl<E: LogExporter> SpoolingLogExporter<E> {
+    /// Wrap `inner`, spooling to `spool` if set. Spooled data is sent to `endpoint` with `metadata`.
+    pub fn new(inner: E, spool: Option<TelemetrySpool>, endpoint: &str, metadata: MetadataMap) -> Result<Self, String> {
+        let client = match spool {
+            Some(_) => Some(LogsServiceClient::with_interceptor(replay_channel(endpoint)?, HeaderInterceptor(metadata))),
+            None => None,
+        };
+        Ok(Self { inner, spool, client, resource: ResourceAttributesWithSchema::default() })
+    }
+    async fn replay(&self) {
+        let (Some(spool), Some(client)) = (&self.spool, &self.client) else {
+            return;
+        };
+        for path in spool.pending_async("logs").await.into_iter().take(REPLAY_BATCH) {
+            let request = match read_spooled(&path).await.map(|data| ExportLogsServiceRequest::decode(data.as_slice())) {
+                Ok(Ok(request)) => request,
+                _ => {
+                    remove_spooled(&path).await;
+                    continue;
+                }
+            };
+            if client.clone().export(request).await.is_err() {
+                return;
+            }
+            remove_spooled(&path).await;
+        }
+    }
+}
+impl<E: LogExporter> LogExporter for SpoolingLogExporter<E> {
+    async fn export(&self, batch: LogBatch<'_>) -> OTelSdkResult {
+        let Some(spool) = &self.spool else {
+            return self.inner.export(batch).await;
+        };
+        // keep the records, so they can be spooled if the export fails
+        let records: Vec<_> = batch.iter().collect();
+        match self.inner.export(LogBatch::new(&records)).await {
+            Ok(()) => {
+                self.replay().await;
+                Ok(())
+            }
+            Err(e) => {
+                let request = ExportLogsServiceRequest {
+                    resource_logs: group_logs_by_resource_and_scope(LogBatch::new(&records), &self.resource),
+                };
+                spool.push_async("logs", request.encode_to_vec()).await
+                    .map_err(|spool_error| opentelemetry_sdk::error::OTelSdkError::InternalFailure(
+                        format!("{}, and failed to spool logs: {}", e, spool_error)))
+            }
+        }
+    }
+    fn shutdown(&mut self) -> OTelSdkResult {
+        self.inner.shutdown()
+    }
+    fn set_resource(&mut self, resource: &Resource) {
+        self.resource = resource.into();
+        self.inner.set_resource(resource);
+    }
+}
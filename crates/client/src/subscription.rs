@@ -0,0 +1,211 @@
+use futures::Stream;
+use openiap_proto::errors::OpenIAPError;
+use openiap_proto::openiap::{PopWorkitemRequest, RegisterQueueRequest, UpdateWorkitemRequest, WatchRequest, Workitem};
+use openiap_proto::workitem::WorkitemState;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, trace};
+
+use crate::{Client, EnvConfig};
+
+/// Options for `Client::subscribe_workitems`.
+#[derive(Debug, Clone)]
+pub struct WorkitemSubscriptionOptions {
+    /// Fallback poll interval, used in case a notification is lost.
+    pub poll_interval: Duration,
+    /// Folder passed to `pop_workitem` for downloading workitem files.
+    pub download_folder: Option<String>,
+    /// Register on the queue's `amqpqueue` instead of watching the workitems collection.
+    /// The registration consumes the messages on that queue, so robots and agents listening
+    /// on the same `amqpqueue` will miss them. Only enable this when nothing else uses the queue.
+    pub use_amqpqueue: bool,
+}
+impl Default for WorkitemSubscriptionOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(60),
+            download_folder: None,
+            use_amqpqueue: false,
+        }
+    }
+}
+/// How a `WorkitemSubscription` gets notified about new workitems.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkitemNotification {
+    /// Registered on the queue's `amqpqueue`, holds the queue name.
+    Queue(String),
+    /// Watching the workitems collection, holds the watch id.
+    Watch(String),
+    /// No notification could be set up, only polling.
+    Poll,
+}
+/// A stream of workitems popped from a workitem queue as they arrive.
+/// Dropping the stream stops popping and removes the queue registration or watch.
+pub struct WorkitemSubscription {
+    receiver: mpsc::Receiver<Workitem>,
+    _handle: JoinHandle<()>,
+    notification: WorkitemNotification,
+}
+impl WorkitemSubscription {
+    /// How this subscription receives notifications.
+    pub fn notification(&self) -> &WorkitemNotification {
+        &self.notification
+    }
+}
+impl Stream for WorkitemSubscription {
+    type Item = Workitem;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+impl Drop for WorkitemSubscription {
+    fn drop(&mut self) {
+        // closing the receiver makes the pop loop exit and clean up
+        self.receiver.close();
+        trace!("WorkitemSubscription dropped");
+    }
+}
+
+/// Build the watch pipeline used by `subscribe_workitems`, so the server only streams new workitems in `wiq`.
+pub fn workitem_watch_pipeline(wiq: &str) -> Vec<String> {
+    let pipeline = serde_json::json!([
+        { "$match": {
+            "fullDocument._type": "workitem",
+            "fullDocument.wiq": wiq,
+            "fullDocument.state": WorkitemState::New.as_str(),
+        } }
+    ]);
+    vec![pipeline.to_string()]
+}
+
+impl Client {
+    /// Subscribe to a workitem queue, and receive workitems as a `Stream` when they arrive.
+    /// Watches the workitems collection, or registers on the queue's `amqpqueue` when `use_amqpqueue` is set.
+    /// The queue is also polled every `poll_interval` as a safety net.
+    #[tracing::instrument(skip_all)]
+    pub async fn subscribe_workitems(
+        &self,
+        env: EnvConfig,
+        wiq: &str,
+        options: WorkitemSubscriptionOptions,
+    ) -> Result<WorkitemSubscription, OpenIAPError> {
+        if wiq.is_empty() {
+            return Err(OpenIAPError::ClientError("No queue name provided".to_string()));
+        }
+        let notify = Arc::new(Notify::new());
+        let mut amqpqueue = String::new();
+        if options.use_amqpqueue {
+            if let Some(queue) = self.get_workitem_queue(env.clone(), wiq).await? {
+                amqpqueue = queue.amqpqueue;
+            }
+        }
+        let notification = if !amqpqueue.is_empty() {
+            let n = notify.clone();
+            let queuename = self
+                .register_queue(
+                    RegisterQueueRequest::byqueuename(&amqpqueue),
+                    env.clone(),
+                    Arc::new(move |_client, _event| {
+                        n.notify_one();
+                        Box::pin(async { None })
+                    }),
+                )
+                .await?;
+            WorkitemNotification::Queue(queuename)
+        } else {
+            let n = notify.clone();
+            let name = wiq.to_string();
+            let result = self
+                .watch(
+                    WatchRequest::new("workitems", workitem_watch_pipeline(wiq)),
+                    env.clone(),
+                    Box::new(move |event| {
+                        let document: serde_json::Value =
+                            serde_json::from_str(&event.document).unwrap_or_default();
+                        if document["wiq"].as_str() == Some(name.as_str())
+                            && document["state"].as_str() == Some(WorkitemState::New.as_str())
+                        {
+                            n.notify_one();
+                        }
+                    }),
+                )
+                .await;
+            match result {
+                Ok(id) => WorkitemNotification::Watch(id),
+                Err(e) => {
+                    error!("Failed to watch workitems, falling back to polling: {}", e);
+                    WorkitemNotification::Poll
+                }
+            }
+        };
+        debug!("Subscribed to {} using {:?}", wiq, notification);
+        // capacity 1, so we never hold more than one popped workitem the consumer has not asked for
+        let (sender, receiver) = mpsc::channel(1);
+        let client = self.clone();
+        let wiq = wiq.to_string();
+        let cleanup = notification.clone();
+        let handle = tokio::task::spawn(async move {
+            'outer: loop {
+                loop {
+                    let popped = client
+                        .pop_workitem(
+                            PopWorkitemRequest::bywiq(&wiq),
+                            env.clone(),
+                            options.download_folder.as_deref(),
+                        )
+                        .await;
+                    match popped {
+                        Ok(response) => match response.workitem {
+                            Some(workitem) => {
+                                if let Err(mpsc::error::SendError(mut workitem)) = sender.send(workitem).await {
+                                    // the stream was dropped, hand the workitem back to the queue.
+                                    // processing to new is not a valid transition, but this workitem was never
+                                    // handed to the consumer, so it goes back as if it was never popped
+                                    workitem.state = WorkitemState::New.to_string();
+                                    let request = UpdateWorkitemRequest {
+                                        workitem: Some(workitem),
+                                        ignoremaxretries: true,
+                                        ..Default::default()
+                                    };
+                                    if let Err(e) = client.update_workitem(request, env.clone()).await {
+                                        error!("Failed to return workitem to {}: {}", wiq, e);
+                                    }
+                                    break 'outer;
+                                }
+                            }
+                            None => break,
+                        },
+                        Err(e) => {
+                            error!("Failed to pop workitem from {}: {}", wiq, e);
+                            break;
+                        }
+                    }
+                }
+                tokio::select! {
+                    _ = notify.notified() => {},
+                    _ = tokio::time::sleep(options.poll_interval) => {},
+                    _ = sender.closed() => break,
+                }
+            }
+            let result = match &cleanup {
+                WorkitemNotification::Queue(queuename) => {
+                    client.unregister_queue(env.clone(), queuename).await
+                }
+                WorkitemNotification::Watch(id) => client.unwatch(env.clone(), id).await,
+                WorkitemNotification::Poll => Ok(()),
+            };
+            if let Err(e) = result {
+                debug!("Failed to clean up workitem subscription: {}", e);
+            }
+        });
+        Ok(WorkitemSubscription {
+            receiver,
+            _handle: handle,
+            notification,
+        })
+    }
+}
@@ -0,0 +1,422 @@
+use futures::StreamExt;
+use openiap_proto::errors::OpenIAPError;
+use openiap_proto::openiap::{DeleteOneRequest, DownloadRequest, QueryRequest, UploadRequest};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use tracing::{debug, info};
+
+use crate::{Client, EnvConfig};
+
+/// Which side of a `sync_dir` call is the source of truth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncDirection {
+    /// Mirror the local folder into GridFS.
+    Upload,
+    /// Mirror GridFS into the local folder.
+    Download,
+}
+/// Options for `Client::sync_dir`.
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Remove files on the target side that do not exist on the source side.
+    pub delete_extras: bool,
+    /// Only compute and log the plan, do not transfer or delete anything.
+    pub dry_run: bool,
+    /// Maximum number of transfers running at the same time.
+    pub parallelism: usize,
+    /// The GridFS files collection, defaults to `fs.files`.
+    pub collectionname: String,
+}
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            delete_extras: false,
+            dry_run: false,
+            parallelism: 4,
+            collectionname: "fs.files".to_string(),
+        }
+    }
+}
+/// A file seen on either side of a sync, keyed by its path relative to the synced root.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncEntry {
+    /// Path relative to the local folder / remote prefix, always using `/` as separator.
+    pub path: String,
+    /// File size in bytes.
+    pub size: u64,
+    /// Hex encoded md5 checksum, empty if unknown.
+    pub checksum: String,
+    /// The `_id` in the files collection, empty for local files.
+    pub id: String,
+}
+/// The operation `sync_dir` will perform for one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncActionKind {
+    /// Upload the local file to GridFS.
+    Upload,
+    /// Download the remote file into the local folder.
+    Download,
+    /// Delete the remote file.
+    DeleteRemote,
+    /// Delete the local file.
+    DeleteLocal,
+}
+/// One planned step of a sync.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncAction {
+    /// What to do.
+    pub kind: SyncActionKind,
+    /// Path relative to the synced root.
+    pub path: String,
+    /// Size of the source file in bytes.
+    pub size: u64,
+    /// `_id`s of existing remote files with this name, replaced or removed by this action.
+    pub remote_ids: Vec<String>,
+}
+/// The result of `Client::sync_dir`.
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Every action in the plan, also filled when `dry_run` is set.
+    pub actions: Vec<SyncAction>,
+    /// Number of files that already matched and were skipped.
+    pub unchanged: usize,
+    /// Number of actions that completed.
+    pub completed: usize,
+    /// Actions that failed, as (path, error message).
+    pub errors: Vec<(String, String)>,
+}
+
+/// Compare both sides and return the actions needed to make the target match the source.
+/// Files match when size is equal and both checksums are known and equal.
+pub fn plan_sync(
+    local: &[SyncEntry],
+    remote: &[SyncEntry],
+    direction: SyncDirection,
+    delete_extras: bool,
+) -> (Vec<SyncAction>, usize) {
+    let mut remote_by_path: HashMap<&str, Vec<&SyncEntry>> = HashMap::new();
+    for entry in remote {
+        remote_by_path.entry(entry.path.as_str()).or_default().push(entry);
+    }
+    let local_by_path: HashMap<&str, &SyncEntry> =
+        local.iter().map(|e| (e.path.as_str(), e)).collect();
+    let same = |a: &SyncEntry, b: &SyncEntry| {
+        a.size == b.size && !a.checksum.is_empty() && a.checksum == b.checksum
+    };
+    let mut actions = Vec::new();
+    let mut unchanged = 0;
+    match direction {
+        SyncDirection::Upload => {
+            for l in local {
+                let existing = remote_by_path.get(l.path.as_str());
+                match existing {
+                    Some(r) if r.len() == 1 && same(l, r[0]) => unchanged += 1,
+                    _ => actions.push(SyncAction {
+                        kind: SyncActionKind::Upload,
+                        path: l.path.clone(),
+                        size: l.size,
+                        remote_ids: existing
+                            .map(|r| r.iter().map(|e| e.id.clone()).collect())
+                            .unwrap_or_default(),
+                    }),
+                }
+            }
+            if delete_extras {
+                for (path, entries) in remote_by_path.iter() {
+                    if !local_by_path.contains_key(path) {
+                        actions.push(SyncAction {
+                            kind: SyncActionKind::DeleteRemote,
+                            path: path.to_string(),
+                            size: entries[0].size,
+                            remote_ids: entries.iter().map(|e| e.id.clone()).collect(),
+                        });
+                    }
+                }
+            }
+        }
+        SyncDirection::Download => {
+            for (path, entries) in remote_by_path.iter() {
+                // GridFS allows duplicate names, the last one listed is the newest upload
+                let newest = entries[entries.len() - 1];
+                match local_by_path.get(path) {
+                    Some(l) if same(l, newest) => unchanged += 1,
+                    _ => actions.push(SyncAction {
+                        kind: SyncActionKind::Download,
+                        path: path.to_string(),
+                        size: newest.size,
+                        remote_ids: vec![newest.id.clone()],
+                    }),
+                }
+            }
+            if delete_extras {
+                for l in local {
+                    if !remote_by_path.contains_key(l.path.as_str()) {
+                        actions.push(SyncAction {
+                            kind: SyncActionKind::DeleteLocal,
+                            path: l.path.clone(),
+                            size: l.size,
+                            remote_ids: vec![],
+                        });
+                    }
+                }
+            }
+        }
+    }
+    actions.sort_by(|a, b| a.path.cmp(&b.path));
+    (actions, unchanged)
+}
+
+/// Return the hex encoded md5 checksum of a file.
+pub(crate) fn file_checksum(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = md5::Context::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.consume(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.compute()))
+}
+/// Resolve a sync path below `root`.
+/// Remote filenames are not trusted, so paths that are absolute or contain `..` are rejected instead of escaping the folder.
+pub fn sync_local_path(root: &Path, path: &str) -> Result<PathBuf, OpenIAPError> {
+    let relative = Path::new(path);
+    let safe = !path.is_empty()
+        && relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !safe {
+        return Err(OpenIAPError::ClientError(format!("Refusing to sync unsafe path {}", path)));
+    }
+    Ok(root.join(relative))
+}
+fn remote_name(remote_prefix: &str, path: &str) -> String {
+    if remote_prefix.is_empty() || remote_prefix.ends_with('/') {
+        format!("{}{}", remote_prefix, path)
+    } else {
+        format!("{}/{}", remote_prefix, path)
+    }
+}
+fn escape_regex(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+fn walk_local(root: &Path, dir: &Path, entries: &mut Vec<SyncEntry>) -> std::io::Result<()> {
+    for item in std::fs::read_dir(dir)? {
+        let item = item?;
+        let path = item.path();
+        let meta = item.metadata()?;
+        if meta.is_dir() {
+            walk_local(root, &path, entries)?;
+        } else if meta.is_file() {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            let relative = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+                .join("/");
+            entries.push(SyncEntry {
+                path: relative,
+                size: meta.len(),
+                checksum: file_checksum(&path)?,
+                id: String::new(),
+            });
+        }
+    }
+    Ok(())
+}
+
+impl Client {
+    /// List the files in GridFS whose filename starts with `remote_prefix`.
+    /// Returned paths are relative to the prefix, ordered by upload date.
+    #[tracing::instrument(skip_all)]
+    pub async fn list_remote_files(
+        &self,
+        collectionname: &str,
+        remote_prefix: &str,
+        env: EnvConfig,
+    ) -> Result<Vec<SyncEntry>, OpenIAPError> {
+        let prefix = remote_name(remote_prefix, "");
+        let query = serde_json::json!({ "filename": { "$regex": format!("^{}", escape_regex(&prefix)) } });
+        let top = 1000;
+        let mut skip = 0;
+        let mut result = Vec::new();
+        loop {
+            let q = QueryRequest {
+                collectionname: collectionname.to_string(),
+                query: query.to_string(),
+                projection: "{\"filename\":1,\"length\":1,\"md5\":1,\"metadata.checksum\":1,\"uploadDate\":1}".to_string(),
+                orderby: "{\"uploadDate\":1}".to_string(),
+                top,
+                skip,
+                ..Default::default()
+            };
+            let response = self.query(q, env.clone()).await?;
+            let items: Vec<serde_json::Value> = serde_json::from_str(&response.results)
+                .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse files: {}", e)))?;
+            let count = items.len() as i32;
+            for item in items {
+                let filename = item["filename"].as_str().unwrap_or_default();
+                let checksum = match item["metadata"]["checksum"].as_str() {
+                    Some(c) => c,
+                    None => item["md5"].as_str().unwrap_or_default(),
+                };
+                result.push(SyncEntry {
+                    path: filename[prefix.len().min(filename.len())..].to_string(),
+                    size: item["length"].as_u64().unwrap_or_default(),
+                    checksum: checksum.to_string(),
+                    id: item["_id"].as_str().unwrap_or_default().to_string(),
+                });
+            }
+            if count < top {
+                break;
+            }
+            skip += top;
+        }
+        Ok(result)
+    }
+    /// Mirror a local folder with the GridFS files whose name starts with `remote_prefix`.
+    /// Files are compared by size and md5 checksum, and only changed files are transferred.
+    #[tracing::instrument(skip_all)]
+    pub async fn sync_dir(
+        &self,
+        local: &str,
+        remote_prefix: &str,
+        direction: SyncDirection,
+        options: SyncOptions,
+        env: EnvConfig,
+    ) -> Result<SyncReport, OpenIAPError> {
+        let root = PathBuf::from(local);
+        if direction == SyncDirection::Download {
+            std::fs::create_dir_all(&root).map_err(|e| {
+                OpenIAPError::ClientError(format!("Failed to create folder {}: {}", local, e))
+            })?;
+        }
+        // hashing every file is blocking io, keep it off the async executor
+        let walk_root = root.clone();
+        let local_entries = tokio::task::spawn_blocking(move || {
+            let mut entries = Vec::new();
+            walk_local(&walk_root, &walk_root, &mut entries).map(|_| entries)
+        })
+        .await
+        .map_err(|e| OpenIAPError::ClientError(format!("Failed to read folder {}: {}", local, e)))?
+        .map_err(|e| {
+            OpenIAPError::ClientError(format!("Failed to read folder {}: {}", local, e))
+        })?;
+        let remote_entries = self
+            .list_remote_files(&options.collectionname, remote_prefix, env.clone())
+            .await?;
+        let (actions, unchanged) =
+            plan_sync(&local_entries, &remote_entries, direction, options.delete_extras);
+        let mut report = SyncReport {
+            actions: actions.clone(),
+            unchanged,
+            ..Default::default()
+        };
+        if options.dry_run {
+            for action in actions.iter() {
+                info!("{:?} {} ({} bytes)", action.kind, action.path, action.size);
+            }
+            info!("{} actions, {} files unchanged", actions.len(), unchanged);
+            return Ok(report);
+        }
+        let parallelism = options.parallelism.max(1);
+        let results = futures::stream::iter(actions.into_iter().map(|action| {
+            let client = self.clone();
+            let env = env.clone();
+            let root = root.clone();
+            let collectionname = options.collectionname.clone();
+            let remote_prefix = remote_prefix.to_string();
+            async move {
+                let path = action.path.clone();
+                let result = client
+                    .sync_action(&root, &remote_prefix, &collectionname, action, env)
+                    .await;
+                (path, result)
+            }
+        }))
+        .buffer_unordered(parallelism)
+        .collect::<Vec<_>>()
+        .await;
+        for (path, result) in results {
+            match result {
+                Ok(_) => report.completed += 1,
+                Err(e) => report.errors.push((path, e.to_string())),
+            }
+        }
+        Ok(report)
+    }
+    async fn sync_action(
+        &self,
+        root: &Path,
+        remote_prefix: &str,
+        collectionname: &str,
+        action: SyncAction,
+        env: EnvConfig,
+    ) -> Result<(), OpenIAPError> {
+        let localpath = sync_local_path(root, &action.path)?;
+        debug!("{:?} {}", action.kind, action.path);
+        match action.kind {
+            SyncActionKind::Upload => {
+                let checksum_path = localpath.clone();
+                let checksum = tokio::task::spawn_blocking(move || file_checksum(&checksum_path))
+                    .await
+                    .map_err(|e| OpenIAPError::ClientError(e.to_string()))?
+                    .map_err(|e| OpenIAPError::ClientError(e.to_string()))?;
+                let request = UploadRequest {
+                    filename: remote_name(remote_prefix, &action.path),
+                    collectionname: collectionname.to_string(),
+                    metadata: serde_json::json!({ "checksum": checksum }).to_string(),
+                    ..Default::default()
+                };
+                self.upload(request, env.clone(), localpath.to_str().unwrap_or_default())
+                    .await?;
+                // remove the versions we just replaced
+                for id in action.remote_ids {
+                    self.delete_remote_file(collectionname, &id, env.clone()).await?;
+                }
+            }
+            SyncActionKind::Download => {
+                let folder = localpath.parent().unwrap_or(root);
+                std::fs::create_dir_all(folder)
+                    .map_err(|e| OpenIAPError::ClientError(e.to_string()))?;
+                let filename = localpath.file_name().unwrap_or_default().to_string_lossy();
+                let request = DownloadRequest::by_id(collectionname, &action.remote_ids[0]);
+                self.download(request, env, folder.to_str(), Some(&filename)).await?;
+            }
+            SyncActionKind::DeleteRemote => {
+                for id in action.remote_ids {
+                    self.delete_remote_file(collectionname, &id, env.clone()).await?;
+                }
+            }
+            SyncActionKind::DeleteLocal => {
+                std::fs::remove_file(&localpath)
+                    .map_err(|e| OpenIAPError::ClientError(e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+    async fn delete_remote_file(
+        &self,
+        collectionname: &str,
+        id: &str,
+        env: EnvConfig,
+    ) -> Result<(), OpenIAPError> {
+        let request = DeleteOneRequest {
+            collectionname: collectionname.to_string(),
+            id: id.to_string(),
+            ..Default::default()
+        };
+        self.delete_one(request, env).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::Client;
+
+/// Endpoint anonymous usage statistics are sent to, unless `TelemetryConfig::analytics` is false.
+pub const DEFAULT_ANALYTICS_URL: &str = "https://otel.stats.openiap.io:443";
+
+/// Configure where and how the client exports telemetry, see `Client::set_telemetry_config`.
+/// Must be set before connecting, telemetry is initialized when the client connects.
+#[derive(Clone, PartialEq)]
+pub struct TelemetryConfig {
+    /// Send anonymous usage statistics to `analytics_url`.
+    /// Defaults to true, unless the `enable_analytics` environment variable is "false".
+    pub analytics: bool,
+    /// Endpoint for anonymous usage statistics.
+    pub analytics_url: String,
+    /// OTLP endpoint for metrics, overrides OTEL_METRIC_URL and the server config.
+    pub metric_url: Option<String>,
+    /// OTLP endpoint for logs, overrides OTEL_LOG_URL and the server config.
+    pub log_url: Option<String>,
+    /// OTLP endpoint for traces, overrides OTEL_TRACE_URL and the server config.
+    pub trace_url: Option<String>,
+    /// Headers sent with every export to `metric_url`, `log_url` and `trace_url`, like "authorization".
+    pub headers: HashMap<String, String>,
+    /// How often metrics are exported, defaults to OTEL_METRIC_EXPORT_INTERVAL or 10 seconds.
+    pub metric_interval: Duration,
+    /// How often batched logs are exported.
+    pub log_interval: Duration,
+    /// Folder metrics and logs are written to while the collector is unreachable, and sent from once it is back.
+    /// No spooling when not set.
+    pub spool_dir: Option<PathBuf>,
+    /// Maximum size of `spool_dir` in bytes, the oldest data is dropped first.
+    pub spool_max_bytes: u64,
+}
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        let enable_analytics = std::env::var("enable_analytics").unwrap_or_default();
+        let metric_interval = std::env::var("OTEL_METRIC_EXPORT_INTERVAL").ok()
+            .and_then(|ms| ms.parse::<u64>().ok())
+            .unwrap_or(10000);
+        Self {
+            analytics: !enable_analytics.eq_ignore_ascii_case("false"),
+            analytics_url: DEFAULT_ANALYTICS_URL.to_string(),
+            metric_url: None,
+            log_url: None,
+            trace_url: None,
+            headers: HashMap::new(),
+            metric_interval: Duration::from_millis(metric_interval),
+            log_interval: Duration::from_secs(1),
+            spool_dir: None,
+            spool_max_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+impl std::fmt::Debug for TelemetryConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // header values may hold credentials, only show the names
+        let mut headers: Vec<&String> = self.headers.keys().collect();
+        headers.sort();
+        f.debug_struct("TelemetryConfig")
+            .field("analytics", &self.analytics)
+            .field("analytics_url", &self.analytics_url)
+            .field("metric_url", &self.metric_url)
+            .field("log_url", &self.log_url)
+            .field("trace_url", &self.trace_url)
+            .field("headers", &headers)
+            .field("metric_interval", &self.metric_interval)
+            .field("log_interval", &self.log_interval)
+            .field("spool_dir", &self.spool_dir)
+            .field("spool_max_bytes", &self.spool_max_bytes)
+            .finish()
+    }
+}
+impl TelemetryConfig {
+    /// Add a header sent with every OTLP export.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name.to_lowercase(), value.to_string());
+        self
+    }
+    /// Authenticate OTLP exports with a bearer token.
+    pub fn with_bearer_token(self, token: &str) -> Self {
+        self.with_header("authorization", &format!("Bearer {}", token))
+    }
+    /// Spool metrics and logs to `dir` while the collector is unreachable, using at most `max_bytes`.
+    pub fn with_spool(mut self, dir: &str, max_bytes: u64) -> Self {
+        self.spool_dir = Some(PathBuf::from(dir));
+        self.spool_max_bytes = max_bytes;
+        self
+    }
+}
+
+impl Client {
+    /// Set the telemetry configuration, used the next time the client connects.
+    pub fn set_telemetry_config(&self, config: TelemetryConfig) {
+        *self.telemetry.lock().unwrap() = config;
+    }
+    /// Get the telemetry configuration.
+    pub fn get_telemetry_config(&self) -> TelemetryConfig {
+        self.telemetry.lock().unwrap().clone()
+    }
+}
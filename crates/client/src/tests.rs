@@ -1691,4 +1691,654 @@ mod tests {
         );
         println!("InvokeOpenRpa response: {:?}", response.unwrap());
     }
+    #[test] // cargo test test_plan_sync -- --nocapture
+    fn test_plan_sync() {
+        let entry = |path: &str, size: u64, checksum: &str, id: &str| crate::SyncEntry {
+            path: path.to_string(),
+            size,
+            checksum: checksum.to_string(),
+            id: id.to_string(),
+        };
+        let local = vec![
+            entry("a.txt", 10, "aa", ""),
+            entry("sub/b.txt", 20, "bb", ""),
+            entry("c.txt", 30, "cc", ""),
+        ];
+        let remote = vec![
+            entry("a.txt", 10, "aa", "1"),
+            entry("sub/b.txt", 20, "old", "2"),
+            entry("d.txt", 40, "dd", "3"),
+        ];
+        let (actions, unchanged) = crate::plan_sync(&local, &remote, crate::SyncDirection::Upload, true);
+        assert_eq!(unchanged, 1);
+        let kinds: Vec<_> = actions.iter().map(|a| (a.kind, a.path.as_str())).collect();
+        assert_eq!(kinds, vec![
+            (crate::SyncActionKind::Upload, "c.txt"),
+            (crate::SyncActionKind::DeleteRemote, "d.txt"),
+            (crate::SyncActionKind::Upload, "sub/b.txt"),
+        ]);
+        assert_eq!(actions[2].remote_ids, vec!["2".to_string()]);
+
+        let (actions, unchanged) = crate::plan_sync(&local, &remote, crate::SyncDirection::Download, false);
+        assert_eq!(unchanged, 1);
+        let kinds: Vec<_> = actions.iter().map(|a| (a.kind, a.path.as_str())).collect();
+        assert_eq!(kinds, vec![
+            (crate::SyncActionKind::Download, "d.txt"),
+            (crate::SyncActionKind::Download, "sub/b.txt"),
+        ]);
+    }
+    #[test] // cargo test test_sync_local_path -- --nocapture
+    fn test_sync_local_path() {
+        let root = std::path::Path::new("/data/sync");
+        assert_eq!(crate::sync_local_path(root, "sub/b.txt").unwrap(), root.join("sub/b.txt"));
+        assert_eq!(crate::sync_local_path(root, "./a.txt").unwrap(), root.join("a.txt"));
+        assert!(crate::sync_local_path(root, "prefix/../../etc/x").is_err());
+        assert!(crate::sync_local_path(root, "../x").is_err());
+        assert!(crate::sync_local_path(root, "/etc/passwd").is_err());
+        assert!(crate::sync_local_path(root, "").is_err());
+    }
+    #[test] // cargo test test_workitem_error_apply -- --nocapture
+    fn test_workitem_error_apply() {
+        let mut workitem = Workitem { state: "processing".to_string(), ..Default::default() };
+        crate::WorkitemError::Retry("timeout".to_string()).apply(&mut workitem, "worker1");
+        assert_eq!(workitem.state, "retry");
+        assert_eq!(workitem.errortype, "application");
+        assert_eq!(workitem.errormessage, "timeout");
+        assert_eq!(workitem.errorsource, "worker1");
+        crate::WorkitemError::Permanent("bad input".to_string()).apply(&mut workitem, "worker2");
+        assert_eq!(workitem.state, "failed");
+        assert_eq!(workitem.errortype, "business");
+        assert_eq!(workitem.errorsource, "worker2");
+    }
+    #[test] // cargo test test_workitem_state -- --nocapture
+    fn test_workitem_state() {
+        use crate::WorkitemState;
+        for state in WorkitemState::ALL {
+            assert_eq!(state.as_str().parse::<WorkitemState>().unwrap(), state);
+        }
+        assert!("done".parse::<WorkitemState>().is_err());
+        let mut workitem = Workitem { state: "new".to_string(), ..Default::default() };
+        workitem.set_state(WorkitemState::Processing).unwrap();
+        assert!(workitem.set_state(WorkitemState::New).is_err());
+        assert_eq!(workitem.state, "processing");
+        workitem.set_state(WorkitemState::Successful).unwrap();
+        assert!(workitem.set_state(WorkitemState::Retry).is_err());
+        crate::validate_workitem_transition("1", "processing", "successful").unwrap();
+        assert!(crate::validate_workitem_transition("1", "processing", "new").is_err());
+        assert!(crate::validate_workitem_transition("1", "successful", "processing").is_err());
+        assert!(crate::validate_workitem_transition("1", "new", "done").is_err());
+        crate::validate_workitem_transition("1", "", "processing").unwrap();
+
+        let now = std::time::SystemTime::now();
+        workitem.set_nextrun(Some(now));
+        assert_eq!(workitem.get_nextrun(), Some(now));
+        assert_eq!(workitem.get_lastrun(), None);
+    }
+    #[tokio::test()] // cargo test test_update_workitem_checked -- --nocapture
+    async fn test_update_workitem_checked() {
+        let client = Client::new();
+        let workitem = Workitem { id: "1".to_string(), state: "processing".to_string(), ..Default::default() };
+        let request = UpdateWorkitemRequest { workitem: Some(workitem), ..Default::default() };
+        // rejected before anything is sent, so no server is needed
+        let result = client
+            .update_workitem_checked(request, crate::WorkitemState::Successful, crate::EnvConfig::new())
+            .await;
+        assert!(result.unwrap_err().to_string().contains("cannot move from successful to processing"));
+    }
+    #[test] // cargo test test_typed_workitem -- --nocapture
+    fn test_typed_workitem() {
+        #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct Invoice {
+            number: u32,
+            #[serde(default)]
+            paid: bool,
+        }
+        let workitem = Workitem { id: "1".to_string(), payload: "{\"number\": 42}".to_string(), ..Default::default() };
+        let mut typed = crate::TypedWorkitem::<Invoice>::from_workitem(workitem).unwrap();
+        assert_eq!(typed.payload, Invoice { number: 42, paid: false });
+        typed.payload.paid = true;
+        let workitem = typed.into_workitem().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&workitem.payload).unwrap();
+        assert_eq!(value["paid"], true);
+        let bad = Workitem { payload: "{}".to_string(), ..Default::default() };
+        assert!(crate::TypedWorkitem::<Invoice>::from_workitem(bad).is_err());
+    }
+    #[test] // cargo test test_cron_schedule -- --nocapture
+    fn test_cron_schedule() {
+        use std::time::{Duration, UNIX_EPOCH};
+        // 2024-02-28T23:58:00Z, a wednesday
+        let start = UNIX_EPOCH + Duration::from_secs(1709164680);
+        let every5 = crate::CronSchedule::parse("*/5 * * * *").unwrap();
+        assert_eq!(every5.next_after(start), Some(UNIX_EPOCH + Duration::from_secs(1709164800)));
+        // leap day at 09:30
+        let leap = crate::CronSchedule::parse("30 9 29 2 *").unwrap();
+        assert_eq!(leap.next_after(start), Some(UNIX_EPOCH + Duration::from_secs(1709199000)));
+        // next monday 08:00 is 2024-03-04
+        let monday = crate::CronSchedule::parse("0 8 * * 1").unwrap();
+        assert_eq!(monday.next_after(start), Some(UNIX_EPOCH + Duration::from_secs(1709539200)));
+        assert!(crate::CronSchedule::parse("* * *").is_err());
+        assert!(crate::CronSchedule::parse("61 * * * *").is_err());
+        assert!(crate::CronSchedule::parse("*/0 * * * *").is_err());
+    }
+    #[test] // cargo test test_recurring_due_runs -- --nocapture
+    fn test_recurring_due_runs() {
+        use std::time::{Duration, UNIX_EPOCH};
+        let lastrun = UNIX_EPOCH + Duration::from_secs(1709164800);
+        let now = lastrun + Duration::from_secs(3 * 3600 + 10);
+        let mut item = crate::RecurringWorkitem::new("hourly", "0 * * * *", "q2", "{}").unwrap();
+        let (runs, latest) = item.due_runs(lastrun, now);
+        assert_eq!(runs, vec![lastrun + Duration::from_secs(3 * 3600)]);
+        assert_eq!(latest, Some(lastrun + Duration::from_secs(3 * 3600)));
+        item.catch_up = crate::CatchUpPolicy::RunAll(2);
+        let (runs, _) = item.due_runs(lastrun, now);
+        assert_eq!(runs, vec![lastrun + Duration::from_secs(2 * 3600), lastrun + Duration::from_secs(3 * 3600)]);
+        item.catch_up = crate::CatchUpPolicy::Skip;
+        let (runs, latest) = item.due_runs(lastrun, now);
+        assert_eq!(runs.len(), 1);
+        assert!(latest.is_some());
+        let (runs, latest) = item.due_runs(lastrun, now + Duration::from_secs(120));
+        assert!(runs.is_empty());
+        assert!(latest.is_some());
+    }
+    #[test] // cargo test test_workitem_from_document -- --nocapture
+    fn test_workitem_from_document() {
+        let document: serde_json::Value = serde_json::from_str(r#"{
+            "_id": "abc", "_type": "workitem", "name": "item", "wiq": "q2", "state": "processing",
+            "payload": {"number": 1}, "retries": 2, "errorsource": "worker1",
+            "lastrun": "2024-02-28T23:58:00.250Z",
+            "files": [{"name": "a.txt", "filename": "a.txt", "_id": "f1"}]
+        }"#).unwrap();
+        let workitem = crate::workitem_from_document(&document);
+        assert_eq!(workitem.id, "abc");
+        assert_eq!(workitem.payload, "{\"number\":1}");
+        assert_eq!(workitem.retries, 2);
+        assert_eq!(workitem.files[0].id, "f1");
+        let lastrun = workitem.get_lastrun().unwrap();
+        assert_eq!(lastrun, std::time::UNIX_EPOCH + std::time::Duration::from_millis(1709164680250));
+    }
+    #[test] // cargo test test_workitem_queue_stats_parse -- --nocapture
+    fn test_workitem_queue_stats_parse() {
+        let results = r#"[{"states":[{"_id":"new","count":3},{"_id":"retry","count":2},{"_id":"successful","count":10}],
+            "oldest":[{"_id":"x","age":90500}],"throughput":[{"count":7}]}]"#;
+        let stats = crate::WorkitemQueueStats::from_aggregate("q2", results).unwrap();
+        assert_eq!(stats.new, 3);
+        assert_eq!(stats.backlog(), 5);
+        assert_eq!(stats.total, 15);
+        assert_eq!(stats.oldest_pending_age, Some(std::time::Duration::from_millis(90500)));
+        assert_eq!(stats.throughput_per_hour, 7);
+        let empty = crate::WorkitemQueueStats::from_aggregate("q2", r#"[{"states":[],"oldest":[],"throughput":[]}]"#).unwrap();
+        assert_eq!(empty.oldest_pending_age, None);
+        assert_eq!(empty.total, 0);
+    }
+    #[test] // cargo test test_workitem_filter_query -- --nocapture
+    fn test_workitem_filter_query() {
+        let mut filter = crate::WorkitemFilter::bystate("q2", crate::WorkitemState::Failed);
+        filter.states.push(crate::WorkitemState::Retry);
+        filter.errortype = Some("application".to_string());
+        filter.older_than = Some(std::time::Duration::from_secs(86400));
+        let query = filter.to_query();
+        assert_eq!(query["wiq"], "q2");
+        assert_eq!(query["state"]["$in"], serde_json::json!(["failed", "retry"]));
+        assert_eq!(query["errortype"], "application");
+        assert_eq!(query["$expr"]["$gte"][1], 86400000);
+        let query = crate::WorkitemFilter { wiq: "q2".to_string(), ..Default::default() }.to_query();
+        assert!(query.get("state").is_none());
+    }
+    #[test] // cargo test test_workitem_queue_spec -- --nocapture
+    fn test_workitem_queue_spec() {
+        let spec = crate::WorkitemQueueSpec {
+            retrydelay: std::time::Duration::from_secs(30),
+            ..crate::WorkitemQueueSpec::new("invoices")
+        }
+        .with_chaining(Some("invoices_done"), None);
+        let document: serde_json::Value = serde_json::from_str(r#"{
+            "_id": "q1", "_type": "workitemqueue", "name": "invoices", "maxretries": 3, "retrydelay": 30,
+            "initialdelay": 0, "success_wiq": "invoices_done", "success_wiqid": "q2", "failed_wiq": "errors",
+            "_created": "2024-02-28T23:58:00.000Z", "_acl": [{"_id": "u1", "name": "admins", "rights": 65535, "deny": false}]
+        }"#).unwrap();
+        let queue = crate::workitemqueue_from_document(&document);
+        assert_eq!(queue.id, "q1");
+        assert_eq!(queue.acl[0].rights, 65535);
+        assert!(queue.created.is_some());
+        assert!(spec.matches(&queue));
+        let mut changed = spec.clone();
+        changed.maxretries = 5;
+        assert!(!changed.matches(&queue));
+        let mut updated = queue.clone();
+        changed.apply(&mut updated);
+        assert_eq!(updated.maxretries, 5);
+        assert_eq!(updated.failed_wiq, "errors");
+        assert_eq!(updated.id, "q1");
+    }
+    #[test] // cargo test test_workitem_import_rows -- --nocapture
+    fn test_workitem_import_rows() {
+        let records = crate::parse_csv("a,b\r\n\"x, \"\"y\"\"\",\"line1\nline2\"\n\n", ',');
+        assert_eq!(records, vec![
+            vec!["a".to_string(), "b".to_string()],
+            vec!["x, \"y\"".to_string(), "line1\nline2".to_string()],
+        ]);
+        let records = crate::parse_csv("a\n\nb\n", ',');
+        assert_eq!(records, vec![vec!["a".to_string()], vec!["b".to_string()]]);
+        let text = std::fs::read_to_string("../../testfile.csv").unwrap();
+        let mut options = crate::WorkitemImportOptions::new("q");
+        options.name_template = "{sex} {age} ({row})".to_string();
+        let (rows, _) = crate::csv_to_rows(&text, &options);
+        assert_eq!(rows[0].1["survived"], serde_json::json!("0"));
+        options.infer_types = true;
+        let (rows, errors) = crate::csv_to_rows(&text, &options);
+        assert!(errors.is_empty());
+        assert_eq!(rows.len(), text.lines().count() - 1);
+        let (rownumber, row) = &rows[0];
+        assert_eq!(row["survived"], serde_json::json!(0));
+        assert_eq!(row["age"], serde_json::json!(22.0));
+        assert_eq!(row["alone"], serde_json::json!("n"));
+        let workitem = crate::row_to_workitem(*rownumber, row, &options).unwrap();
+        assert_eq!(workitem.name, "male 22.0 (1)");
+        options.columns = Some(vec![("fare".to_string(), "price".to_string())]);
+        let workitem = crate::row_to_workitem(*rownumber, row, &options).unwrap();
+        assert_eq!(workitem.payload, r#"{"price":7.25}"#);
+        options.file_column = Some("class".to_string());
+        assert!(crate::row_to_workitem(*rownumber, row, &options).is_err());
+        let (rows, errors) = crate::ndjson_to_rows("{\"a\":1}\n\n[1]\nnope\n");
+        assert_eq!(rows.len(), 1);
+        assert_eq!(errors.iter().map(|e| e.row).collect::<Vec<_>>(), vec![3, 4]);
+    }
+    #[test] // cargo test test_popped_workitem -- --nocapture
+    fn test_popped_workitem() {
+        let file = |id: &str| crate::WorkitemFile { id: id.to_string(), filename: format!("{}.txt", id), ..Default::default() };
+        let mut popped = crate::PoppedWorkitem {
+            workitem: crate::Workitem::default(),
+            files: vec![
+                crate::PoppedFile { file: file("a"), path: Some("./a.txt".into()), ..Default::default() },
+                crate::PoppedFile { file: file("b"), content: Some(vec![1, 2]), ..Default::default() },
+            ],
+        };
+        assert_eq!(popped.path_of("a"), Some(&std::path::PathBuf::from("./a.txt")));
+        assert_eq!(popped.path_of("b"), None);
+        assert!(popped.all_downloaded());
+        popped.files[1].error = Some("Failed".to_string());
+        assert!(!popped.all_downloaded());
+        assert_eq!(crate::PopWorkitemOptions::default().on_error, crate::DownloadErrorPolicy::Fail);
+    }
+    #[test] // cargo test test_agent_pods_from_json -- --nocapture
+    fn test_agent_pods_from_json() {
+        let json = r#"[
+            {"metadata": {"name": "agent-1", "creationTimestamp": "2024-01-02T03:04:05Z"},
+             "status": {"phase": "Running", "containerStatuses": [{"ready": true, "restartCount": 2}]},
+             "metrics": {"cpu": "250m", "memory": "128Mi"}},
+            {"metadata": {"name": "agent-2", "deletionTimestamp": "2024-01-02T03:04:05Z"},
+             "status": {"phase": "Running", "containerStatuses": [{"ready": true, "restartCount": 0}]}},
+            {"metadata": {"name": "agent-3"}, "status": {"phase": "Pending", "containerStatuses": [{"ready": false}]}},
+            {"metadata": {"name": "docker-agent"}, "status": {"phase": "running"}}
+        ]"#;
+        let pods = crate::agent_pods_from_json(json).unwrap();
+        assert_eq!(pods.len(), 4);
+        assert!(pods[0].is_running());
+        assert_eq!(pods[0].restarts, 2);
+        assert!(pods[0].created.is_some());
+        let stats = pods[0].stats.clone().unwrap();
+        assert_eq!(stats.cpu_millicores(), Some(250.0));
+        assert_eq!(stats.memory_bytes(), Some(128 * 1024 * 1024));
+        assert!(pods[1].terminating && !pods[1].is_running());
+        assert!(!pods[2].is_running());
+        assert!(pods[3].is_running());
+        let stats = crate::AgentStats { cpu: Some("1".to_string()), memory: Some("1000".to_string()) };
+        assert_eq!(stats.cpu_millicores(), Some(1000.0));
+        assert_eq!(stats.memory_bytes(), Some(1000));
+        assert!(crate::agent_pods_from_json("[]").unwrap().is_empty());
+    }
+    #[test] // cargo test test_new_log_lines -- --nocapture
+    fn test_new_log_lines() {
+        let lines = |l: &[&str]| l.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let previous = lines(&["a", "b", "c"]);
+        assert_eq!(crate::new_log_lines(&previous, &lines(&["a", "b", "c", "d"])), &lines(&["d"])[..]);
+        assert_eq!(crate::new_log_lines(&previous, &lines(&["b", "c", "d", "e"])), &lines(&["d", "e"])[..]);
+        assert_eq!(crate::new_log_lines(&previous, &lines(&["x", "y"])), &lines(&["x", "y"])[..]);
+        assert!(crate::new_log_lines(&previous, &previous).is_empty());
+        assert_eq!(crate::new_log_lines(&[], &lines(&["a"])), &lines(&["a"])[..]);
+        let options = crate::TailLogOptions::default().with_filter("^ERR").unwrap();
+        assert!(options.filter.unwrap().is_match("ERROR something"));
+        assert!(crate::TailLogOptions::default().with_filter("(").is_err());
+    }
+    #[test] // cargo test test_package_manifest -- --nocapture
+    fn test_package_manifest() {
+        assert_eq!(crate::parse_version("v1.2.3"), Some((1, 2, 3)));
+        assert_eq!(crate::parse_version("1.2"), None);
+        assert_eq!(crate::parse_version("1.2.3.4"), None);
+        assert_eq!(crate::bump_version("1.2.3"), "1.2.4");
+        assert_eq!(crate::bump_version(""), "0.0.1");
+        let mut manifest = crate::PackageManifest::new("my-package", "python");
+        assert!(manifest.validate().is_ok());
+        manifest.version = "1.0".to_string();
+        assert!(manifest.validate().is_err());
+        manifest.version = "1.0.0".to_string();
+        manifest.language = "cobol".to_string();
+        assert!(manifest.validate().is_err());
+        manifest.language = "nodejs".to_string();
+        manifest.name = "-bad name".to_string();
+        assert!(manifest.validate().is_err());
+        manifest.name = "@org/my-package".to_string();
+        assert!(manifest.validate().is_ok());
+        for name in ["org/my-package", "@org", "@/my-package", "@org/my/package", "my@package"] {
+            manifest.name = name.to_string();
+            assert!(manifest.validate().is_err(), "{} should be invalid", name);
+        }
+        let package: crate::Package = serde_json::from_str(
+            r#"{"_id": "1", "_type": "package", "name": "p", "language": "nodejs", "main": null, "fileid": "f", "_version": 3, "ports": [{"port": 80, "portname": "web"}]}"#,
+        ).unwrap();
+        assert_eq!(package.id, "1");
+        assert_eq!(package.manifest.ports.len(), 1);
+        assert_eq!(package.docversion, 3);
+    }
+    #[test] // cargo test test_agent_context -- --nocapture
+    fn test_agent_context() {
+        let vars = [("agentid", "a1"), ("slug", "myagent"), ("grpcapiurl", "grpc://localhost:50051"),
+            ("jwt", "token"), ("HOSTNAME", "myagent-abc"), ("custom", "value")];
+        let context = crate::AgentContext::from_vars(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        assert!(context.is_agent());
+        assert_eq!(context.apiurl, "grpc://localhost:50051");
+        assert_eq!(context.jwt.expose(), "token");
+        assert_eq!(context.podname, "myagent-abc");
+        assert_eq!(context.get("custom"), Some("value"));
+        assert_eq!(context.credentials(), crate::Credentials::jwt("token"));
+        let empty = crate::AgentContext::from_vars(vec![]);
+        assert!(!empty.is_agent());
+        assert_eq!(empty.credentials(), crate::Credentials::None);
+        let mut status = crate::AgentStatus {
+            state: "Signedin".to_string(), ready: true, stopping: false, message: String::new(),
+            uptime: 1, agentid: "a1".to_string(), podname: "p".to_string(),
+        };
+        assert!(crate::health_response("/readyz", &status).starts_with("HTTP/1.1 200"));
+        status.ready = false;
+        assert!(crate::health_response("/readyz", &status).starts_with("HTTP/1.1 503"));
+        assert!(crate::health_response("/healthz", &status).starts_with("HTTP/1.1 200"));
+        assert!(crate::health_response("/status", &status).ends_with(&serde_json::to_string(&status).unwrap()));
+        assert!(crate::health_response("/nope", &status).starts_with("HTTP/1.1 404"));
+        let shutdown = crate::Shutdown::new();
+        assert!(!shutdown.is_triggered());
+        shutdown.trigger();
+        assert!(shutdown.is_triggered());
+    }
+    #[tokio::test()] // cargo test test_diagnostics -- --nocapture
+    async fn test_diagnostics() {
+        let client = Client::new();
+        client.record_command("query", std::time::Duration::from_millis(30), 100, 400, None);
+        client.record_command("query", std::time::Duration::from_millis(10), 100, 0, Some("timeout"));
+        let diagnostics = client.diagnostics(true).await;
+        assert_eq!(diagnostics.state, "Disconnected");
+        assert_eq!(diagnostics.uptime, 0);
+        let query = &diagnostics.commands["query"];
+        assert_eq!(query.count, 2);
+        assert_eq!(query.bytes_sent, 200);
+        assert_eq!(query.bytes_received, 400);
+        assert_eq!(query.errors["timeout"], 1);
+        assert!((query.average_ms - 20.0).abs() < 1.0);
+        let json = serde_json::to_string(&diagnostics).unwrap();
+        assert!(json.contains("\"pending_queries\":0"));
+        let diagnostics = client.diagnostics(false).await;
+        assert!(diagnostics.commands.is_empty());
+        // the statistics behind the metrics keep counting
+        assert_eq!(client.stats.lock().unwrap().commands["query"].count, 2);
+        client.record_command("query", std::time::Duration::from_millis(40), 10, 0, None);
+        let query = &client.diagnostics(false).await.commands["query"];
+        assert_eq!(query.count, 1);
+        assert_eq!(query.bytes_sent, 10);
+        assert!(query.errors.is_empty());
+        assert!((query.average_ms - 40.0).abs() < 1.0);
+    }
+    #[cfg(feature = "otel")]
+    #[test] // cargo test test_trace_context -- --nocapture
+    fn test_trace_context() {
+        use opentelemetry::trace::TracerProvider;
+        use tracing_subscriber::layer::SubscriberExt;
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder().build();
+        let subscriber = tracing_subscriber::Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        tracing::subscriber::with_default(subscriber, || {
+            let mut envelope = Envelope { command: "query".to_string(), ..Default::default() };
+            crate::trace_context::apply_trace_context(&mut envelope);
+            assert!(envelope.traceid.is_empty());
+            let span = tracing::info_span!("request");
+            let _guard = span.enter();
+            crate::trace_context::apply_trace_context(&mut envelope);
+            assert_eq!(envelope.traceid.len(), 32);
+            assert_eq!(envelope.spanid.len(), 16);
+            let mut ping = Envelope { command: "ping".to_string(), ..Default::default() };
+            crate::trace_context::apply_trace_context(&mut ping);
+            assert!(ping.traceid.is_empty());
+            let traceid = "4bf92f3577b34da6a3ce929d0e0e4736";
+            let consumer = crate::trace_context::queue_event_span("myqueue", traceid, "00f067aa0ba902b7");
+            let _consumer = consumer.enter();
+            assert_eq!(crate::current_trace_ids().unwrap().0, traceid);
+            assert!(crate::trace_context::remote_span_context("nothex", "00f067aa0ba902b7").is_none());
+        });
+    }
+    #[cfg(feature = "prometheus")]
+    #[test] // cargo test --features prometheus test_render_prometheus -- --nocapture
+    fn test_render_prometheus() {
+        let mut diagnostics = crate::ClientDiagnostics { state: "Signedin".to_string(), connections: 2, ..Default::default() };
+        let mut query = crate::CommandDiagnostics { count: 4, total_ms: 2000.0, ..Default::default() };
+        query.errors.insert("timeout".to_string(), 1);
+        diagnostics.commands.insert("query".to_string(), query);
+        let process = crate::ProcessMetrics { resident_memory: 1024, ..Default::default() };
+        let gauges = vec![("my.gauge".to_string(), 1.5, "My gauge".to_string())];
+        let text = crate::render_prometheus(&diagnostics, &process, &gauges);
+        assert!(text.contains("openiap_client_connections_total 2\n"));
+        assert!(text.contains("openiap_client_state{state=\"Signedin\"} 1\n"));
+        assert!(text.contains("openiap_client_commands_total{command=\"query\"} 4\n"));
+        assert!(text.contains("openiap_client_command_errors_total{command=\"query\",kind=\"timeout\"} 1\n"));
+        assert!(text.contains("openiap_client_command_duration_seconds_sum{command=\"query\"} 2\n"));
+        assert!(text.contains("process_resident_memory_bytes 1024\n"));
+        assert!(text.contains("# HELP my_gauge My gauge\n# TYPE my_gauge gauge\nmy_gauge 1.5\n"));
+        assert_eq!(crate::prometheus_name("1st.metric-x"), "_1st_metric_x");
+    }
+    #[cfg(feature = "otel")]
+    #[test] // cargo test test_observable_gauge_values -- --nocapture
+    fn test_observable_gauge_values() {
+        // stored even before telemetry is initialized, and read back as the type it was set as
+        crate::set_u64_observable_gauge("test_gauge_u64", 5, "u64 gauge").unwrap();
+        crate::set_i64_observable_gauge("test_gauge_i64", -3, "i64 gauge").unwrap();
+        crate::set_f64_observable_gauge("test_gauge_f64", 1.5, "f64 gauge").unwrap();
+        crate::set_u64_observable_gauge("test_gauge_u64", 7, "u64 gauge").unwrap();
+        assert_eq!(crate::otel::get_metric_value("test_gauge_u64"), Some(7.0));
+        assert_eq!(crate::otel::get_metric_value("test_gauge_i64"), Some(-3.0));
+        assert_eq!(crate::otel::get_metric_value("test_gauge_f64"), Some(1.5));
+    }
+    #[cfg(feature = "otel")]
+    #[test] // cargo test test_telemetry_spool -- --nocapture
+    fn test_telemetry_spool() {
+        let config = crate::TelemetryConfig::default().with_bearer_token("secret").with_spool("spool", 100);
+        assert_eq!(config.headers["authorization"], "Bearer secret");
+        let debug = format!("{:?}", config);
+        assert!(debug.contains("authorization") && !debug.contains("secret"));
+        assert_eq!(config.analytics_url, crate::DEFAULT_ANALYTICS_URL);
+        assert!(crate::spool::headers_to_metadata(&config.headers).is_ok());
+        let invalid = crate::TelemetryConfig::default().with_header("bad header", "x");
+        assert!(crate::spool::headers_to_metadata(&invalid.headers).is_err());
+
+        let dir = std::env::temp_dir().join(format!("openiap_spool_test_{}", std::process::id()));
+        let spool = crate::TelemetrySpool::new(&dir, 100).unwrap();
+        spool.push("metrics", &[1; 40]).unwrap();
+        spool.push("logs", &[2; 40]).unwrap();
+        assert_eq!(spool.pending("metrics").len(), 1);
+        assert_eq!(spool.size(), 80);
+        // the oldest file is dropped to stay within max_bytes
+        spool.push("logs", &[3; 40]).unwrap();
+        assert!(spool.pending("metrics").is_empty());
+        assert_eq!(spool.pending("logs").len(), 2);
+        assert_eq!(std::fs::read(&spool.pending("logs")[1]).unwrap(), vec![3; 40]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test] // cargo test test_collection_log_layer -- --nocapture
+    fn test_collection_log_layer() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let options = crate::CollectionLogOptions { max_per_second: 2, ..crate::CollectionLogOptions::new("logs") };
+        let (layer, mut receiver, limiter) = crate::log_sink::CollectionLogLayer::new(&options, "agent", "1.0.0");
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("outer");
+            let _enter = span.enter();
+            tracing::info!(target: "myapp", count = 3, "hello {}", "world");
+            tracing::debug!(target: "myapp", "below the level");
+            tracing::info!(target: "openiap_client", "from the client");
+            crate::log_sink::FLUSHING.sync_scope(true, || tracing::error!(target: "myapp", "while flushing"));
+            tracing::error!(target: "myapp", "second");
+            tracing::error!(target: "myapp", "over the limit");
+        });
+        let document = receiver.try_recv().unwrap();
+        assert_eq!(document["name"], "hello world");
+        assert_eq!(document["level"], "INFO");
+        assert_eq!(document["target"], "myapp");
+        assert_eq!(document["fields"]["count"], 3);
+        assert_eq!(document["spans"], serde_json::json!(["outer"]));
+        assert_eq!(document["agent"], "agent");
+        assert_eq!(document["agentversion"], "1.0.0");
+        assert_eq!(receiver.try_recv().unwrap()["name"], "second");
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(limiter.dropped.load(std::sync::atomic::Ordering::Relaxed), 1);
+        assert!(crate::is_client_event("openiap_client::otel", &tracing::Level::INFO));
+        assert!(!crate::is_client_event("openiap_client", &tracing::Level::WARN));
+        assert!(!crate::is_client_event("openiap_worker", &tracing::Level::INFO));
+        assert!(!crate::is_client_event("otelcol", &tracing::Level::INFO));
+        assert_eq!(crate::util::format_iso8601(std::time::UNIX_EPOCH + std::time::Duration::from_millis(1709164680123)), "2024-02-28T23:58:00.123Z");
+    }
+    #[test] // cargo test test_secret_redaction -- --nocapture
+    fn test_secret_redaction() {
+        let secret = crate::Secret::new("hunter2");
+        assert_eq!(secret.expose(), "hunter2");
+        assert_eq!(format!("{:?} {}", secret, secret), "Secret([redacted]) [redacted]");
+        assert_eq!(format!("{:?}", crate::Secret::default()), "Secret()");
+        let client = crate::Client::new();
+        client.set_jwt("token");
+        assert_eq!(client.get_jwt().expose(), "token");
+        assert!(!format!("{:?}", client.get_jwt()).contains("token"));
+
+        let mut envelope = crate::SigninRequest::with_jwt("token").to_envelope();
+        envelope.jwt = "token".to_string();
+        let debug = format!("{:?}", envelope);
+        assert!(!debug.contains("token") && debug.contains("[redacted]"));
+        let debug = format!("{:?}", crate::SigninRequest::with_userpass("guest", "password"));
+        assert!(debug.contains("guest") && !debug.contains("\"password\""));
+        let context = crate::AgentContext { jwt: crate::Secret::new("token"), ..Default::default() };
+        assert!(!format!("{:?}", context).contains("token"));
+        let refresh = openiap_proto::openiap::RefreshToken { username: "guest".to_string(), jwt: "token".to_string(), user: None };
+        let debug = format!("{:?}", refresh);
+        assert!(debug.contains("guest") && !debug.contains("token\""));
+        let envelope = Envelope { command: "refreshtoken".to_string(), data: Some(Default::default()), ..Default::default() };
+        assert!(format!("{:?}", envelope).contains("data: Some(\"[redacted]\")"));
+    }
+    #[tokio::test()] // cargo test test_credential_providers -- --nocapture
+    async fn test_credential_providers() {
+        use crate::CredentialProvider;
+        let path = std::env::temp_dir().join(format!("openiap_jwt_test_{}", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+        let file = crate::FileCredentials::jwt(path.to_str().unwrap());
+        assert_eq!(file.credentials().await.unwrap(), crate::Credentials::jwt("first"));
+        // a rotated token is picked up on the next read
+        std::fs::write(&path, "second").unwrap();
+        let modified = std::time::SystemTime::now() + std::time::Duration::from_secs(1);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        assert_eq!(file.credentials().await.unwrap(), crate::Credentials::jwt("second"));
+        std::fs::remove_file(&path).unwrap();
+        assert!(file.credentials().await.is_err());
+
+        let fixed = crate::StaticCredentials(crate::Credentials::userpass("guest", "password"));
+        assert_eq!(fixed.credentials().await.unwrap(), crate::Credentials::userpass("guest", "password"));
+        let callback = crate::CallbackCredentials::new(|| Box::pin(async { Ok(crate::Credentials::jwt("token")) }));
+        assert_eq!(callback.credentials().await.unwrap(), crate::Credentials::jwt("token"));
+
+        let client = crate::Client::new();
+        client.set_jwt("old");
+        client.set_credential_provider(std::sync::Arc::new(fixed));
+        client.refresh_credentials().await.unwrap();
+        assert_eq!(client.get_username(), "guest");
+        assert_eq!(client.get_password().expose(), "password");
+        assert!(client.get_jwt().is_empty());
+        // after a sign out the provider is kept, but not used until credentials are given again
+        client.set_username("");
+        client.set_signed_out(true);
+        client.refresh_credentials().await.unwrap();
+        assert!(client.get_username().is_empty());
+        assert!(client.provided_credentials().await.unwrap().is_some());
+        client.clear_credential_provider();
+        assert!(!client.is_signed_out());
+        assert_eq!(client.provided_credentials().await.unwrap(), None);
+    }
+    #[test] // cargo test test_decode_jwt_claims -- --nocapture
+    fn test_decode_jwt_claims() {
+        use base64::Engine;
+        let payload = serde_json::json!({
+            "data": { "_id": "5ce94386320b9ce0bc2c3d07", "name": "Guest", "username": "guest", "customerid": "cust1",
+                "roles": [{ "_id": "r1", "name": "users" }, { "_id": "r2", "name": "robots" }] },
+            "iat": 1700000000, "exp": 4102444800u64, "iss": "openflow"
+        });
+        let encode = |value: &str| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(value);
+        let jwt = format!("{}.{}.signature", encode("{\"alg\":\"HS256\"}"), encode(&payload.to_string()));
+        let claims = crate::decode_jwt_claims(&jwt).unwrap();
+        assert_eq!(claims.userid, "5ce94386320b9ce0bc2c3d07");
+        assert_eq!(claims.username, "guest");
+        assert_eq!(claims.roles, vec!["users", "robots"]);
+        assert_eq!(claims.customerid, "cust1");
+        assert_eq!(claims.issuer, "openflow");
+        assert_eq!(claims.issued_at, Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1700000000)));
+        assert!(!claims.is_expired());
+        assert!(crate::decode_jwt_claims("not a token").is_err());
+
+        let client = crate::Client::new();
+        assert!(client.session().is_err());
+        client.set_session_jwt(&jwt);
+        assert_eq!(client.session().unwrap().username, "guest");
+    }
+    #[test] // cargo test test_workitem_watch_pipeline -- --nocapture
+    fn test_workitem_watch_pipeline() {
+        let paths = crate::workitem_watch_pipeline("invoices");
+        assert_eq!(paths.len(), 1);
+        let pipeline: serde_json::Value = serde_json::from_str(&paths[0]).unwrap();
+        let matched = &pipeline[0]["$match"];
+        assert_eq!(matched["fullDocument._type"], "workitem");
+        assert_eq!(matched["fullDocument.wiq"], "invoices");
+        assert_eq!(matched["fullDocument.state"], "new");
+        // queue names are data, not part of the query
+        let paths = crate::workitem_watch_pipeline("a\"b");
+        let pipeline: serde_json::Value = serde_json::from_str(&paths[0]).unwrap();
+        assert_eq!(pipeline[0]["$match"]["fullDocument.wiq"], "a\"b");
+    }
+    #[tokio::test] // cargo test test_requeue_workitems_paging -- --nocapture
+    async fn test_requeue_workitems_paging() {
+        let client = Client::new_connect(TEST_URL).await.unwrap();
+        let wiq = "rustbulkqueue";
+        let items = (0..5)
+            .map(|i| Workitem {
+                name: format!("bulk workitem {}", i),
+                ..Default::default()
+            })
+            .collect();
+        client
+            .push_workitems(PushWorkitemsRequest {
+                wiq: wiq.to_string(),
+                items,
+                ..Default::default()
+            }, crate::EnvConfig::new())
+            .await
+            .unwrap();
+        // requeue sets state to new, so the items keep matching the filter after each batch
+        let filter = crate::WorkitemFilter::bystate(wiq, crate::WorkitemState::New);
+        let options = crate::WorkitemBulkOptions {
+            batch_size: 2,
+            ..Default::default()
+        };
+        let result = client
+            .requeue_workitems(crate::EnvConfig::new(), &filter, options.clone())
+            .await
+            .unwrap();
+        assert!(result.matched >= 5);
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        assert_eq!(result.processed, result.matched);
+        let result = client
+            .purge_workitems(crate::EnvConfig::new(), &filter, options)
+            .await
+            .unwrap();
+        assert_eq!(result.processed, result.matched);
+        assert_eq!(client.count_workitems(crate::EnvConfig::new(), &filter).await.unwrap(), 0);
+    }
 }
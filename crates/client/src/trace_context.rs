@@ -0,0 +1,65 @@
+use openiap_proto::openiap::Envelope;
+
+#[cfg(feature = "otel")]
+use {
+    opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+    tracing_opentelemetry::OpenTelemetrySpanExt,
+};
+
+/// Returns the trace id and span id of the current span, as lowercase hex, if it is part of a trace.
+/// The ids come from the OpenTelemetry context of the current `tracing` span, falling back to the active OpenTelemetry context.
+#[cfg(feature = "otel")]
+pub fn current_trace_ids() -> Option<(String, String)> {
+    let context = tracing::Span::current().context();
+    let mut span_context = context.span().span_context().clone();
+    if !span_context.is_valid() {
+        span_context = opentelemetry::Context::current().span().span_context().clone();
+    }
+    if span_context.is_valid() {
+        Some((span_context.trace_id().to_string(), span_context.span_id().to_string()))
+    } else {
+        None
+    }
+}
+/// Returns the trace id and span id of the current span, always None without the `otel` feature.
+#[cfg(not(feature = "otel"))]
+pub fn current_trace_ids() -> Option<(String, String)> {
+    None
+}
+
+/// Set traceid and spanid on an outgoing envelope from the current span, unless the caller already set them.
+pub(crate) fn apply_trace_context(envelope: &mut Envelope) {
+    if !envelope.traceid.is_empty() || envelope.command == "ping" || envelope.command == "pong" {
+        return;
+    }
+    if let Some((traceid, spanid)) = current_trace_ids() {
+        envelope.traceid = traceid;
+        envelope.spanid = spanid;
+    }
+}
+
+/// Create the span a queue event callback runs in.
+/// If the envelope carried a trace, the span continues it, so work done in the callback ends up in the senders trace.
+pub(crate) fn queue_event_span(queuename: &str, traceid: &str, spanid: &str) -> tracing::Span {
+    let span = tracing::info_span!("queueevent", otel.kind = "consumer", queuename = queuename);
+    #[cfg(feature = "otel")]
+    if let Some(parent) = remote_span_context(traceid, spanid) {
+        span.set_parent(opentelemetry::Context::new().with_remote_span_context(parent));
+    }
+    #[cfg(not(feature = "otel"))]
+    let _ = (traceid, spanid);
+    span
+}
+
+/// Parse the trace id and span id received from the server.
+#[cfg(feature = "otel")]
+pub(crate) fn remote_span_context(traceid: &str, spanid: &str) -> Option<SpanContext> {
+    let traceid = TraceId::from_hex(traceid).ok()?;
+    let spanid = SpanId::from_hex(spanid).ok()?;
+    let span_context = SpanContext::new(traceid, spanid, TraceFlags::SAMPLED, true, TraceState::default());
+    if span_context.is_valid() {
+        Some(span_context)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,197 @@
+use openiap_proto::errors::OpenIAPError;
+use openiap_proto::openiap::{PopWorkitemRequest, PushWorkitemRequest, UpdateWorkitemRequest, UpdateWorkitemResponse, Workitem, WorkitemFile};
+use openiap_proto::workitem::{systemtime_to_timestamp, WorkitemState};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{Client, EnvConfig};
+
+/// A `Workitem` with its payload deserialized into `P`.
+#[derive(Debug, Clone)]
+pub struct TypedWorkitem<P> {
+    /// The workitem, `payload` is replaced with `P` when saving.
+    pub workitem: Workitem,
+    /// The deserialized payload.
+    pub payload: P,
+}
+impl<P: Serialize + DeserializeOwned> TypedWorkitem<P> {
+    /// Deserialize the payload of `workitem`, an empty payload is read as `{}`.
+    pub fn from_workitem(workitem: Workitem) -> Result<Self, OpenIAPError> {
+        let payload = if workitem.payload.is_empty() { "{}" } else { workitem.payload.as_str() };
+        let payload = serde_json::from_str(payload).map_err(|e| {
+            OpenIAPError::ClientError(format!("Failed to parse payload of workitem {}: {}", workitem.id, e))
+        })?;
+        Ok(Self { workitem, payload })
+    }
+    /// Serialize the payload back into the workitem.
+    pub fn into_workitem(self) -> Result<Workitem, OpenIAPError> {
+        let mut workitem = self.workitem;
+        workitem.payload = serde_json::to_string(&self.payload)
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to serialize payload: {}", e)))?;
+        Ok(workitem)
+    }
+    /// The current state of the workitem.
+    pub fn state(&self) -> Result<WorkitemState, OpenIAPError> {
+        self.workitem.get_state()
+    }
+    /// Move the workitem to `state`, returns an error if the transition is not allowed.
+    pub fn set_state(&mut self, state: WorkitemState) -> Result<(), OpenIAPError> {
+        self.workitem.set_state(state)
+    }
+}
+
+/// Build a `Workitem` from a document in the workitems collection, as returned by `query`.
+pub fn workitem_from_document(document: &serde_json::Value) -> Workitem {
+    let text = |key: &str| document[key].as_str().unwrap_or_default().to_string();
+    let date = |key: &str| {
+        document[key]
+            .as_str()
+            .and_then(crate::util::parse_iso8601)
+            .map(systemtime_to_timestamp)
+    };
+    let payload = match &document["payload"] {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(payload) => payload.clone(),
+        payload => payload.to_string(),
+    };
+    let files = document["files"]
+        .as_array()
+        .map(|files| {
+            files
+                .iter()
+                .map(|f| WorkitemFile {
+                    filename: f["filename"].as_str().or(f["name"].as_str()).unwrap_or_default().to_string(),
+                    id: f["_id"].as_str().unwrap_or_default().to_string(),
+                    compressed: f["compressed"].as_bool().unwrap_or_default(),
+                    ..Default::default()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Workitem {
+        id: text("_id"),
+        name: text("name"),
+        payload,
+        priority: document["priority"].as_i64().unwrap_or_default() as i32,
+        nextrun: date("nextrun"),
+        lastrun: date("lastrun"),
+        files,
+        state: text("state"),
+        wiq: text("wiq"),
+        wiqid: text("wiqid"),
+        retries: document["retries"].as_i64().unwrap_or_default() as i32,
+        username: text("username"),
+        success_wiqid: text("success_wiqid"),
+        failed_wiqid: text("failed_wiqid"),
+        success_wiq: text("success_wiq"),
+        failed_wiq: text("failed_wiq"),
+        errormessage: text("errormessage"),
+        errorsource: text("errorsource"),
+        errortype: text("errortype"),
+    }
+}
+
+/// Check that workitem `id` may move from the `stored` state to `next`.
+/// An empty state on either side is not checked, the server fills in a default.
+pub fn validate_workitem_transition(id: &str, stored: &str, next: &str) -> Result<(), OpenIAPError> {
+    if stored.is_empty() || next.is_empty() {
+        return Ok(());
+    }
+    let stored: WorkitemState = stored.parse()?;
+    let next: WorkitemState = next.parse()?;
+    if !stored.can_transition_to(next) {
+        return Err(OpenIAPError::ClientError(format!(
+            "Workitem {} cannot move from {} to {}",
+            id, stored, next
+        )));
+    }
+    Ok(())
+}
+
+impl Client {
+    /// Update a workitem after checking that it may move from `previous`, the state the caller last saw, to the state in `config`.
+    /// `update_workitem` does not check transitions, use this when the caller wants the check without an extra round trip.
+    #[tracing::instrument(skip_all)]
+    pub async fn update_workitem_checked(
+        &self,
+        config: UpdateWorkitemRequest,
+        previous: WorkitemState,
+        env: EnvConfig,
+    ) -> Result<UpdateWorkitemResponse, OpenIAPError> {
+        if let Some(workitem) = &config.workitem {
+            validate_workitem_transition(&workitem.id, previous.as_str(), &workitem.state)?;
+        }
+        self.update_workitem(config, env).await
+    }
+    /// Push a workitem with a typed payload.
+    #[tracing::instrument(skip_all)]
+    pub async fn push_typed_workitem<P: Serialize + DeserializeOwned>(
+        &self,
+        wiq: &str,
+        name: &str,
+        payload: &P,
+        env: EnvConfig,
+    ) -> Result<TypedWorkitem<P>, OpenIAPError> {
+        let request = PushWorkitemRequest {
+            wiq: wiq.to_string(),
+            name: name.to_string(),
+            payload: serde_json::to_string(payload)
+                .map_err(|e| OpenIAPError::ClientError(format!("Failed to serialize payload: {}", e)))?,
+            ..Default::default()
+        };
+        let response = self.push_workitem(request, env).await?;
+        match response.workitem {
+            Some(workitem) => TypedWorkitem::from_workitem(workitem),
+            None => Err(OpenIAPError::ClientError("No workitem returned".to_string())),
+        }
+    }
+    /// Pop a workitem and deserialize its payload, return None if no workitem is available
+    #[tracing::instrument(skip_all)]
+    pub async fn pop_typed_workitem<P: Serialize + DeserializeOwned>(
+        &self,
+        config: PopWorkitemRequest,
+        env: EnvConfig,
+        downloadfolder: Option<&str>,
+    ) -> Result<Option<TypedWorkitem<P>>, OpenIAPError> {
+        let response = self.pop_workitem(config, env, downloadfolder).await?;
+        match response.workitem {
+            Some(workitem) => Ok(Some(TypedWorkitem::from_workitem(workitem)?)),
+            None => Ok(None),
+        }
+    }
+    /// Save a typed workitem, including its serialized payload and state
+    #[tracing::instrument(skip_all)]
+    pub async fn update_typed_workitem<P: Serialize + DeserializeOwned>(
+        &self,
+        item: TypedWorkitem<P>,
+        env: EnvConfig,
+    ) -> Result<TypedWorkitem<P>, OpenIAPError> {
+        let request = UpdateWorkitemRequest {
+            workitem: Some(item.into_workitem()?),
+            ..Default::default()
+        };
+        let response = self.update_workitem(request, env).await?;
+        match response.workitem {
+            Some(workitem) => TypedWorkitem::from_workitem(workitem),
+            None => Err(OpenIAPError::ClientError("No workitem returned".to_string())),
+        }
+    }
+    /// Move a workitem to `state` and save it, the transition is checked before calling `update_workitem`
+    #[tracing::instrument(skip_all)]
+    pub async fn set_workitem_state(
+        &self,
+        mut workitem: Workitem,
+        state: WorkitemState,
+        env: EnvConfig,
+    ) -> Result<Workitem, OpenIAPError> {
+        workitem.set_state(state)?;
+        let request = UpdateWorkitemRequest {
+            workitem: Some(workitem),
+            ..Default::default()
+        };
+        let response = self.update_workitem(request, env).await?;
+        response
+            .workitem
+            .ok_or_else(|| OpenIAPError::ClientError("No workitem returned".to_string()))
+    }
+}
@@ -3,6 +3,9 @@ use std::fs::File;
 use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static UNIQUE_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
 pub fn generate_unique_filename(base: &str) -> PathBuf {
     let start = SystemTime::now();
@@ -10,7 +13,9 @@ pub fn generate_unique_filename(base: &str) -> PathBuf {
         .duration_since(UNIX_EPOCH)
         .expect("Time went backwards");
     let timestamp = since_the_epoch.as_secs();
-    let filename = format!("{}_{}.tmp", base, timestamp);
+    // downloads can run in parallel, so the timestamp alone is not unique
+    let counter = UNIQUE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let filename = format!("{}_{}_{}_{}.tmp", base, timestamp, std::process::id(), counter);
     let dir = env::temp_dir();
     dir.join(filename)
 }
@@ -25,6 +30,57 @@ pub fn move_file(from: &str, to: &str) -> std::io::Result<()> {
     Ok(())
 }
 
+// days since 1970-01-01 to (year, month, day), see http://howardhinnant.github.io/date_algorithms.html
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+/// Parse a UTC ISO 8601 date as returned by the database, like `2024-02-28T23:58:00.000Z`.
+pub fn parse_iso8601(value: &str) -> Option<SystemTime> {
+    let value = value.trim_end_matches('Z');
+    let (date, time) = value.split_once('T').unwrap_or((value, "00:00:00"));
+    let mut date = date.split('-');
+    let year: i64 = date.next()?.parse().ok()?;
+    let month: u32 = date.next()?.parse().ok()?;
+    let day: u32 = date.next()?.parse().ok()?;
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.split(':');
+    let hour: i64 = time.next()?.parse().ok()?;
+    let minute: i64 = time.next()?.parse().ok()?;
+    let second: i64 = time.next().unwrap_or("0").parse().ok()?;
+    let millis: u64 = format!("{:0<3}", &fraction[..fraction.len().min(3)]).parse().ok()?;
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    if secs < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + std::time::Duration::from_millis(secs as u64 * 1000 + millis))
+}
+/// Format a time as UTC ISO 8601 with milliseconds, like `2024-02-28T23:58:00.000Z`.
+pub fn format_iso8601(time: SystemTime) -> String {
+    let millis = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+    let secs = millis.div_euclid(1000);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let seconds_of_day = secs.rem_euclid(86400);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day,
+        seconds_of_day / 3600, seconds_of_day % 3600 / 60, seconds_of_day % 60, millis.rem_euclid(1000))
+}
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use opentelemetry::{Key, KeyValue};
@@ -119,9 +175,10 @@ use tracing::span::{Attributes, Record};
 #[cfg(feature = "otel")]
 use {
     opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge,
-    opentelemetry_otlp::{LogExporter, WithTonicConfig, WithExportConfig}, // SpanExporter
+    opentelemetry_otlp::{LogExporter, SpanExporter, WithTonicConfig, WithExportConfig},
     opentelemetry_sdk::{
-        logs::{SdkLoggerProvider, SdkLogger},
+        logs::{SdkLoggerProvider, SdkLogger, BatchLogProcessor, BatchConfigBuilder},
+        trace::{BatchSpanProcessor, SpanProcessor},
         Resource,
     },
     tracing::Subscriber,
@@ -147,6 +204,7 @@ static OTEL_BRIDGE_HANDLE: Lazy<OnceCell<reload::Handle<OtelBridgeState, Console
 #[derive(Clone)]
 struct PendingOtelConfig {
     log_url: String,
+    trace_url: String,
     filter: String,
     ofid: String,
     version: String, 
@@ -159,6 +217,88 @@ struct PendingOtelConfig {
 static PENDING_OTEL_CONFIG: Lazy<Mutex<Option<PendingOtelConfig>>> =
     Lazy::new(|| Mutex::new(None));
 
+/// Exports the spans of the trace layer, None until a trace url is set
+#[cfg(feature="otel")]
+static TRACE_EXPORT: Lazy<Mutex<Option<BatchSpanProcessor>>> =
+    Lazy::new(|| Mutex::new(None));
+
+/// Span processor of the trace layer, hands finished spans to `TRACE_EXPORT` if a trace url is set.
+/// The trace layer is installed once, so the exporter is swapped here instead of in the layer.
+#[cfg(feature="otel")]
+#[derive(Debug)]
+struct TraceExportProcessor;
+
+#[cfg(feature="otel")]
+impl SpanProcessor for TraceExportProcessor {
+    fn on_start(&self, _span: &mut opentelemetry_sdk::trace::Span, _cx: &opentelemetry::Context) {}
+    fn on_end(&self, span: opentelemetry_sdk::trace::SpanData) {
+        if let Some(processor) = TRACE_EXPORT.lock().unwrap().as_ref() {
+            processor.on_end(span);
+        }
+    }
+    fn force_flush(&self) -> opentelemetry_sdk::error::OTelSdkResult {
+        match TRACE_EXPORT.lock().unwrap().as_ref() {
+            Some(processor) => processor.force_flush(),
+            None => Ok(()),
+        }
+    }
+    fn shutdown(&self) -> opentelemetry_sdk::error::OTelSdkResult {
+        match TRACE_EXPORT.lock().unwrap().take() {
+            Some(processor) => processor.shutdown(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Start exporting spans to `endpoint`, or stop exporting them if it is empty
+#[cfg(feature="otel")]
+fn update_trace_export(endpoint: &str, resource: Resource) {
+    let processor = if endpoint.is_empty() {
+        None
+    } else {
+        match build_trace_export(endpoint, resource) {
+            Ok(processor) => Some(processor),
+            Err(e) => {
+                eprintln!("Failed to enable OTel tracing: {e}");
+                None
+            }
+        }
+    };
+    let previous = std::mem::replace(&mut *TRACE_EXPORT.lock().unwrap(), processor);
+    if let Some(previous) = previous {
+        let _ = previous.shutdown();
+    }
+}
+
+#[cfg(feature="otel")]
+fn build_trace_export(endpoint: &str, resource: Resource) -> Result<BatchSpanProcessor, String> {
+    let telemetry = LOG_TELEMETRY.lock().unwrap().clone();
+    let metadata = crate::spool::headers_to_metadata(&telemetry.headers)?;
+    let exporter = SpanExporter::builder()
+        .with_tonic()
+        .with_tls_config(
+            tonic::transport::ClientTlsConfig::new().with_native_roots()
+        )
+        .with_endpoint(endpoint)
+        .with_metadata(metadata)
+        .build()
+        .map_err(|e| format!("Failed to build OTel trace exporter for {endpoint}: {e}"))?;
+    let mut processor = BatchSpanProcessor::builder(exporter).build();
+    processor.set_resource(&resource);
+    Ok(processor)
+}
+
+/// Headers, export interval and spool used for the log exporter
+#[cfg(feature="otel")]
+static LOG_TELEMETRY: Lazy<Mutex<crate::TelemetryConfig>> =
+    Lazy::new(|| Mutex::new(crate::TelemetryConfig::default()));
+
+/// Set the headers, export interval and spool used the next time the log exporter is created
+#[cfg(feature="otel")]
+pub(crate) fn set_otel_log_telemetry(telemetry: &crate::TelemetryConfig) {
+    *LOG_TELEMETRY.lock().unwrap() = telemetry.clone();
+}
+
 #[derive(Clone)]
 struct LastUsedFilters {
     console_filter: String,
@@ -249,6 +389,7 @@ pub fn disable_tracing() {
     {
         // bridging = none
         update_otel_state("", "none", "", "", "rust", "", "");
+        update_trace_export("", Resource::builder_empty().build());
     }
 }
 
@@ -272,7 +413,7 @@ pub fn update_console_filter(new_filter: &str) {
 #[cfg(feature="otel")]
 pub fn set_otel_url(log_url: &str, trace_url: &str, ofid: &str, version: &str, service_name: &str, agent_name: &str, agent_version: &str) {
     let log_url = log_url.trim();
-    let _trace_url = trace_url.trim();
+    let trace_url = trace_url.trim();
     let ofid = ofid.trim();
 
     // Store the configuration for later application
@@ -280,6 +421,7 @@ pub fn set_otel_url(log_url: &str, trace_url: &str, ofid: &str, version: &str, s
         let mut pending = PENDING_OTEL_CONFIG.lock().unwrap();
         *pending = Some(PendingOtelConfig {
             log_url: log_url.to_string(),
+            trace_url: trace_url.to_string(),
             filter: LAST_USED_FILTERS.lock().unwrap().otel_filter.clone(),
             ofid: ofid.to_string(),
             version: version.to_string(),
@@ -309,11 +451,12 @@ fn apply_pending_otel_config() {
         .unwrap()
         .take();
 
-    if let Some(PendingOtelConfig { log_url, filter, ofid, version, service_name, agent_name, agent_version }) = maybe_config {
+    if let Some(PendingOtelConfig { log_url, trace_url, filter, ofid, version, service_name, agent_name, agent_version }) = maybe_config {
         // Ensure we're not in a tracing context
         tracing::dispatcher::with_default(
             &tracing::dispatcher::Dispatch::new(tracing_subscriber::Registry::default()),
             || {
+                update_trace_export(&trace_url, otel_resource(&ofid, &version, &service_name, &agent_name, &agent_version));
                 let new_state = build_otel_state(&log_url, &filter, &ofid, &version, &service_name, &agent_name, &agent_version);
                 if let Some(handle) = OTEL_BRIDGE_HANDLE.get() {
                     if let Err(err) = handle.modify(|state| {
@@ -352,8 +495,21 @@ fn install_global_subscriber() {
     #[cfg(feature="otel")]
     let (otel_layer, otel_reload_handle) = reload::Layer::new(OtelBridgeState::none());
 
+    // Gives every span an OpenTelemetry context, so requests can carry the trace to the server
+    #[cfg(feature="otel")]
+    let trace_layer = {
+        use opentelemetry::trace::TracerProvider;
+        // spans always get ids to pass on, they are only exported once a trace url is set
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_span_processor(TraceExportProcessor)
+            .build();
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("openiap"))
+            .with_filter(tracing_subscriber::filter::LevelFilter::INFO)
+    };
+
     #[cfg(feature="otel")]
-    let full_subscriber = console_subscriber.with(otel_layer);
+    let full_subscriber = console_subscriber.with(otel_layer).with(trace_layer);
 
     #[cfg(not(feature="otel"))]
     let full_subscriber = console_subscriber;
@@ -415,11 +571,34 @@ fn build_otel_state(endpoint: &str, filter_directives: &str, ofid: &str, version
     tracing::dispatcher::with_default(
         &tracing::dispatcher::Dispatch::new(tracing_subscriber::Registry::default()),
         || {
-            OtelBridgeState::some(endpoint, filter_directives, ofid, version, service_name, agent_name, agent_version)
+            match OtelBridgeState::some(endpoint, filter_directives, ofid, version, service_name, agent_name, agent_version) {
+                Ok(state) => state,
+                Err(e) => {
+                    eprintln!("Failed to enable OTel logging: {e}");
+                    OtelBridgeState::none()
+                }
+            }
         },
     )
 }
 
+/// Resource describing this process, shared by the log and trace exporters
+#[cfg(feature="otel")]
+fn otel_resource(ofid: &str, version: &str, service_name: &str, agent_name: &str, agent_version: &str) -> Resource {
+    let common_attributes = [
+        KeyValue::new(HOSTNAME, hostname::get().unwrap_or_default().into_string().unwrap()),
+        KeyValue::new(OFID, ofid.to_string()),
+        KeyValue::new("PID", std::process::id().to_string()),
+    ];
+
+    Resource::builder().with_service_name(service_name.to_string())
+    .with_attribute(KeyValue::new("service.version", version.to_string() ))
+    .with_attribute(KeyValue::new("agent.name", agent_name.to_string() ))
+    .with_attribute(KeyValue::new("agent.version", agent_version.to_string() ))
+    .with_attributes(common_attributes)
+    .build()
+}
+
 #[cfg(feature="otel")]
 struct OtelBridgeState {
     bridging: Option<OpenTelemetryTracingBridge<SdkLoggerProvider, SdkLogger>>,
@@ -435,30 +614,31 @@ impl OtelBridgeState {
         }
     }
 
-    fn some(endpoint: &str, filter_directives: &str, ofid: &str, version: &str, service_name: &str, agent_name: &str, agent_version: &str) -> Self {
+    fn some(endpoint: &str, filter_directives: &str, ofid: &str, version: &str, service_name: &str, agent_name: &str, agent_version: &str) -> Result<Self, String> {
+        let telemetry = LOG_TELEMETRY.lock().unwrap().clone();
+        let metadata = crate::spool::headers_to_metadata(&telemetry.headers)?;
         let exporter = LogExporter::builder()
             .with_tonic()
             .with_tls_config(
                 tonic::transport::ClientTlsConfig::new().with_native_roots()
             )
             .with_endpoint(endpoint)
+            .with_metadata(metadata.clone())
             .build()
-            .expect("Failed to build OTel exporter");
-        let common_attributes = [
-            KeyValue::new(HOSTNAME, hostname::get().unwrap_or_default().into_string().unwrap()),
-            KeyValue::new(OFID, ofid.to_string()),
-            KeyValue::new("PID", std::process::id().to_string()),
-        ];
-    
-        let resource = Resource::builder().with_service_name(service_name.to_string())
-        .with_attribute(KeyValue::new("service.version", version.to_string() ))
-        .with_attribute(KeyValue::new("agent.name", agent_name.to_string() ))
-        .with_attribute(KeyValue::new("agent.version", agent_version.to_string() ))
-        .with_attributes(common_attributes)
-        .build();
+            .map_err(|e| format!("Failed to build OTel exporter for {endpoint}: {e}"))?;
+        let spool = match &telemetry.spool_dir {
+            Some(dir) => Some(crate::spool::TelemetrySpool::new(dir, telemetry.spool_max_bytes)
+                .map_err(|e| format!("Failed to create telemetry spool {}: {e}", dir.display()))?),
+            None => None,
+        };
+        let exporter = crate::spool::SpoolingLogExporter::new(exporter, spool, endpoint, metadata)?;
+        let processor = BatchLogProcessor::builder(exporter)
+            .with_batch_config(BatchConfigBuilder::default().with_scheduled_delay(telemetry.log_interval).build())
+            .build();
+        let resource = otel_resource(ofid, version, service_name, agent_name, agent_version);
 
         let provider = SdkLoggerProvider::builder()
-            .with_batch_exporter(exporter)
+            .with_log_processor(processor)
             .with_resource(resource)
             .build();
 
@@ -467,7 +647,7 @@ impl OtelBridgeState {
         let filter = EnvFilter::try_new(filter_directives)
             .unwrap_or_else(|_| EnvFilter::new("hyper=off,opentelemetry=off,tonic=off,h2=off,reqwest=off"));
 
-        Self { bridging, filter }
+        Ok(Self { bridging, filter })
     }
 }
 
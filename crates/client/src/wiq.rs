@@ -0,0 +1,591 @@
+use openiap_proto::errors::OpenIAPError;
+use openiap_proto::openiap::{
+    Ace, AddWorkItemQueueRequest, AggregateRequest, CountRequest, DeleteManyRequest, QueryRequest,
+    UpdateDocumentRequest, UpdateWorkItemQueueRequest, WorkItemQueue,
+};
+use openiap_proto::workitem::{systemtime_to_timestamp, WorkitemState};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error};
+
+use crate::{Client, EnvConfig};
+
+/// Counts and throughput for a workitem queue, returned by `Client::workitem_queue_stats`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorkitemQueueStats {
+    /// Name of the workitem queue.
+    pub wiq: String,
+    /// Workitems waiting to be processed.
+    pub new: u64,
+    /// Workitems currently being processed.
+    pub processing: u64,
+    /// Workitems waiting for a retry.
+    pub retry: u64,
+    /// Workitems completed.
+    pub successful: u64,
+    /// Workitems failed permanently.
+    pub failed: u64,
+    /// All workitems in the queue.
+    pub total: u64,
+    /// Age of the oldest workitem in new or retry, None if nothing is waiting.
+    pub oldest_pending_age: Option<Duration>,
+    /// Workitems completed as successful during the last hour.
+    pub throughput_per_hour: u64,
+}
+impl WorkitemQueueStats {
+    /// Workitems waiting to be processed, new and retry.
+    pub fn backlog(&self) -> u64 {
+        self.new + self.retry
+    }
+    /// Parse the result of the pipeline built by `workitem_queue_stats`.
+    pub(crate) fn from_aggregate(wiq: &str, results: &str) -> Result<Self, OpenIAPError> {
+        let results: serde_json::Value = serde_json::from_str(results)
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse queue stats: {}", e)))?;
+        let facets = &results[0];
+        let mut stats = WorkitemQueueStats {
+            wiq: wiq.to_string(),
+            ..Default::default()
+        };
+        if let Some(states) = facets["states"].as_array() {
+            for state in states {
+                let count = state["count"].as_u64().unwrap_or_default();
+                match state["_id"].as_str().unwrap_or_default() {
+                    "new" => stats.new = count,
+                    "processing" => stats.processing = count,
+                    "retry" => stats.retry = count,
+                    "successful" => stats.successful = count,
+                    "failed" => stats.failed = count,
+                    _ => {}
+                }
+                stats.total += count;
+            }
+        }
+        stats.oldest_pending_age = facets["oldest"][0]["age"]
+            .as_f64()
+            .map(|age| Duration::from_millis(age.max(0.0) as u64));
+        stats.throughput_per_hour = facets["throughput"][0]["count"].as_u64().unwrap_or_default();
+        Ok(stats)
+    }
+}
+
+/// Build a `WorkItemQueue` from a document in the mq collection, as returned by `query`.
+pub fn workitemqueue_from_document(document: &serde_json::Value) -> WorkItemQueue {
+    let text = |key: &str| document[key].as_str().unwrap_or_default().to_string();
+    let number = |key: &str| document[key].as_i64().unwrap_or_default() as i32;
+    let date = |key: &str| {
+        document[key]
+            .as_str()
+            .and_then(crate::util::parse_iso8601)
+            .map(systemtime_to_timestamp)
+    };
+    WorkItemQueue {
+        workflowid: text("workflowid"),
+        robotqueue: text("robotqueue"),
+        amqpqueue: text("amqpqueue"),
+        projectid: text("projectid"),
+        usersrole: text("usersrole"),
+        maxretries: number("maxretries"),
+        retrydelay: number("retrydelay"),
+        initialdelay: number("initialdelay"),
+        success_wiqid: text("success_wiqid"),
+        failed_wiqid: text("failed_wiqid"),
+        success_wiq: text("success_wiq"),
+        failed_wiq: text("failed_wiq"),
+        id: text("_id"),
+        name: text("name"),
+        createdbyid: text("_createdbyid"),
+        createdby: text("_createdby"),
+        created: date("_created"),
+        modifiedbyid: text("_modifiedbyid"),
+        modifiedby: text("_modifiedby"),
+        modified: date("_modified"),
+        version: number("_version"),
+        packageid: text("packageid"),
+        acl: document["_acl"]
+            .as_array()
+            .map(|acl| {
+                acl.iter()
+                    .map(|ace| Ace {
+                        id: ace["_id"].as_str().unwrap_or_default().to_string(),
+                        deny: ace["deny"].as_bool().unwrap_or_default(),
+                        rights: ace["rights"].as_i64().unwrap_or_default() as i32,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+/// The desired configuration of a workitem queue, see `Client::ensure_workitem_queue`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkitemQueueSpec {
+    /// Name of the workitem queue.
+    pub name: String,
+    /// Number of retries before a workitem is set to failed.
+    pub maxretries: i32,
+    /// Wait time before a workitem in retry can be popped again, in whole seconds.
+    pub retrydelay: Duration,
+    /// Wait time before a new workitem can be popped, in whole seconds.
+    pub initialdelay: Duration,
+    /// Workitems are moved to this queue when successful.
+    pub success_wiq: Option<String>,
+    /// Workitems are moved to this queue when failed.
+    pub failed_wiq: Option<String>,
+    /// Message queue notified when workitems are added.
+    pub amqpqueue: Option<String>,
+    /// OpenRPA robot or role that processes the queue.
+    pub robotqueue: Option<String>,
+    /// OpenRPA workflow that processes the queue.
+    pub workflowid: Option<String>,
+    /// Package that processes the queue.
+    pub packageid: Option<String>,
+    /// Project the queue belongs to.
+    pub projectid: Option<String>,
+    /// Role given access to the queue.
+    pub usersrole: Option<String>,
+}
+impl WorkitemQueueSpec {
+    /// A queue named `name` with 3 retries and no delays.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            maxretries: 3,
+            ..Default::default()
+        }
+    }
+    /// Move workitems to `success_wiq` when successful and to `failed_wiq` when failed.
+    pub fn with_chaining(mut self, success_wiq: Option<&str>, failed_wiq: Option<&str>) -> Self {
+        self.success_wiq = success_wiq.map(|s| s.to_string());
+        self.failed_wiq = failed_wiq.map(|s| s.to_string());
+        self
+    }
+    /// Write the spec into `queue`, fields set to None are left untouched.
+    pub fn apply(&self, queue: &mut WorkItemQueue) {
+        queue.name = self.name.clone();
+        queue.maxretries = self.maxretries;
+        queue.retrydelay = self.retrydelay.as_secs() as i32;
+        queue.initialdelay = self.initialdelay.as_secs() as i32;
+        let set = |target: &mut String, value: &Option<String>| {
+            if let Some(value) = value {
+                *target = value.clone();
+            }
+        };
+        set(&mut queue.success_wiq, &self.success_wiq);
+        set(&mut queue.failed_wiq, &self.failed_wiq);
+        set(&mut queue.amqpqueue, &self.amqpqueue);
+        set(&mut queue.robotqueue, &self.robotqueue);
+        set(&mut queue.workflowid, &self.workflowid);
+        set(&mut queue.packageid, &self.packageid);
+        set(&mut queue.projectid, &self.projectid);
+        set(&mut queue.usersrole, &self.usersrole);
+        if self.success_wiq.is_some() {
+            queue.success_wiqid = String::new();
+        }
+        if self.failed_wiq.is_some() {
+            queue.failed_wiqid = String::new();
+        }
+    }
+    /// Returns true if `queue` already matches the spec.
+    pub fn matches(&self, queue: &WorkItemQueue) -> bool {
+        let mut expected = queue.clone();
+        self.apply(&mut expected);
+        // the ids are resolved by the server, compare on names only
+        expected.success_wiqid = queue.success_wiqid.clone();
+        expected.failed_wiqid = queue.failed_wiqid.clone();
+        expected == *queue
+    }
+}
+/// Selects workitems for the bulk operations, like `Client::requeue_workitems`.
+#[derive(Debug, Clone, Default)]
+pub struct WorkitemFilter {
+    /// Name of the workitem queue.
+    pub wiq: String,
+    /// Only workitems in one of these states, all states if empty.
+    pub states: Vec<WorkitemState>,
+    /// Only workitems not modified for at least this long.
+    pub older_than: Option<Duration>,
+    /// Only workitems with this errortype, like "application" or "business".
+    pub errortype: Option<String>,
+}
+impl WorkitemFilter {
+    /// Select workitems in `wiq` with the given state.
+    pub fn bystate(wiq: &str, state: WorkitemState) -> Self {
+        Self {
+            wiq: wiq.to_string(),
+            states: vec![state],
+            ..Default::default()
+        }
+    }
+    /// The query used against the workitems collection.
+    pub fn to_query(&self) -> serde_json::Value {
+        let mut query = serde_json::json!({ "_type": "workitem", "wiq": self.wiq });
+        if !self.states.is_empty() {
+            let states: Vec<&str> = self.states.iter().map(|s| s.as_str()).collect();
+            query["state"] = serde_json::json!({ "$in": states });
+        }
+        if let Some(older_than) = self.older_than {
+            query["$expr"] = serde_json::json!({
+                "$gte": [{ "$subtract": ["$$NOW", "$_modified"] }, older_than.as_millis() as u64]
+            });
+        }
+        if let Some(errortype) = &self.errortype {
+            query["errortype"] = serde_json::json!(errortype);
+        }
+        query
+    }
+}
+/// Called after each batch with the number of processed and matched workitems.
+pub type BulkProgressFn = Arc<dyn Fn(u64, u64) + Send + Sync>;
+/// Options for the bulk workitem operations.
+#[derive(Clone)]
+pub struct WorkitemBulkOptions {
+    /// Number of workitems handled per request.
+    pub batch_size: usize,
+    /// Only count the matching workitems.
+    pub dry_run: bool,
+    /// Progress callback.
+    pub progress: Option<BulkProgressFn>,
+}
+impl Default for WorkitemBulkOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 100,
+            dry_run: false,
+            progress: None,
+        }
+    }
+}
+/// Result of a bulk workitem operation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkitemBulkResult {
+    /// Number of workitems matching the filter when the operation started.
+    pub matched: u64,
+    /// Number of workitems updated or deleted, 0 on a dry run.
+    pub processed: u64,
+    /// Errors from failed batches.
+    pub errors: Vec<String>,
+}
+enum BulkAction {
+    Update(serde_json::Value),
+    Delete,
+}
+
+impl Client {
+    /// Get a workitem queue by name or id, returns None if it does not exist
+    #[tracing::instrument(skip_all)]
+    pub async fn get_workitem_queue(
+        &self,
+        env: EnvConfig,
+        name_or_id: &str,
+    ) -> Result<Option<WorkItemQueue>, OpenIAPError> {
+        let query = QueryRequest {
+            collectionname: "mq".to_string(),
+            query: serde_json::json!({ "_type": "workitemqueue", "$or": [{ "name": name_or_id }, { "_id": name_or_id }] }).to_string(),
+            top: 1,
+            ..Default::default()
+        };
+        let response = self.query(query, env).await?;
+        let queues: Vec<serde_json::Value> = serde_json::from_str(&response.results)
+            .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse workitem queues: {}", e)))?;
+        Ok(queues.first().map(workitemqueue_from_document))
+    }
+    /// List all workitem queues the user has access to
+    #[tracing::instrument(skip_all)]
+    pub async fn list_workitem_queues(&self, env: EnvConfig) -> Result<Vec<WorkItemQueue>, OpenIAPError> {
+        let top = 1000;
+        let mut skip = 0;
+        let mut result = Vec::new();
+        loop {
+            let query = QueryRequest {
+                collectionname: "mq".to_string(),
+                query: "{\"_type\": \"workitemqueue\"}".to_string(),
+                orderby: "{\"name\": 1}".to_string(),
+                top,
+                skip,
+                ..Default::default()
+            };
+            let response = self.query(query, env.clone()).await?;
+            let queues: Vec<serde_json::Value> = serde_json::from_str(&response.results)
+                .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse workitem queues: {}", e)))?;
+            let count = queues.len() as i32;
+            result.extend(queues.iter().map(workitemqueue_from_document));
+            if count < top {
+                break;
+            }
+            skip += top;
+        }
+        Ok(result)
+    }
+    /// Create the workitem queue described by `spec`, or update it if it exists and differs
+    #[tracing::instrument(skip_all)]
+    pub async fn ensure_workitem_queue(
+        &self,
+        env: EnvConfig,
+        spec: &WorkitemQueueSpec,
+    ) -> Result<WorkItemQueue, OpenIAPError> {
+        if spec.name.is_empty() {
+            return Err(OpenIAPError::ClientError("No workitem queue name provided".to_string()));
+        }
+        match self.get_workitem_queue(env.clone(), &spec.name).await? {
+            Some(queue) if spec.matches(&queue) => Ok(queue),
+            Some(mut queue) => {
+                debug!("Updating workitem queue {}", spec.name);
+                spec.apply(&mut queue);
+                let request = UpdateWorkItemQueueRequest {
+                    workitemqueue: Some(queue),
+                    ..Default::default()
+                };
+                self.update_workitem_queue(request, env).await
+            }
+            None => {
+                debug!("Creating workitem queue {}", spec.name);
+                let mut queue = WorkItemQueue::default();
+                spec.apply(&mut queue);
+                let request = AddWorkItemQueueRequest {
+                    workitemqueue: Some(queue),
+                    ..Default::default()
+                };
+                self.add_workitem_queue(request, env).await
+            }
+        }
+    }
+    /// Count workitems matching `filter`
+    #[tracing::instrument(skip_all)]
+    pub async fn count_workitems(&self, env: EnvConfig, filter: &WorkitemFilter) -> Result<u64, OpenIAPError> {
+        let request = CountRequest {
+            collectionname: "workitems".to_string(),
+            query: filter.to_query().to_string(),
+            ..Default::default()
+        };
+        let response = self.count(request, env).await?;
+        Ok(response.result.max(0) as u64)
+    }
+    /// Reset state to new and retries to 0 for workitems matching `filter`, and clear the error fields
+    #[tracing::instrument(skip_all)]
+    pub async fn requeue_workitems(
+        &self,
+        env: EnvConfig,
+        filter: &WorkitemFilter,
+        options: WorkitemBulkOptions,
+    ) -> Result<WorkitemBulkResult, OpenIAPError> {
+        let update = serde_json::json!({
+            "$set": { "state": "new", "retries": 0 },
+            "$currentDate": { "nextrun": true },
+            "$unset": { "errormessage": "", "errortype": "", "errorsource": "" }
+        });
+        self.bulk_workitems(env, filter, options, BulkAction::Update(update)).await
+    }
+    /// Move workitems matching `filter` to another workitem queue
+    #[tracing::instrument(skip_all)]
+    pub async fn move_workitems(
+        &self,
+        env: EnvConfig,
+        filter: &WorkitemFilter,
+        target_wiq: &str,
+        options: WorkitemBulkOptions,
+    ) -> Result<WorkitemBulkResult, OpenIAPError> {
+        let target_wiqid = match self.get_workitem_queue(env.clone(), target_wiq).await? {
+            Some(queue) => queue.id,
+            None => {
+                return Err(OpenIAPError::ClientError(format!("Workitem queue {} not found", target_wiq)));
+            }
+        };
+        let update = serde_json::json!({ "$set": { "wiq": target_wiq, "wiqid": target_wiqid } });
+        self.bulk_workitems(env, filter, options, BulkAction::Update(update)).await
+    }
+    /// Delete workitems matching `filter`, including their files
+    #[tracing::instrument(skip_all)]
+    pub async fn purge_workitems(
+        &self,
+        env: EnvConfig,
+        filter: &WorkitemFilter,
+        options: WorkitemBulkOptions,
+    ) -> Result<WorkitemBulkResult, OpenIAPError> {
+        self.bulk_workitems(env, filter, options, BulkAction::Delete).await
+    }
+    async fn bulk_workitems(
+        &self,
+        env: EnvConfig,
+        filter: &WorkitemFilter,
+        options: WorkitemBulkOptions,
+        action: BulkAction,
+    ) -> Result<WorkitemBulkResult, OpenIAPError> {
+        if filter.wiq.is_empty() {
+            return Err(OpenIAPError::ClientError("No queue name provided".to_string()));
+        }
+        let mut result = WorkitemBulkResult {
+            matched: self.count_workitems(env.clone(), filter).await?,
+            ..Default::default()
+        };
+        if options.dry_run {
+            return Ok(result);
+        }
+        let batch_size = options.batch_size.max(1) as i32;
+        // page by _id, so batches the update leaves matching the filter, or that failed, are not handled again
+        let mut last_id: Option<String> = None;
+        loop {
+            let mut query = filter.to_query();
+            if let Some(last_id) = &last_id {
+                query["_id"] = serde_json::json!({ "$gt": last_id });
+            }
+            let query = QueryRequest {
+                collectionname: "workitems".to_string(),
+                query: query.to_string(),
+                projection: "{\"_id\":1,\"files._id\":1}".to_string(),
+                orderby: "{\"_id\":1}".to_string(),
+                top: batch_size,
+                ..Default::default()
+            };
+            let response = self.query(query, env.clone()).await?;
+            let documents: Vec<serde_json::Value> = serde_json::from_str(&response.results)
+                .map_err(|e| OpenIAPError::ClientError(format!("Failed to parse workitems: {}", e)))?;
+            let ids: Vec<String> = documents
+                .iter()
+                .filter_map(|d| d["_id"].as_str().map(|id| id.to_string()))
+                .collect();
+            let Some(last) = ids.last() else {
+                break;
+            };
+            last_id = Some(last.clone());
+            match &action {
+                BulkAction::Update(update) => {
+                    let request = UpdateDocumentRequest {
+                        collectionname: "workitems".to_string(),
+                        query: serde_json::json!({ "_id": { "$in": ids } }).to_string(),
+                        document: update.to_string(),
+                        ..Default::default()
+                    };
+                    match self.update_document(request, env.clone()).await {
+                        Ok(_) => result.processed += ids.len() as u64,
+                        Err(e) => {
+                            error!("Bulk workitem batch failed: {}", e);
+                            result.errors.push(e.to_string());
+                        }
+                    }
+                }
+                BulkAction::Delete => {
+                    let fileids: Vec<String> = documents
+                        .iter()
+                        .filter_map(|d| d["files"].as_array())
+                        .flatten()
+                        .filter_map(|f| f["_id"].as_str().map(|id| id.to_string()))
+                        .collect();
+                    let request = DeleteManyRequest {
+                        collectionname: "workitems".to_string(),
+                        ids,
+                        ..Default::default()
+                    };
+                    match self.delete_many(request, env.clone()).await {
+                        Ok(affected) => {
+                            result.processed += affected.max(0) as u64;
+                            // only remove the files once the workitems referencing them are gone
+                            if !fileids.is_empty() {
+                                let request = DeleteManyRequest {
+                                    collectionname: "fs.files".to_string(),
+                                    ids: fileids,
+                                    ..Default::default()
+                                };
+                                if let Err(e) = self.delete_many(request, env.clone()).await {
+                                    error!("Failed to delete workitem files: {}", e);
+                                    result.errors.push(e.to_string());
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            error!("Bulk workitem batch failed: {}", e);
+                            result.errors.push(e.to_string());
+                        }
+                    }
+                }
+            }
+            debug!("Bulk workitems {}/{}", result.processed, result.matched);
+            if let Some(progress) = &options.progress {
+                progress(result.processed, result.matched);
+            }
+            if (documents.len() as i32) < batch_size {
+                break;
+            }
+        }
+        Ok(result)
+    }
+    /// Count workitems per state in a workitem queue, and get the age of the oldest waiting
+    /// workitem and the number of workitems completed the last hour.
+    #[tracing::instrument(skip_all)]
+    pub async fn workitem_queue_stats(
+        &self,
+        env: EnvConfig,
+        wiq: &str,
+    ) -> Result<WorkitemQueueStats, OpenIAPError> {
+        if wiq.is_empty() {
+            return Err(OpenIAPError::ClientError("No queue name provided".to_string()));
+        }
+        // $$NOW keeps date math on the server, so client clock skew does not matter
+        let pipeline = serde_json::json!([
+            { "$match": { "_type": "workitem", "wiq": wiq } },
+            { "$facet": {
+                "states": [
+                    { "$group": { "_id": "$state", "count": { "$sum": 1 } } }
+                ],
+                "oldest": [
+                    { "$match": { "state": { "$in": ["new", "retry"] } } },
+                    { "$sort": { "_created": 1 } },
+                    { "$limit": 1 },
+                    { "$project": { "age": { "$subtract": ["$$NOW", "$_created"] } } }
+                ],
+                "throughput": [
+                    { "$match": { "state": "successful",
+                        "$expr": { "$lt": [{ "$subtract": ["$$NOW", "$_modified"] }, 3600000] } } },
+                    { "$count": "count" }
+                ]
+            } }
+        ]);
+        let request = AggregateRequest {
+            collectionname: "workitems".to_string(),
+            aggregates: pipeline.to_string(),
+            ..Default::default()
+        };
+        let response = self.aggregate(request, env).await?;
+        WorkitemQueueStats::from_aggregate(wiq, &response.results)
+    }
+    /// Refresh the workitem queue stats as observable gauges every `interval`.
+    /// Gauges are named `wiq_<name>_<state>`, `wiq_<name>_oldest_seconds` and `wiq_<name>_throughput`.
+    #[cfg(feature = "otel")]
+    pub fn monitor_workitem_queue(&self, wiq: &str, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let client = self.clone();
+        let wiq = wiq.to_string();
+        tokio::task::spawn(async move {
+            loop {
+                match client.workitem_queue_stats(EnvConfig::new(), &wiq).await {
+                    Ok(stats) => publish_workitem_queue_stats(&stats),
+                    Err(e) => error!("Failed to get stats for {}: {}", wiq, e),
+                }
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+}
+/// Set observable gauges for the numbers in `stats`, see `Client::monitor_workitem_queue`.
+#[cfg(feature = "otel")]
+pub fn publish_workitem_queue_stats(stats: &WorkitemQueueStats) {
+    let prefix: String = stats
+        .wiq
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    let gauges = [
+        ("new", stats.new, "Workitems in state new"),
+        ("processing", stats.processing, "Workitems in state processing"),
+        ("retry", stats.retry, "Workitems in state retry"),
+        ("successful", stats.successful, "Workitems in state successful"),
+        ("failed", stats.failed, "Workitems in state failed"),
+        ("backlog", stats.backlog(), "Workitems waiting to be processed"),
+        ("oldest_seconds", stats.oldest_pending_age.map(|a| a.as_secs()).unwrap_or_default(), "Age of the oldest waiting workitem"),
+        ("throughput", stats.throughput_per_hour, "Workitems completed the last hour"),
+    ];
+    for (name, value, description) in gauges {
+        let name = format!("wiq_{}_{}", prefix, name);
+        if let Err(e) = crate::otel::set_u64_observable_gauge(&name, value, description) {
+            error!("Failed to set {}: {}", name, e);
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use futures::future::BoxFuture;
+use openiap_proto::errors::OpenIAPError;
+use openiap_proto::openiap::{PopWorkitemRequest, UpdateWorkitemRequest, Workitem};
+use openiap_proto::workitem::WorkitemState;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+use crate::{Client, DownloadErrorPolicy, EnvConfig, PopWorkitemOptions};
+
+/// The error a workitem handler returns, decides if the workitem is retried.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkitemError {
+    /// Temporary problem, set the workitem to "retry" with errortype "application".
+    Retry(String),
+    /// The workitem can never succeed, set it to "failed" with errortype "business".
+    Permanent(String),
+}
+impl std::fmt::Display for WorkitemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorkitemError::Retry(e) => write!(f, "Retry: {}", e),
+            WorkitemError::Permanent(e) => write!(f, "Permanent: {}", e),
+        }
+    }
+}
+impl From<OpenIAPError> for WorkitemError {
+    fn from(e: OpenIAPError) -> Self {
+        WorkitemError::Retry(e.to_string())
+    }
+}
+impl WorkitemError {
+    /// Write state, errormessage, errortype and errorsource for this error into the workitem.
+    pub fn apply(&self, workitem: &mut Workitem, errorsource: &str) {
+        match self {
+            WorkitemError::Retry(message) => {
+                workitem.state = WorkitemState::Retry.to_string();
+                workitem.errortype = "application".to_string();
+                workitem.errormessage = message.clone();
+            }
+            WorkitemError::Permanent(message) => {
+                workitem.state = WorkitemState::Failed.to_string();
+                workitem.errortype = "business".to_string();
+                workitem.errormessage = message.clone();
+            }
+        }
+        workitem.errorsource = errorsource.to_string();
+    }
+}
+
+/// Handler called by `WorkitemWorker` for each popped workitem.
+/// It receives the client, the workitem and the folder its files were downloaded to,
+/// and returns the workitem to save as successful, or an error.
+pub type WorkitemHandlerFn = Arc<
+    dyn Fn(Arc<Client>, Workitem, String) -> BoxFuture<'static, Result<Workitem, WorkitemError>>
+        + Send
+        + Sync,
+>;
+
+/// Options for `WorkitemWorker`.
+#[derive(Debug, Clone)]
+pub struct WorkitemWorkerOptions {
+    /// Number of workitems processed at the same time.
+    pub concurrency: usize,
+    /// Wait time after the first empty pop, doubled on each empty pop.
+    pub idle_backoff_min: Duration,
+    /// Upper limit for the wait time between empty pops.
+    pub idle_backoff_max: Duration,
+    /// Folder where workitem files are downloaded, defaults to the system temp folder.
+    pub download_folder: Option<String>,
+    /// Remove downloaded files once the workitem has been updated.
+    pub cleanup_files: bool,
+    /// Written to `errorsource` on failure, defaults to the agent name and hostname.
+    pub errorsource: String,
+    /// Touch the workitem every `lease / 3` while the handler runs, None disables the heartbeat.
+    pub lease: Option<Duration>,
+    /// Also requeue workitems in the queue whose lease expired, checked at this interval.
+    pub sweep_interval: Option<Duration>,
+}
+impl Default for WorkitemWorkerOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            idle_backoff_min: Duration::from_millis(100),
+            idle_backoff_max: Duration::from_secs(10),
+            download_folder: None,
+            cleanup_files: true,
+            errorsource: String::new(),
+            lease: Some(Duration::from_secs(120)),
+            sweep_interval: None,
+        }
+    }
+}
+
+/// Managed pop / process / update loop for a workitem queue.
+#[derive(Clone)]
+pub struct WorkitemWorker {
+    client: Arc<Client>,
+    wiq: String,
+    options: WorkitemWorkerOptions,
+    handler: WorkitemHandlerFn,
+    shutdown: Arc<watch::Sender<bool>>,
+}
+/// Handle to a started `WorkitemWorker`.
+pub struct WorkitemWorkerHandle {
+    shutdown: Arc<watch::Sender<bool>>,
+    handle: JoinHandle<Result<(), OpenIAPError>>,
+}
+impl WorkitemWorkerHandle {
+    /// Stop popping new workitems and wait for the running handlers to finish.
+    pub async fn stop(self) -> Result<(), OpenIAPError> {
+        let _ = self.shutdown.send(true);
+        self.handle
+            .await
+            .map_err(|e| OpenIAPError::ClientError(format!("Worker task failed: {}", e)))?
+    }
+    /// Returns true once the worker has stopped.
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+impl WorkitemWorker {
+    /// Create a worker for `wiq` with default options.
+    pub fn new<F, Fut>(client: Client, wiq: &str, handler: F) -> Self
+    where
+        F: Fn(Arc<Client>, Workitem, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Workitem, WorkitemError>> + Send + 'static,
+    {
+        Self::with_options(client, wiq, WorkitemWorkerOptions::default(), handler)
+    }
+    /// Create a worker for `wiq` with the given options.
+    pub fn with_options<F, Fut>(
+        client: Client,
+        wiq: &str,
+        mut options: WorkitemWorkerOptions,
+        handler: F,
+    ) -> Self
+    where
+        F: Fn(Arc<Client>, Workitem, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Workitem, WorkitemError>> + Send + 'static,
+    {
+        if options.errorsource.is_empty() {
+            let host = hostname::get()
+                .map(|h| h.to_string_lossy().to_string())
+                .unwrap_or_default();
+            options.errorsource = format!("{}@{}", client.get_agent_name(), host);
+        }
+        options.concurrency = options.concurrency.max(1);
+        let (shutdown, _) = watch::channel(false);
+        Self {
+            client: Arc::new(client),
+            wiq: wiq.to_string(),
+            options,
+            handler: Arc::new(move |client, workitem, folder| {
+                Box::pin(handler(client, workitem, folder))
+            }),
+            shutdown: Arc::new(shutdown),
+        }
+    }
+    /// Signal the worker to stop after the running handlers complete.
+    pub fn shutdown(&self) {
+        let _ = self.shutdown.send(true);
+    }
+    /// Run the worker in a background task.
+    pub fn start(self) -> WorkitemWorkerHandle {
+        let shutdown = self.shutdown.clone();
+        let handle = tokio::task::spawn(async move { self.run().await });
+        WorkitemWorkerHandle { shutdown, handle }
+    }
+    /// Run the worker until `shutdown` is called.
+    #[tracing::instrument(skip_all, fields(wiq = %self.wiq))]
+    pub async fn run(&self) -> Result<(), OpenIAPError> {
+        let base = match &self.options.download_folder {
+            Some(folder) => PathBuf::from(folder),
+            None => std::env::temp_dir(),
+        };
+        let mut slots = Vec::new();
+        for slot in 0..self.options.concurrency {
+            let folder = base.join(format!(
+                "openiap_worker_{}_{}_{}",
+                self.wiq,
+                std::process::id(),
+                slot
+            ));
+            let me = self.clone();
+            slots.push(tokio::task::spawn(async move { me.run_slot(folder).await }));
+        }
+        if let (Some(lease), Some(interval)) = (self.options.lease, self.options.sweep_interval) {
+            let me = self.clone();
+            slots.push(tokio::task::spawn(async move { me.run_sweeper(lease, interval).await }));
+        }
+        for slot in slots {
+            if let Err(e) = slot.await {
+                error!("Worker slot failed: {}", e);
+            }
+        }
+        info!("Worker for {} stopped", self.wiq);
+        Ok(())
+    }
+    async fn run_slot(&self, folder: PathBuf) {
+        let mut shutdown = self.shutdown.subscribe();
+        let mut backoff = self.options.idle_backoff_min;
+        while !*shutdown.borrow() {
+            let _ = std::fs::create_dir_all(&folder);
+            // a failed download puts the workitem back to retry, instead of running the handler without its files
+            let options = PopWorkitemOptions {
+                downloadfolder: Some(folder.to_string_lossy().to_string()),
+                on_error: DownloadErrorPolicy::Fail,
+                ..Default::default()
+            };
+            let popped = self
+                .client
+                .pop_workitem_with_files(PopWorkitemRequest::bywiq(&self.wiq), EnvConfig::new(), options)
+                .await;
+            let wait = match popped {
+                Ok(Some(popped)) => {
+                    backoff = self.options.idle_backoff_min;
+                    self.process(popped.workitem, &folder).await;
+                    None
+                }
+                Ok(None) => Some(backoff),
+                Err(e) => {
+                    error!("Failed to pop workitem from {}: {}", self.wiq, e);
+                    Some(backoff)
+                }
+            };
+            if let Some(wait) = wait {
+                backoff = (backoff * 2).min(self.options.idle_backoff_max);
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {},
+                    _ = shutdown.changed() => {},
+                }
+            }
+        }
+        if self.options.cleanup_files {
+            let _ = std::fs::remove_dir_all(&folder);
+        }
+    }
+    async fn run_sweeper(&self, lease: Duration, interval: Duration) {
+        let mut shutdown = self.shutdown.subscribe();
+        while !*shutdown.borrow() {
+            if let Err(e) = self
+                .client
+                .requeue_expired_workitems(EnvConfig::new(), &self.wiq, lease)
+                .await
+            {
+                error!("Failed to requeue expired workitems in {}: {}", self.wiq, e);
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {},
+                _ = shutdown.changed() => {},
+            }
+        }
+    }
+    fn start_heartbeat(&self, id: &str) -> Option<JoinHandle<()>> {
+        let lease = self.options.lease?;
+        let client = self.client.clone();
+        let id = id.to_string();
+        let owner = self.options.errorsource.clone();
+        Some(tokio::task::spawn(async move {
+            let interval = lease / 3;
+            loop {
+                tokio::time::sleep(interval).await;
+                match client.touch_workitem(&id, EnvConfig::new(), &owner).await {
+                    Ok(true) => {}
+                    Ok(false) => warn!("Lease on workitem {} was lost, it is no longer processing", id),
+                    Err(e) => error!("Failed to renew lease on workitem {}: {}", id, e),
+                }
+            }
+        }))
+    }
+    async fn process(&self, workitem: Workitem, folder: &Path) {
+        let id = workitem.id.clone();
+        debug!("Processing workitem {} {}", id, workitem.name);
+        let handler = self.handler.clone();
+        let client = self.client.clone();
+        let original = workitem.clone();
+        let folder_name = folder.to_string_lossy().to_string();
+        let heartbeat = self.start_heartbeat(&id);
+        // run in its own task, so a panicking handler becomes a retry instead of killing the slot
+        let result = tokio::task::spawn(async move { handler(client, workitem, folder_name).await })
+            .await
+            .unwrap_or_else(|e| Err(WorkitemError::Retry(format!("Handler panicked: {}", e))));
+        if let Some(heartbeat) = heartbeat {
+            heartbeat.abort();
+            let _ = heartbeat.await;
+        }
+        let workitem = match result {
+            Ok(mut workitem) => {
+                workitem.state = WorkitemState::Successful.to_string();
+                workitem
+            }
+            Err(e) => {
+                debug!("Workitem {} failed: {}", id, e);
+                let mut workitem = original;
+                e.apply(&mut workitem, &self.options.errorsource);
+                workitem
+            }
+        };
+        let request = UpdateWorkitemRequest {
+            workitem: Some(workitem),
+            ignoremaxretries: false,
+            ..Default::default()
+        };
+        if let Err(e) = self.client.update_workitem(request, EnvConfig::new()).await {
+            error!("Failed to update workitem {}: {}", id, e);
+        }
+        if self.options.cleanup_files {
+            let _ = std::fs::remove_dir_all(folder);
+        }
+    }
+}
@@ -0,0 +1,21 @@
+// src/openiap.rs is checked in, so building the crate does not need protoc.
+// Set OPENIAP_GENERATE_PROTO=1 to regenerate it from the files in proto/, this needs protoc in PATH or PROTOC.
+fn main() {
+    println!("cargo:rerun-if-env-changed=OPENIAP_GENERATE_PROTO");
+    if std::env::var_os("OPENIAP_GENERATE_PROTO").is_none() {
+        return;
+    }
+    let mut builder = tonic_build::configure().out_dir("src");
+    // these carry credentials, signin.rs implements a Debug that redacts them
+    for message in [
+        ".openiap.Envelope",
+        ".openiap.SigninRequest",
+        ".openiap.SigninResponse",
+        ".openiap.RefreshToken",
+    ] {
+        builder = builder.skip_debug(message);
+    }
+    builder
+        .compile_protos(&["proto/base.proto"], &["proto"])
+        .expect("Unable to generate protos");
+}
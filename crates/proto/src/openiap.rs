@@ -1,5 +1,3 @@
-// This file is generated by tonic_build. Do not edit manually.
-// (You should copy the actual generated file here. For now, this is a placeholder.)
 // This file is @generated by prost-build.
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct ListCollectionsRequest {
@@ -924,6 +922,7 @@ pub struct DeleteAgentRequest {
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct DeleteAgentResponse {}
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
 pub struct Envelope {
     #[prost(string, tag = "1")]
     pub command: ::prost::alloc::string::String,
@@ -1079,6 +1078,7 @@ pub struct CustomCommandResponse {
     pub result: ::prost::alloc::string::String,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
 pub struct SigninRequest {
     #[prost(string, tag = "1")]
     pub username: ::prost::alloc::string::String,
@@ -1098,6 +1098,7 @@ pub struct SigninRequest {
     pub longtoken: bool,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
 pub struct SigninResponse {
     #[prost(string, tag = "1")]
     pub jwt: ::prost::alloc::string::String,
@@ -1107,6 +1108,7 @@ pub struct SigninResponse {
     pub config: ::prost::alloc::string::String,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
 pub struct RefreshToken {
     #[prost(string, tag = "1")]
     pub username: ::prost::alloc::string::String,
@@ -1,5 +1,14 @@
 #![warn(missing_docs)]
-use super::openiap::{Envelope, SigninRequest};
+use super::openiap::{Envelope, RefreshToken, SigninRequest, SigninResponse};
+
+/// Shown in place of tokens and passwords in `Debug` output.
+pub const REDACTED: &str = "[redacted]";
+
+/// Returns `REDACTED` for a non empty credential, so `Debug` output still shows if it was set.
+pub fn redact(value: &str) -> &str {
+    if value.is_empty() { "" } else { REDACTED }
+}
+
 
 impl SigninRequest {
     /// Creates a new `SigninRequest` with the given `username` and `password`.
@@ -37,3 +46,57 @@ impl SigninRequest {
         }
     }
 }
+impl std::fmt::Debug for SigninRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigninRequest")
+            .field("username", &self.username)
+            .field("password", &redact(&self.password))
+            .field("jwt", &redact(&self.jwt))
+            .field("ping", &self.ping)
+            .field("validateonly", &self.validateonly)
+            .field("agent", &self.agent)
+            .field("version", &self.version)
+            .field("longtoken", &self.longtoken)
+            .finish()
+    }
+}
+impl std::fmt::Debug for SigninResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigninResponse")
+            .field("jwt", &redact(&self.jwt))
+            .field("user", &self.user)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+impl std::fmt::Debug for RefreshToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RefreshToken")
+            .field("username", &self.username)
+            .field("jwt", &redact(&self.jwt))
+            .field("user", &self.user)
+            .finish()
+    }
+}
+impl std::fmt::Debug for Envelope {
+    // data of signin requests, replies and token refreshes holds credentials too
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("Envelope");
+        debug
+            .field("command", &self.command)
+            .field("priority", &self.priority)
+            .field("seq", &self.seq)
+            .field("id", &self.id)
+            .field("rid", &self.rid);
+        if self.command == "signin" || self.command == "signinreply" || self.command == "refreshtoken" {
+            debug.field("data", &self.data.as_ref().map(|_| REDACTED));
+        } else {
+            debug.field("data", &self.data);
+        }
+        debug
+            .field("jwt", &redact(&self.jwt))
+            .field("traceid", &self.traceid)
+            .field("spanid", &self.spanid)
+            .finish()
+    }
+}
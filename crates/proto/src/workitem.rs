@@ -1,8 +1,123 @@
 #![warn(missing_docs)]
 use super::openiap::{
     Envelope, PushWorkitemRequest, PushWorkitemsRequest, PopWorkitemRequest, UpdateWorkitemRequest, DeleteWorkitemRequest,
-    AddWorkItemQueueRequest, UpdateWorkItemQueueRequest, DeleteWorkItemQueueRequest
+    AddWorkItemQueueRequest, UpdateWorkItemQueueRequest, DeleteWorkItemQueueRequest, Workitem
 };
+use super::errors::OpenIAPError;
+use std::time::SystemTime;
+
+/// The state of a `Workitem`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WorkitemState {
+    /// Waiting in the queue.
+    New,
+    /// Popped by a worker.
+    Processing,
+    /// Completed.
+    Successful,
+    /// Failed, and will be retried after the queue's retrydelay.
+    Retry,
+    /// Failed and will not be retried.
+    Failed,
+}
+impl WorkitemState {
+    /// All states, in the order a workitem normally moves through them.
+    pub const ALL: [WorkitemState; 5] = [
+        WorkitemState::New,
+        WorkitemState::Processing,
+        WorkitemState::Retry,
+        WorkitemState::Successful,
+        WorkitemState::Failed,
+    ];
+    /// Returns the string used for `Workitem.state`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkitemState::New => "new",
+            WorkitemState::Processing => "processing",
+            WorkitemState::Successful => "successful",
+            WorkitemState::Retry => "retry",
+            WorkitemState::Failed => "failed",
+        }
+    }
+    /// Returns true if a workitem in this state may be updated to `next`.
+    pub fn can_transition_to(&self, next: WorkitemState) -> bool {
+        use WorkitemState::*;
+        match self {
+            New => matches!(next, New | Processing | Successful | Failed),
+            // processing to processing is used to touch a running workitem
+            Processing => matches!(next, Processing | Successful | Retry | Failed),
+            Retry => matches!(next, Retry | New | Processing | Failed),
+            Failed => matches!(next, Failed | New | Retry),
+            Successful => matches!(next, Successful | New),
+        }
+    }
+}
+impl std::fmt::Display for WorkitemState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl std::str::FromStr for WorkitemState {
+    type Err = OpenIAPError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "new" => Ok(WorkitemState::New),
+            "processing" => Ok(WorkitemState::Processing),
+            "successful" => Ok(WorkitemState::Successful),
+            "retry" => Ok(WorkitemState::Retry),
+            "failed" => Ok(WorkitemState::Failed),
+            _ => Err(OpenIAPError::ClientError(format!("Unknown workitem state {:?}", s))),
+        }
+    }
+}
+/// Converts a protobuf `Timestamp` to a `SystemTime`.
+pub fn timestamp_to_systemtime(timestamp: &prost_types::Timestamp) -> SystemTime {
+    // Timestamp::try_from normalizes nanos, out of range values fall back to the epoch
+    SystemTime::try_from(*timestamp).unwrap_or(SystemTime::UNIX_EPOCH)
+}
+/// Converts a `SystemTime` to a protobuf `Timestamp`.
+pub fn systemtime_to_timestamp(time: SystemTime) -> prost_types::Timestamp {
+    prost_types::Timestamp::from(time)
+}
+impl Workitem {
+    /// Parse `state` into a `WorkitemState`.
+    pub fn get_state(&self) -> Result<WorkitemState, OpenIAPError> {
+        self.state.parse()
+    }
+    /// Set `state`, returns an error if the current state cannot move to `state`.
+    /// An empty current state is treated as `new`.
+    pub fn set_state(&mut self, state: WorkitemState) -> Result<(), OpenIAPError> {
+        let current = if self.state.is_empty() {
+            WorkitemState::New
+        } else {
+            self.get_state()?
+        };
+        if !current.can_transition_to(state) {
+            return Err(OpenIAPError::ClientError(format!(
+                "Workitem {} cannot move from {} to {}",
+                self.id, current, state
+            )));
+        }
+        self.state = state.as_str().to_string();
+        Ok(())
+    }
+    /// Returns `nextrun` as a `SystemTime`.
+    pub fn get_nextrun(&self) -> Option<SystemTime> {
+        self.nextrun.as_ref().map(timestamp_to_systemtime)
+    }
+    /// Sets `nextrun` from a `SystemTime`.
+    pub fn set_nextrun(&mut self, time: Option<SystemTime>) {
+        self.nextrun = time.map(systemtime_to_timestamp);
+    }
+    /// Returns `lastrun` as a `SystemTime`.
+    pub fn get_lastrun(&self) -> Option<SystemTime> {
+        self.lastrun.as_ref().map(timestamp_to_systemtime)
+    }
+    /// Sets `lastrun` from a `SystemTime`.
+    pub fn set_lastrun(&mut self, time: Option<SystemTime>) {
+        self.lastrun = time.map(systemtime_to_timestamp);
+    }
+}
 
 impl PushWorkitemRequest {
     /// Creates a new `PushWorkitemRequest` with the given `workitem`.
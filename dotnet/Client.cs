@@ -964,6 +964,12 @@ namespace OpenIAP
         private static extern void _set_i64_observable_gauge(string name, long value, string description);
         [DllImport("libopeniap", CallingConvention = CallingConvention.Cdecl, EntryPoint = "disable_observable_gauge")]
         private static extern void _disable_observable_gauge(string name);
+        [DllImport("libopeniap", CallingConvention = CallingConvention.Cdecl, EntryPoint = "inc_counter")]
+        private static extern void _inc_counter(string name, ulong value, string description, string? attributes);
+        [DllImport("libopeniap", CallingConvention = CallingConvention.Cdecl, EntryPoint = "add_up_down_counter")]
+        private static extern void _add_up_down_counter(string name, long value, string description, string? attributes);
+        [DllImport("libopeniap", CallingConvention = CallingConvention.Cdecl, EntryPoint = "record_histogram")]
+        private static extern void _record_histogram(string name, double value, string description, string? attributes);
 
 
         [UnmanagedFunctionPointer(CallingConvention.Cdecl)]
@@ -1350,6 +1356,18 @@ namespace OpenIAP
         {
             Client._disable_observable_gauge(name);
         }
+        public void inc_counter(string name, ulong value, string description, Dictionary<string, string>? attributes = null)
+        {
+            Client._inc_counter(name, value, description, attributes == null ? null : JsonSerializer.Serialize(attributes));
+        }
+        public void add_up_down_counter(string name, long value, string description, Dictionary<string, string>? attributes = null)
+        {
+            Client._add_up_down_counter(name, value, description, attributes == null ? null : JsonSerializer.Serialize(attributes));
+        }
+        public void record_histogram(string name, double value, string description, Dictionary<string, string>? attributes = null)
+        {
+            Client._record_histogram(name, value, description, attributes == null ? null : JsonSerializer.Serialize(attributes));
+        }
         void _ConnectCallback(IntPtr clientWrapperPtr)
         {
             try
@@ -753,6 +753,18 @@ void set_i64_observable_gauge(const char *name, int64_t value, const char *descr
 
 void disable_observable_gauge(const char *name);
 
+void inc_counter(const char *name, uint64_t value, const char *description, const char *attributes);
+
+void add_up_down_counter(const char *name,
+                         int64_t value,
+                         const char *description,
+                         const char *attributes);
+
+void record_histogram(const char *name,
+                      double value,
+                      const char *description,
+                      const char *attributes);
+
 /**
  * Return currentlly signed in user
  */
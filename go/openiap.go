@@ -13,6 +13,7 @@ package openiap
 */
 import "C"
 import (
+	"encoding/json"
 	"errors"
 	"unsafe"
 )
@@ -273,3 +274,51 @@ func DisableObservableGauge(name string) {
 
 	C.disable_observable_gauge(cName)
 }
+
+// metricAttributes converts metric attributes to a json string for the C library, nil when empty
+func metricAttributes(attributes map[string]string) *C.char {
+	if len(attributes) == 0 {
+		return nil
+	}
+	data, err := json.Marshal(attributes)
+	if err != nil {
+		return nil
+	}
+	return C.CString(string(data))
+}
+
+// IncCounter adds value to a counter metric, with optional attributes
+func IncCounter(name string, value uint64, description string, attributes map[string]string) {
+	cName := C.CString(name)
+	cDesc := C.CString(description)
+	cAttributes := metricAttributes(attributes)
+	defer C.free(unsafe.Pointer(cName))
+	defer C.free(unsafe.Pointer(cDesc))
+	defer C.free(unsafe.Pointer(cAttributes))
+
+	C.inc_counter(cName, C.uint64_t(value), cDesc, cAttributes)
+}
+
+// AddUpDownCounter adds value to an up-down counter metric, use a negative value to decrease it
+func AddUpDownCounter(name string, value int64, description string, attributes map[string]string) {
+	cName := C.CString(name)
+	cDesc := C.CString(description)
+	cAttributes := metricAttributes(attributes)
+	defer C.free(unsafe.Pointer(cName))
+	defer C.free(unsafe.Pointer(cDesc))
+	defer C.free(unsafe.Pointer(cAttributes))
+
+	C.add_up_down_counter(cName, C.int64_t(value), cDesc, cAttributes)
+}
+
+// RecordHistogram records value in a histogram metric, with optional attributes
+func RecordHistogram(name string, value float64, description string, attributes map[string]string) {
+	cName := C.CString(name)
+	cDesc := C.CString(description)
+	cAttributes := metricAttributes(attributes)
+	defer C.free(unsafe.Pointer(cName))
+	defer C.free(unsafe.Pointer(cDesc))
+	defer C.free(unsafe.Pointer(cAttributes))
+
+	C.record_histogram(cName, C.double(value), cDesc, cAttributes)
+}
@@ -37,6 +37,9 @@ interface CLib extends Library {
     void set_u64_observable_gauge(String name, long value, String description);
     void set_i64_observable_gauge(String name, long value, String description);
     void disable_observable_gauge(String name);
+    void inc_counter(String name, long value, String description, String attributes);
+    void add_up_down_counter(String name, long value, String description, String attributes);
+    void record_histogram(String name, double value, String description, String attributes);
     void disable_tracing();
     void error(String message);
     void info(String message);
@@ -268,6 +271,25 @@ public class Client implements AutoCloseable {
     public void disable_observable_gauge(String name) {
         clibInstance.disable_observable_gauge(name);
     }
+    private String metricAttributes(Map<String, String> attributes) {
+        if (attributes == null || attributes.isEmpty()) {
+            return null;
+        }
+        try {
+            return objectMapper.writeValueAsString(attributes);
+        } catch (Exception e) {
+            return null;
+        }
+    }
+    public void inc_counter(String name, long value, String description, Map<String, String> attributes) {
+        clibInstance.inc_counter(name, value, description, metricAttributes(attributes));
+    }
+    public void add_up_down_counter(String name, long value, String description, Map<String, String> attributes) {
+        clibInstance.add_up_down_counter(name, value, description, metricAttributes(attributes));
+    }
+    public void record_histogram(String name, double value, String description, Map<String, String> attributes) {
+        clibInstance.record_histogram(name, value, description, metricAttributes(attributes));
+    }
     public void disconnect() {
         if (clientPtr != null) {
             exchangeCallbacks.clear();
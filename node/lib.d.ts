@@ -16,6 +16,9 @@ export class Client {
     set_u64_observable_gauge(name: any, value: any, description: any): void;
     set_i64_observable_gauge(name: any, value: any, description: any): void;
     disable_observable_gauge(name: any): void;
+    inc_counter(name: any, value: any, description: any, attributes?: any): void;
+    add_up_down_counter(name: any, value: any, description: any, attributes?: any): void;
+    record_histogram(name: any, value: any, description: any, attributes?: any): void;
     set_agent_name(name: any): void;
     set_default_timeout(timeout: any): void;
     get_default_timeout(): any;
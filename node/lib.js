@@ -728,6 +728,9 @@ function loadLibrary() {
         lib.set_u64_observable_gauge = lib.func('void set_u64_observable_gauge(const char* name, uint64_t value, const char* description);');
         lib.set_i64_observable_gauge = lib.func('void set_i64_observable_gauge(const char* name, int64_t value, const char* description);');
         lib.disable_observable_gauge = lib.func('void disable_observable_gauge(const char* name);');
+        lib.inc_counter = lib.func('void inc_counter(const char* name, uint64_t value, const char* description, const char* attributes);');
+        lib.add_up_down_counter = lib.func('void add_up_down_counter(const char* name, int64_t value, const char* description, const char* attributes);');
+        lib.record_histogram = lib.func('void record_histogram(const char* name, double value, const char* description, const char* attributes);');
 
         lib.create_client = lib.func('create_client', ClientWrapperPtr, []);
         lib.on_client_event = lib.func('on_client_event', ClientEventResponseWrapperPtr, [ClientWrapperPtr]);
@@ -992,6 +995,9 @@ function loadLibrary_old() {
         lib.set_u64_observable_gauge = lib.func('void set_u64_observable_gauge(const char* name, uint64_t value, const char* description);');
         lib.set_i64_observable_gauge = lib.func('void set_i64_observable_gauge(const char* name, int64_t value, const char* description);');
         lib.disable_observable_gauge = lib.func('void disable_observable_gauge(const char* name);');
+        lib.inc_counter = lib.func('void inc_counter(const char* name, uint64_t value, const char* description, const char* attributes);');
+        lib.add_up_down_counter = lib.func('void add_up_down_counter(const char* name, int64_t value, const char* description, const char* attributes);');
+        lib.record_histogram = lib.func('void record_histogram(const char* name, double value, const char* description, const char* attributes);');
 
         lib.create_client = lib.func('create_client', ClientWrapperPtr, []);
         lib.on_client_event = lib.func('on_client_event', ClientEventResponseWrapperPtr, [ClientWrapperPtr]);
@@ -1255,6 +1261,15 @@ class Client {
     disable_observable_gauge(name) {
         this.lib.disable_observable_gauge(name);
     }
+    inc_counter(name, value, description, attributes) {
+        this.lib.inc_counter(name, value, description, attributes ? JSON.stringify(attributes) : null);
+    }
+    add_up_down_counter(name, value, description, attributes) {
+        this.lib.add_up_down_counter(name, value, description, attributes ? JSON.stringify(attributes) : null);
+    }
+    record_histogram(name, value, description, attributes) {
+        this.lib.record_histogram(name, value, description, attributes ? JSON.stringify(attributes) : null);
+    }
     set_agent_name(name) {
         this.trace('set_agent_name invoked', name);
         this.lib.set_agent_name(this.client, name);
@@ -265,6 +265,18 @@ class Client {
     public function disable_observable_gauge($name) {
         $this->ffi->disable_observable_gauge($name);
     }
+    // void inc_counter(const char *name, uint64_t value, const char *description, const char *attributes);
+    public function inc_counter($name, $value, $description, $attributes = null) {
+        $this->ffi->inc_counter($name, $value, $description, empty($attributes) ? null : json_encode($attributes));
+    }
+    // void add_up_down_counter(const char *name, int64_t value, const char *description, const char *attributes);
+    public function add_up_down_counter($name, $value, $description, $attributes = null) {
+        $this->ffi->add_up_down_counter($name, $value, $description, empty($attributes) ? null : json_encode($attributes));
+    }
+    // void record_histogram(const char *name, double value, const char *description, const char *attributes);
+    public function record_histogram($name, $value, $description, $attributes = null) {
+        $this->ffi->record_histogram($name, $value, $description, empty($attributes) ? null : json_encode($attributes));
+    }
     
     private function set_agent_name($agent_name) {
         $this->ffi->client_set_agent_name($this->client, $agent_name);
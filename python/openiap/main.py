@@ -833,6 +833,19 @@ class Client:
 
     def disable_observable_gauge(self, name):
         self.lib.disable_observable_gauge(name.encode('utf-8'))
+    def _metric_attributes(self, attributes):
+        if not attributes:
+            return None
+        return json.dumps(attributes).encode('utf-8')
+    def inc_counter(self, name: str, value: int, description: str, attributes: dict = None):
+        self.lib.inc_counter.argtypes = [c_char_p, c_uint64, c_char_p, c_char_p]
+        self.lib.inc_counter(name.encode('utf-8'), c_uint64(value), description.encode('utf-8'), self._metric_attributes(attributes))
+    def add_up_down_counter(self, name: str, value: int, description: str, attributes: dict = None):
+        self.lib.add_up_down_counter.argtypes = [c_char_p, c_int64, c_char_p, c_char_p]
+        self.lib.add_up_down_counter(name.encode('utf-8'), c_int64(value), description.encode('utf-8'), self._metric_attributes(attributes))
+    def record_histogram(self, name: str, value: float, description: str, attributes: dict = None):
+        self.lib.record_histogram.argtypes = [c_char_p, c_double, c_char_p, c_char_p]
+        self.lib.record_histogram(name.encode('utf-8'), c_double(value), description.encode('utf-8'), self._metric_attributes(attributes))
     def connect(self, url=""):
         # Event to wait for the callback
         event = threading.Event()